@@ -0,0 +1,723 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use log::{debug, info, warn};
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::checkpoint::Checkpoint;
+use crate::fsutil::{format_bytes, join_components, long_path, InstallLock, TreeTooDeep, DEFAULT_MAX_DEPTH};
+use crate::index::{load_index, parse_index, ModrinthIndex};
+use crate::manifest::portable_path;
+use crate::progress::ProgressSink;
+
+// Signatures of a zip's first local file header and of its end of central directory record
+const LOCAL_FILE_SIGNATURE: &[u8; 4] = b"PK\x03\x04";
+const END_OF_CENTRAL_DIR_SIGNATURE: &[u8; 4] = b"PK\x05\x06";
+// The end of central directory record is 22 bytes plus a comment of up to 64 KiB
+const END_OF_CENTRAL_DIR_SIZE: u64 = 22;
+const MAX_ZIP_COMMENT: u64 = u16::MAX as u64;
+
+// Top-level names a standard .mrpack may contain
+const STANDARD_ENTRIES: [&str; 4] = ["modrinth.index.json", "overrides/", "client-overrides/", "server-overrides/"];
+
+// The overrides directories a .mrpack may have: for both sides, client only and server only
+pub const OVERRIDES_DIRS: [&str; 3] = ["overrides", "client-overrides", "server-overrides"];
+
+// Written into every extraction directory, so a later extraction knows it may clear it
+pub const EXTRACTION_MARKER: &str = ".modrinther-extracted";
+
+// The index file name the .mrpack format specifies
+pub const DEFAULT_INDEX_NAME: &str = "modrinth.index.json";
+
+/// Options for extracting a pack archive.
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Report top-level entries that don't belong in a standard .mrpack
+    pub audit: bool,
+    /// File name of the index to look for, for nonstandard packs
+    pub index_name: String,
+    /// Threads extracting entries at once, each reading the archive through its own handle
+    pub concurrency: usize,
+    /// Skip entries that can't be read (bad data or compression, a broken header)
+    /// instead of failing the extraction; a corrupt index still fails it
+    pub skip_corrupt: bool,
+    /// How deep below the extraction the index is looked for
+    pub max_depth: usize,
+    /// Refuse to clear an existing, non-empty extraction directory that holds no
+    /// marker of an earlier extraction, for a directory the user picked
+    pub protect_existing: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions { audit: false, index_name: DEFAULT_INDEX_NAME.to_string(), concurrency: 1, skip_corrupt: false, max_depth: DEFAULT_MAX_DEPTH, protect_existing: false }
+    }
+}
+
+// Helper function to check if a file is an archive (ZIP or MRPACK)
+pub fn is_archive_file(path: &Path) -> bool {
+    if let Some(ext) = path.extension() {
+        let ext_str = ext.to_string_lossy().to_lowercase();
+        return ext_str == "zip" || ext_str == "mrpack";
+    }
+    false
+}
+
+// Extracts the archive into `temp_dir` (wiping it first) and parses the index inside
+pub fn process_archive_file(
+    archive_path: &Path,
+    temp_dir: &Path,
+    options: &ExtractOptions,
+    progress: &dyn ProgressSink,
+) -> Result<(ModrinthIndex, PathBuf), Box<dyn Error>> {
+    let index = extract_pack(archive_path, temp_dir, options, progress, true)?;
+    Ok((index, archive_path.parent().unwrap_or(Path::new(".")).to_path_buf()))
+}
+
+// Whether `dir` has entries but no EXTRACTION_MARKER, so clearing it could delete the user's files
+fn holds_foreign_files(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some()) && !dir.join(EXTRACTION_MARKER).exists()
+}
+
+// Does the work of process_archive_file. An archive without an index that holds
+// another pack (a .mrpack zipped up with a README, say) is extracted in turn, into
+// a subdirectory of `temp_dir`, if `allow_nested`.
+fn extract_pack(
+    archive_path: &Path,
+    temp_dir: &Path,
+    options: &ExtractOptions,
+    progress: &dyn ProgressSink,
+    allow_nested: bool,
+) -> Result<ModrinthIndex, Box<dyn Error>> {
+    // Tell a wrong or half-downloaded file apart before touching the temp directory
+    check_zip_file(archive_path)?;
+
+    // Open the archive file (ZIP or MRPACK)
+    let file = fs::File::open(archive_path)?;
+    let mut archive = ZipArchive::new(file)
+        .map_err(|e| format!("'{}' is not a readable zip/mrpack archive: {}", archive_path.display(), e))?;
+    let total = archive.len();
+    let encrypted = (0..total).any(|i| {
+        matches!(archive.by_index(i), Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)))
+    });
+    if encrypted {
+        return Err(format!("'{}' is an encrypted archive, which is unsupported", archive_path.display()).into());
+    }
+
+    // Create temp directory for extraction
+    let display_dir = temp_dir;
+    let temp_dir = &long_path(temp_dir);
+    if options.protect_existing && holds_foreign_files(temp_dir) {
+        return Err(format!(
+            "Refusing to extract into '{}': it isn't empty and wasn't created by modrinther. Remove it or pick another directory",
+            display_dir.display()
+        ).into());
+    }
+    if temp_dir.exists() {
+        fs::remove_dir_all(temp_dir)?;
+    }
+    fs::create_dir_all(temp_dir)?;
+    fs::write(temp_dir.join(EXTRACTION_MARKER), b"")?;
+    
+    info!("Extracting archive to temporary directory: {}", display_dir.display());
+    
+    let mut stray = BTreeSet::new();
+    let mut top_level = BTreeSet::new();
+    let mut files = Vec::new();
+    let done = AtomicUsize::new(0);
+    let skipped = Mutex::new(Vec::new());
+
+    // Create every directory up front, so the files can then be written in any order
+    for i in 0..total {
+        let entry = match archive.by_index_raw(i) {
+            Ok(entry) => entry,
+            Err(e) if options.skip_corrupt => {
+                warn!("Skipping corrupt archive entry #{}: {}", i, e);
+                skipped.lock().unwrap().push(format!("#{}", i));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let outpath = join_components(temp_dir, entry.name());
+        let top = top_level_entry(entry.name());
+        if options.audit && !STANDARD_ENTRIES.contains(&top.as_str()) && top != options.index_name {
+            stray.insert(top.clone());
+        }
+        top_level.insert(top);
+
+        if entry.name().ends_with('/') {
+            fs::create_dir_all(&outpath)?;
+            progress.on_extract_entry(entry.name(), done.fetch_add(1, Ordering::Relaxed) + 1, total);
+        } else {
+            if let Some(p) = outpath.parent() {
+                if !p.exists() {
+                    fs::create_dir_all(p)?;
+                }
+            }
+            files.push(FileEntry { index: i, name: entry.name().to_string(), outpath });
+        }
+    }
+
+    // Extract all files, on several threads if asked to. Zip entries can't be read
+    // through one handle concurrently, so each worker opens the archive again.
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let extract = |archive: &mut ZipArchive<fs::File>| -> Result<(), EntryFailure> {
+        while !failed.load(Ordering::Relaxed) {
+            let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                break;
+            };
+            match extract_entry(archive, file) {
+                Ok(()) => {}
+                Err(EntryFailure::Archive(e)) if options.skip_corrupt && !is_index_entry(&file.name, &options.index_name) => {
+                    warn!("Skipping corrupt archive entry {}: {}", file.name, e);
+                    let _ = fs::remove_file(&file.outpath);
+                    skipped.lock().unwrap().push(file.name.clone());
+                }
+                Err(e) => {
+                    failed.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+            progress.on_extract_entry(&file.name, done.fetch_add(1, Ordering::Relaxed) + 1, total);
+        }
+        Ok(())
+    };
+    let workers = options.concurrency.clamp(1, files.len().max(1));
+    let result = if workers == 1 {
+        extract(&mut archive)
+    } else {
+        debug!("Extracting {} files on {} threads", files.len(), workers);
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| scope.spawn(|| {
+                    let file = fs::File::open(archive_path).map_err(|e| EntryFailure::Archive(e.into()))?;
+                    let mut archive = ZipArchive::new(file).map_err(|e| EntryFailure::Archive(e.into()))?;
+                    extract(&mut archive)
+                }))
+                .collect();
+            handles.into_iter().try_for_each(|handle| handle.join().expect("extraction thread panicked"))
+        })
+    };
+    match result {
+        Ok(()) => {}
+        Err(EntryFailure::Incomplete(reason)) => return Err(extraction_incomplete(temp_dir, &reason)),
+        Err(EntryFailure::Archive(e)) => return Err(e),
+    }
+    let skipped = skipped.into_inner().unwrap();
+    if !skipped.is_empty() {
+        warn!("Skipped {} corrupt archive entr{}: {}", skipped.len(), if skipped.len() == 1 { "y" } else { "ies" }, skipped.join(", "));
+    }
+    let skipped: BTreeSet<String> = skipped.into_iter().collect();
+    let nested: Vec<(String, PathBuf)> = files.into_iter()
+        .filter(|file| is_archive_file(&file.outpath) && !skipped.contains(&file.name))
+        .map(|file| (file.name, file.outpath))
+        .collect();
+    
+    for entry in &stray {
+        info!("Audit: nonstandard top-level entry in archive: {}", entry);
+    }
+    if options.audit && stray.is_empty() {
+        info!("Audit: archive layout is standard");
+    }
+    
+    // Find the modrinth.index.json file
+    let index_path = match find_index_json(temp_dir, &options.index_name, options.max_depth) {
+        Ok(index_path) => index_path,
+        Err(e) if e.is::<TreeTooDeep>() => return Err(e),
+        Err(_) if allow_nested && !nested.is_empty() => {
+            let (name, path) = pick_nested_pack(archive_path, &nested)?;
+            info!("{} has no {}, but contains the pack {}; installing that", archive_path.display(), options.index_name, name);
+            return extract_pack(path, &temp_dir.join(NESTED_DIR), options, progress, false);
+        }
+        Err(_) => return Err(missing_index_error(&options.index_name, &top_level)),
+    };
+    
+    // Read and parse the index file
+    let mut index = load_index(&index_path)?;
+    
+    // Check if "overrides" folder exists in the extracted archive
+    if let Some(overrides_path) = find_overrides(&index_path, temp_dir) {
+        info!("Found overrides directory at: {}", overrides_path.display());
+        index.overrides_path = Some(overrides_path);
+    }
+    
+    Ok(index)
+}
+
+// Reads the index straight out of the archive, with nothing extracted or written:
+// the root one, else the shallowest entry named `index_name`. Returns it, its entry
+// name, and the names of all the archive's entries.
+pub fn read_packed_index(archive_path: &Path, index_name: &str) -> Result<(ModrinthIndex, String, Vec<String>), Box<dyn Error>> {
+    check_zip_file(archive_path)?;
+    let mut archive = ZipArchive::new(fs::File::open(archive_path)?)
+        .map_err(|e| format!("'{}' is not a readable zip/mrpack archive: {}", archive_path.display(), e))?;
+    let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    let index_entry = names.iter()
+        .filter(|name| is_index_entry(name, index_name))
+        .min_by_key(|name| name.matches('/').count())
+        .cloned()
+        .ok_or_else(|| missing_index_error(index_name, &names.iter().map(|name| top_level_entry(name)).collect()))?;
+
+    let mut bytes = Vec::new();
+    archive.by_name(&index_entry)?.read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read {} from '{}': {}", index_entry, archive_path.display(), e))?;
+    let index = parse_index(&bytes, &archive_path.join(&index_entry))?;
+    Ok((index, index_entry, names))
+}
+
+// Whether a zip entry is the pack's index (at the root or nested deeper), which can't
+// be skipped: there's no pack without it
+fn is_index_entry(name: &str, index_name: &str) -> bool {
+    name.rsplit('/').next() == Some(index_name)
+}
+
+// Where a nested pack is extracted, inside the outer archive's extraction
+const NESTED_DIR: &str = ".modrinther-nested";
+
+// The one pack inside an archive: a .mrpack if there is one, otherwise a .zip.
+// Several of the same kind are ambiguous, so the user has to pick.
+fn pick_nested_pack<'a>(archive_path: &Path, nested: &'a [(String, PathBuf)]) -> Result<(&'a str, &'a Path), Box<dyn Error>> {
+    let is_mrpack = |name: &str| name.to_ascii_lowercase().ends_with(".mrpack");
+    let mrpacks: Vec<&(String, PathBuf)> = nested.iter().filter(|(name, _)| is_mrpack(name)).collect();
+    let candidates = if mrpacks.is_empty() { nested.iter().collect() } else { mrpacks };
+    match candidates.as_slice() {
+        [(name, path)] => Ok((name.as_str(), path.as_path())),
+        _ => {
+            let names: Vec<&str> = candidates.iter().map(|(name, _)| name.as_str()).collect();
+            Err(format!(
+                "'{}' has no index but contains several packs ({}); extract the one you want and install it directly",
+                archive_path.display(), names.join(", ")
+            ).into())
+        }
+    }
+}
+
+// Checks the magic bytes and the end of central directory record, so a file that
+// isn't a zip at all and a truncated (partially downloaded) archive get their own errors
+fn check_zip_file(path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let mut magic = [0u8; 4];
+    let magic_len = file.read(&mut magic)?;
+    let magic = &magic[..magic_len];
+    if magic != LOCAL_FILE_SIGNATURE && magic != END_OF_CENTRAL_DIR_SIGNATURE {
+        let hint = match magic.first() {
+            None => " (it is empty)",
+            Some(b'<') => " (it looks like an HTML page, e.g. a saved download page)",
+            Some(b'{') => " (it looks like JSON, rename it to .json)",
+            _ => "",
+        };
+        return Err(format!("'{}' is not a zip/mrpack file{}", path.display(), hint).into());
+    }
+
+    // A complete zip ends with the end of central directory record, possibly followed
+    // by a comment; a partial download stops short of it
+    let tail_len = size.min(END_OF_CENTRAL_DIR_SIZE + MAX_ZIP_COMMENT);
+    let mut tail = Vec::with_capacity(tail_len as usize);
+    file.seek(SeekFrom::Start(size - tail_len))?;
+    file.take(tail_len).read_to_end(&mut tail)?;
+    let complete = size >= END_OF_CENTRAL_DIR_SIZE
+        && tail.windows(4).any(|window| window == END_OF_CENTRAL_DIR_SIGNATURE);
+    if !complete {
+        return Err(format!(
+            "'{}' is a truncated archive ({}), probably an unfinished download; download the pack again",
+            path.display(), format_bytes(size as f64)
+        ).into());
+    }
+    Ok(())
+}
+
+// How many top-level entries a missing-index error lists
+const LISTED_ENTRIES: usize = 10;
+
+// Explains an archive without an index: what it contains, and what it probably is instead
+fn missing_index_error(index_name: &str, top_level: &BTreeSet<String>) -> Box<dyn Error> {
+    let hint = if top_level.contains("manifest.json") {
+        "it has a manifest.json, so it looks like a CurseForge pack; install it with a launcher that imports CurseForge packs (e.g. Prism Launcher)"
+    } else if top_level.contains("pack.mcmeta") {
+        "it has a pack.mcmeta, so it looks like a resource pack or data pack, which goes into an instance's resourcepacks/ (or datapacks/) folder, not a modpack"
+    } else if !top_level.is_empty() && top_level.iter().all(|entry| entry.ends_with(".jar") || entry == "META-INF/") {
+        "it looks like a mod (or a pile of mods) rather than a modpack"
+    } else {
+        "it may not be a Modrinth pack, or the download is corrupt; try downloading the .mrpack again"
+    };
+
+    let mut found: Vec<&str> = top_level.iter().take(LISTED_ENTRIES).map(String::as_str).collect();
+    if top_level.len() > LISTED_ENTRIES {
+        found.push("...");
+    }
+    let found = if found.is_empty() { "nothing".to_string() } else { found.join(", ") };
+    format!("Could not find {} in the archive file, {}. The archive contains: {}", index_name, hint, found).into()
+}
+
+// The first path component of an entry name, with a trailing '/' for directories
+fn top_level_entry(name: &str) -> String {
+    let name = name.replace('\\', "/");
+    let name = name.trim_start_matches('/');
+    match name.split_once('/') {
+        Some((first, _)) => format!("{}/", first),
+        None => name.to_string(),
+    }
+}
+
+// A file entry of the archive, and where it's extracted to
+struct FileEntry {
+    index: usize,
+    name: String,
+    outpath: PathBuf,
+}
+
+// Why an entry couldn't be extracted: writing failed (most likely a full disk), or
+// the archive itself is bad
+enum EntryFailure {
+    Incomplete(String),
+    Archive(Box<dyn Error + Send + Sync>),
+}
+
+fn extract_entry(archive: &mut ZipArchive<fs::File>, entry: &FileEntry) -> Result<(), EntryFailure> {
+    let mut file = archive.by_index(entry.index).map_err(|e| EntryFailure::Archive(e.into()))?;
+    let expected = file.size();
+    let outfile = fs::File::create(&entry.outpath).map_err(|e| EntryFailure::Incomplete(e.to_string()))?;
+    match copy_entry(&mut file, &outfile) {
+        Ok(written) if written == expected => {
+            // The entry's own time, so --overrides-newer-only can tell which files
+            // changed since the last install; zip times have no zone and are taken as UTC
+            if let Ok(modified) = file.last_modified().to_time() {
+                let _ = outfile.set_modified(modified.into());
+            }
+            Ok(())
+        }
+        Ok(written) => Err(EntryFailure::Incomplete(format!(
+            "wrote {} of {} bytes of '{}'", written, expected, entry.outpath.display()
+        ))),
+        Err(EntryCopyError::Write(e)) => Err(EntryFailure::Incomplete(e.to_string())),
+        Err(EntryCopyError::Read(e)) => Err(EntryFailure::Archive(e.into())),
+    }
+}
+
+enum EntryCopyError {
+    Read(io::Error),
+    Write(io::Error),
+}
+
+// Like io::copy, but tells read failures (a bad entry) apart from write failures (usually a full disk)
+fn copy_entry(reader: &mut impl Read, mut writer: impl Write) -> Result<u64, EntryCopyError> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut written = 0u64;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(EntryCopyError::Read(e)),
+        };
+        writer.write_all(&buffer[..read]).map_err(EntryCopyError::Write)?;
+        written += read as u64;
+    }
+    writer.flush().map_err(EntryCopyError::Write)?;
+    Ok(written)
+}
+
+/// Extraction stopped partway, most likely because the disk filled up. The partial
+/// extraction has already been removed, so callers can retry elsewhere.
+#[derive(Debug)]
+pub struct ExtractionIncomplete {
+    pub temp_dir: PathBuf,
+    pub reason: String,
+}
+
+impl fmt::Display for ExtractionIncomplete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Extraction incomplete — likely out of disk space on {} ({})",
+            self.temp_dir.display(), self.reason
+        )
+    }
+}
+
+impl Error for ExtractionIncomplete {}
+
+// Removes the partial extraction and explains the most likely cause
+fn extraction_incomplete(temp_dir: &Path, reason: &str) -> Box<dyn Error> {
+    let _ = fs::remove_dir_all(temp_dir);
+    Box::new(ExtractionIncomplete { temp_dir: temp_dir.to_path_buf(), reason: reason.to_string() })
+}
+
+// How many directories above the index's own to look for overrides
+const OVERRIDES_SEARCH_LEVELS: usize = 2;
+
+// Looks for "overrides" beside the index, then a few levels up (repackaged archives
+// sometimes nest the index deeper than the overrides), then at the archive root
+pub fn find_overrides(index_path: &Path, root: &Path) -> Option<PathBuf> {
+    let index_dir = index_path.parent().unwrap_or(Path::new("."));
+    let candidates = index_dir.ancestors()
+        .take(OVERRIDES_SEARCH_LEVELS + 1)
+        .take_while(|dir| dir.starts_with(root))
+        .chain(std::iter::once(root));
+
+    for dir in candidates {
+        let overrides_path = dir.join("overrides");
+        if overrides_path.is_dir() {
+            if dir != index_dir {
+                debug!("Overrides are not beside the index, found them at {}", overrides_path.display());
+            }
+            return Some(overrides_path);
+        }
+    }
+    None
+}
+
+// Looks for the index named `index_name` at the root of `dir`, then anywhere below it
+// (no deeper than `max_depth`), depth-first in directory order. Symlinked directories
+// aren't followed, so a link loop can't keep the search going. Directories past
+// `max_depth` are skipped, and only make the search fail as too deep if the index
+// isn't anywhere else either.
+pub fn find_index_json(dir: &Path, index_name: &str, max_depth: usize) -> Result<PathBuf, Box<dyn Error>> {
+    // First check if the index exists in the root
+    let index_path = dir.join(index_name);
+    if index_path.exists() {
+        return Ok(index_path);
+    }
+
+    // Otherwise, search the tree, with an explicit stack rather than recursion
+    let entries = |dir: &Path| fs::read_dir(dir).map(|entries| entries.flatten().collect::<Vec<_>>()).unwrap_or_default().into_iter();
+    let mut stack = vec![(entries(dir), 0)];
+    let mut too_deep = None;
+    while let Some((dir_entries, depth)) = stack.last_mut() {
+        let depth = *depth;
+        let Some(entry) = dir_entries.next() else {
+            stack.pop();
+            continue;
+        };
+        let path = entry.path();
+        if path.is_file() && entry.file_name().to_string_lossy() == index_name {
+            return Ok(path);
+        }
+        if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            if depth + 1 > max_depth {
+                too_deep.get_or_insert(path);
+                continue;
+            }
+            stack.push((entries(&path), depth + 1));
+        }
+    }
+    match too_deep {
+        Some(path) => Err(Box::new(TreeTooDeep { path, max_depth })),
+        None => Err(format!("Could not find {} in the archive file", index_name).into()),
+    }
+}
+
+/// How [`export_zip`] compresses the files it packs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ZipCompression {
+    /// No compression: fastest, and jars are compressed already
+    Stored,
+    #[default]
+    Deflate,
+    Zstd,
+}
+
+impl FromStr for ZipCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stored" => Ok(ZipCompression::Stored),
+            "deflate" => Ok(ZipCompression::Deflate),
+            "zstd" => Ok(ZipCompression::Zstd),
+            _ => Err(format!("unknown zip compression '{}', expected stored, deflate or zstd", s)),
+        }
+    }
+}
+
+impl ZipCompression {
+    fn method(self) -> CompressionMethod {
+        match self {
+            ZipCompression::Stored => CompressionMethod::Stored,
+            ZipCompression::Deflate => CompressionMethod::Deflated,
+            ZipCompression::Zstd => CompressionMethod::Zstd,
+        }
+    }
+
+    // The levels the zip crate accepts for the method
+    fn levels(self) -> Option<RangeInclusive<i32>> {
+        match self {
+            ZipCompression::Stored => None,
+            ZipCompression::Deflate => Some(0..=9),
+            ZipCompression::Zstd => Some(-7..=22),
+        }
+    }
+}
+
+/// Options for [`export_zip`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExportOptions {
+    pub compression: ZipCompression,
+    /// The method's compression level: 0 to 9 for deflate, -7 to 22 for zstd (0 is its
+    /// default). None is the zip crate's default, 6 for deflate and 3 for zstd.
+    pub level: Option<i32>,
+}
+
+impl ExportOptions {
+    // Whether the zip crate accepts `level` for the method
+    pub fn check(&self) -> Result<(), String> {
+        match (self.level, self.compression.levels()) {
+            (None, _) => Ok(()),
+            (Some(_), None) => Err("A compression level doesn't apply to stored (uncompressed) zip entries".to_string()),
+            (Some(level), Some(levels)) if !levels.contains(&level) => Err(format!(
+                "Zip compression level {} is out of range, expected {} to {}", level, levels.start(), levels.end()
+            )),
+            (Some(_), Some(_)) => Ok(()),
+        }
+    }
+}
+
+/// What [`export_zip`] wrote.
+#[derive(Debug, Clone, Copy)]
+pub struct ExportedZip {
+    pub files: usize,
+    /// The size of the archive itself
+    pub bytes: u64,
+    /// The total size of the files packed into it
+    pub uncompressed: u64,
+}
+
+// Zips an installed directory into `dest`, to hand the finished pack to someone else:
+// every file and directory under its relative path (with its permissions, on Unix).
+// Symlinks, .part files, modrinther's lock and checkpoint, and `dest` itself (if it's
+// inside `dir`) are left out. A failed export removes the partial archive.
+pub fn export_zip(dir: &Path, dest: &Path, options: ExportOptions) -> Result<ExportedZip, Box<dyn Error>> {
+    options.check()?;
+    let file = fs::File::create(dest).map_err(|e| format!("Failed to create '{}': {}", dest.display(), e))?;
+    let skip = fs::canonicalize(dest)?;
+    let mut zip = ZipWriter::new(io::BufWriter::new(file));
+    let mut exported = ExportedZip { files: 0, bytes: 0, uncompressed: 0 };
+    let written = add_dir_to_zip(&mut zip, &long_path(dir), Path::new(""), &skip, options, &mut exported)
+        .and_then(|()| Ok(zip.finish()?.flush()?));
+    if let Err(e) = written {
+        let _ = fs::remove_file(dest);
+        return Err(format!("Failed to export '{}' to '{}': {}", dir.display(), dest.display(), e).into());
+    }
+    exported.bytes = fs::metadata(dest)?.len();
+    Ok(exported)
+}
+
+fn add_dir_to_zip(
+    zip: &mut ZipWriter<io::BufWriter<fs::File>>,
+    dir: &Path,
+    relative: &Path,
+    skip: &Path,
+    options: ExportOptions,
+    exported: &mut ExportedZip,
+) -> Result<(), Box<dyn Error>> {
+    // Sorted, so the same install always gives the same archive layout
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let relative_path = relative.join(entry.file_name());
+        let name = portable_path(&relative_path);
+        let meta = entry.metadata()?;
+        if meta.file_type().is_symlink() {
+            debug!("Not exporting symlink {}", name);
+            continue;
+        }
+        let entry_options = zip_entry_options(&meta);
+        if meta.is_dir() {
+            zip.add_directory(name, entry_options)?;
+            add_dir_to_zip(zip, &path, &relative_path, skip, options, exported)?;
+            continue;
+        }
+        let own_file = relative == Path::new("") && [Checkpoint::FILE_NAME, InstallLock::FILE_NAME].contains(&name.as_str());
+        if own_file || path.extension().is_some_and(|ext| ext == "part") || fs::canonicalize(&path).is_ok_and(|path| path == skip) {
+            continue;
+        }
+        let entry_options = entry_options
+            .compression_method(options.compression.method())
+            .compression_level(options.level)
+            .large_file(meta.len() >= u32::MAX as u64);
+        zip.start_file(name, entry_options)?;
+        exported.uncompressed += io::copy(&mut fs::File::open(&path)?, zip)?;
+        exported.files += 1;
+    }
+    Ok(())
+}
+
+// Directories are stored whatever the compression, there's nothing to compress
+#[cfg(unix)]
+fn zip_entry_options(meta: &fs::Metadata) -> FileOptions {
+    use std::os::unix::fs::PermissionsExt;
+    FileOptions::default().compression_method(CompressionMethod::Stored).unix_permissions(meta.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn zip_entry_options(_meta: &fs::Metadata) -> FileOptions {
+    FileOptions::default().compression_method(CompressionMethod::Stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn too_deep_subtree_doesnt_hide_a_shallow_index() {
+        let dir = temp_dir("find-index");
+        // Several over-deep siblings, so at least one is likely walked before the pack
+        for name in ["a", "m", "z"] {
+            fs::create_dir_all(dir.join(name).join("b/c/d/e")).unwrap();
+        }
+        fs::create_dir_all(dir.join("pack")).unwrap();
+        fs::write(dir.join("pack").join(DEFAULT_INDEX_NAME), "{}").unwrap();
+
+        let found = find_index_json(&dir, DEFAULT_INDEX_NAME, 2).unwrap();
+        assert_eq!(found, dir.join("pack").join(DEFAULT_INDEX_NAME));
+
+        // Without the shallow one, the depth limit is what's reported
+        fs::remove_dir_all(dir.join("pack")).unwrap();
+        let error = find_index_json(&dir, DEFAULT_INDEX_NAME, 2).unwrap_err();
+        assert!(error.is::<TreeTooDeep>(), "{}", error);
+        // and a missing index in a shallow tree is just missing
+        let error = find_index_json(&dir, DEFAULT_INDEX_NAME, 10).unwrap_err();
+        assert!(!error.is::<TreeTooDeep>(), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn protected_extraction_dir_with_foreign_files_is_left_alone() {
+        let dir = temp_dir("protect-existing");
+        let pack = dir.join("pack.mrpack");
+        let mut zip = ZipWriter::new(fs::File::create(&pack).unwrap());
+        zip.start_file(DEFAULT_INDEX_NAME, FileOptions::default()).unwrap();
+        io::Write::write_all(&mut zip, br#"{"formatVersion": 1, "game": "minecraft", "versionId": "1", "name": "Pack", "files": [], "dependencies": {}}"#).unwrap();
+        zip.finish().unwrap();
+        let options = ExtractOptions { protect_existing: true, ..ExtractOptions::default() };
+        let target = dir.join("kept");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("notes.txt"), "mine").unwrap();
+
+        let error = process_archive_file(&pack, &target, &options, &crate::progress::NoProgress).unwrap_err();
+        assert!(error.to_string().contains("Refusing"), "{}", error);
+        assert_eq!(fs::read_to_string(target.join("notes.txt")).unwrap(), "mine");
+
+        // An earlier extraction is marked, so it is replaced
+        fs::remove_dir_all(&target).unwrap();
+        process_archive_file(&pack, &target, &options, &crate::progress::NoProgress).unwrap();
+        process_archive_file(&pack, &target, &options, &crate::progress::NoProgress).unwrap();
+        assert!(target.join(EXTRACTION_MARKER).is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
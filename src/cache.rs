@@ -0,0 +1,194 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use log::debug;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::fsutil::part_path;
+use crate::hash::{digest_file, HashAlgorithm};
+use crate::index::ModFile;
+
+/// A content-addressed store of previously downloaded files, so a file shared by
+/// several packs (or reinstalls) is only fetched once.
+///
+/// Files live at `<dir>/<algorithm>/<first two hex digits>/<hash>`, keyed by the
+/// index's sha512 (or sha1 if that's all the index has).
+#[derive(Debug, Clone)]
+pub struct ContentCache {
+    dir: PathBuf,
+}
+
+/// What [`ContentCache::validate`] found. Paths are relative to the cache directory.
+#[derive(Debug, Default, Serialize)]
+pub struct CacheValidation {
+    /// Entries whose content matches the hash they're stored under
+    pub valid: usize,
+    /// Entries whose content doesn't match (or couldn't be read)
+    pub corrupt: Vec<String>,
+    /// How much space the corrupt entries took up
+    pub corrupt_bytes: u64,
+    /// Whether the corrupt entries were removed
+    pub removed: bool,
+    /// Files that aren't entries (misnamed, misplaced, or a copy that was interrupted),
+    /// which are left alone
+    pub unrecognized: Vec<String>,
+}
+
+impl ContentCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ContentCache { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Where `file` is (or would be) stored, or None if the index gives no usable hash
+    pub fn path_for(&self, file: &ModFile) -> Option<PathBuf> {
+        let (algorithm, hash) = [HashAlgorithm::Sha512, HashAlgorithm::Sha1].into_iter().find_map(|algorithm| {
+            file.hashes.get(algorithm.name())
+                .filter(|hash| algorithm.is_valid_digest(hash))
+                .map(|hash| (algorithm.name(), hash.to_ascii_lowercase()))
+        })?;
+        Some(self.dir.join(algorithm).join(&hash[..2]).join(hash))
+    }
+
+    pub fn contains(&self, file: &ModFile) -> bool {
+        self.path_for(file).is_some_and(|path| path.is_file())
+    }
+
+    // Copies the cached copy of `file` to `dest`. Returns false if there is none, or
+    // if it no longer matches its `algorithm` hash (the bad entry is then dropped).
+    pub fn restore(&self, file: &ModFile, dest: &Path, algorithm: HashAlgorithm) -> io::Result<bool> {
+        let cached = match self.path_for(file) {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(false),
+        };
+
+        let part = part_path(dest);
+        fs::copy(&cached, &part)?;
+        if digest_file(&part)?.mismatch(&file.hashes, algorithm).is_some() {
+            debug!("Cached copy of {} is corrupt, removing {}", file.path, cached.display());
+            let _ = fs::remove_file(&part);
+            let _ = fs::remove_file(&cached);
+            return Ok(false);
+        }
+        fs::rename(&part, dest)?;
+        Ok(true)
+    }
+
+    // Adds a downloaded (and already verified) file to the cache
+    pub fn store(&self, file: &ModFile, src: &Path) -> io::Result<()> {
+        let cached = match self.path_for(file) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if cached.is_file() {
+            return Ok(());
+        }
+        if let Some(parent) = cached.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Copy under a temporary name first so a concurrent reader never sees half a file
+        let temp = cached.with_extension(format!("tmp-{}", std::process::id()));
+        fs::copy(src, &temp)?;
+        fs::rename(&temp, &cached).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })
+    }
+
+    // Re-hashes every entry and checks it against the hash it's stored under, removing
+    // the corrupt ones if `remove` is set. Lists are sorted.
+    pub fn validate(&self, remove: bool) -> io::Result<CacheValidation> {
+        let mut validation = CacheValidation { removed: remove, ..CacheValidation::default() };
+        let mut entries = Vec::new();
+        for algorithm in [HashAlgorithm::Sha512, HashAlgorithm::Sha1] {
+            let algorithm_dir = self.dir.join(algorithm.name());
+            if !algorithm_dir.is_dir() {
+                continue;
+            }
+            for prefix in fs::read_dir(&algorithm_dir)? {
+                let prefix = prefix?;
+                let prefix_name = prefix.file_name().to_string_lossy().to_string();
+                if !prefix.file_type()?.is_dir() {
+                    validation.unrecognized.push(format!("{}/{}", algorithm.name(), prefix_name));
+                    continue;
+                }
+                for entry in fs::read_dir(prefix.path())? {
+                    let entry = entry?;
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    let is_entry = entry.file_type()?.is_file()
+                        && algorithm.is_valid_digest(&name)
+                        && name == name.to_ascii_lowercase()
+                        && name.starts_with(&prefix_name);
+                    let relative = format!("{}/{}/{}", algorithm.name(), prefix_name, name);
+                    if is_entry {
+                        entries.push((algorithm, entry.path(), name, relative));
+                    } else {
+                        validation.unrecognized.push(relative);
+                    }
+                }
+            }
+        }
+
+        let corrupt: Vec<(&PathBuf, &String)> = entries.par_iter()
+            .filter(|(algorithm, path, hash, _)| {
+                let actual = match digest_file(path) {
+                    Ok(digests) if *algorithm == HashAlgorithm::Sha1 => digests.sha1,
+                    Ok(digests) => digests.sha512,
+                    Err(e) => {
+                        debug!("Cache entry {} is unreadable: {}", path.display(), e);
+                        return true;
+                    }
+                };
+                actual != *hash
+            })
+            .map(|(_, path, _, relative)| (path, relative))
+            .collect();
+        validation.valid = entries.len() - corrupt.len();
+        for (path, relative) in corrupt {
+            validation.corrupt_bytes += fs::metadata(path).map_or(0, |meta| meta.len());
+            if remove {
+                fs::remove_file(path)?;
+            }
+            validation.corrupt.push(relative.clone());
+        }
+
+        validation.corrupt.sort();
+        validation.unrecognized.sort();
+        Ok(validation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha1::{Digest, Sha1};
+    use sha2::Sha512;
+    use crate::test_util::{mod_file, temp_dir};
+
+    #[test]
+    fn signed_urls_share_a_cache_entry() {
+        let dir = temp_dir("signed-cache");
+        let content = b"the same jar, signed twice";
+        let (sha1, sha512) = (format!("{:x}", Sha1::digest(content)), format!("{:x}", Sha512::digest(content)));
+        let hashes = [("sha1", sha1.as_str()), ("sha512", sha512.as_str())];
+        let first = mod_file("mods/a.jar", "https://cdn.example.com/a.jar?sig=a&expires=1700000000", &hashes, content.len() as u64);
+        let second = mod_file("mods/b.jar", "https://cdn.example.com/a.jar?sig=b&expires=1700000000", &hashes, content.len() as u64);
+        assert_eq!(first.content_key(), second.content_key());
+
+        let cache = ContentCache::new(dir.join("cache"));
+        assert_eq!(cache.path_for(&first), cache.path_for(&second));
+        let src = dir.join("a.jar");
+        fs::write(&src, content).unwrap();
+        cache.store(&first, &src).unwrap();
+        assert!(cache.contains(&second));
+
+        let dest = dir.join("b.jar");
+        assert!(cache.restore(&second, &dest, HashAlgorithm::Auto).unwrap());
+        assert_eq!(fs::read(&dest).unwrap(), content);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::fsutil::join_components;
+use crate::index::{ModFile, ModrinthIndex};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CheckpointData {
+    name: String,
+    version_id: String,
+    // File path -> the hash it was verified against
+    completed: BTreeMap<String, String>,
+}
+
+/// Records which files of an install have completed (and been verified), so an
+/// interrupted install can resume where it left off. Written to the output directory
+/// after every completed file, and removed once the whole pack has installed.
+#[derive(Debug)]
+pub struct Checkpoint {
+    output_dir: PathBuf,
+    data: Mutex<CheckpointData>,
+}
+
+impl Checkpoint {
+    pub const FILE_NAME: &'static str = ".modrinther-checkpoint.json";
+
+    // Loads the checkpoint left in `output_dir` by an earlier run of the same pack
+    // version; a missing, unreadable or foreign checkpoint starts over empty
+    pub fn open(output_dir: &Path, index: &ModrinthIndex) -> Self {
+        let path = output_dir.join(Self::FILE_NAME);
+        let data = fs::read_to_string(&path).ok()
+            .and_then(|content| serde_json::from_str::<CheckpointData>(&content).ok())
+            .filter(|data| data.name == index.name && data.version_id == index.version_id)
+            .unwrap_or_else(|| CheckpointData {
+                name: index.name.clone(),
+                version_id: index.version_id.clone(),
+                completed: BTreeMap::new(),
+            });
+        Checkpoint { output_dir: output_dir.to_path_buf(), data: Mutex::new(data) }
+    }
+
+    pub fn completed_count(&self) -> usize {
+        self.data.lock().unwrap().completed.len()
+    }
+
+    // Whether `file` was completed by an earlier run and is still on disk at full size
+    pub fn is_complete(&self, file: &ModFile) -> bool {
+        let recorded = self.data.lock().unwrap().completed.get(&file.path) == Some(&file_key(file));
+        recorded && fs::metadata(join_components(&self.output_dir, &file.path))
+            .is_ok_and(|meta| meta.is_file() && meta.len() == file.file_size)
+    }
+
+    // Marks `file` as completed and saves the checkpoint
+    pub fn record(&self, file: &ModFile) -> io::Result<()> {
+        let mut data = self.data.lock().unwrap();
+        data.completed.insert(file.path.clone(), file_key(file));
+        self.save(&data)
+    }
+
+    // Marks `path` as not completed after all (e.g. it failed verification), so the
+    // next run fetches it again
+    pub fn forget(&self, path: &str) -> io::Result<()> {
+        let mut data = self.data.lock().unwrap();
+        if data.completed.remove(path).is_none() {
+            return Ok(());
+        }
+        self.save(&data)
+    }
+
+    fn save(&self, data: &CheckpointData) -> io::Result<()> {
+        // Write under a temporary name, so an interruption never leaves a torn checkpoint
+        let path = self.output_dir.join(Self::FILE_NAME);
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string(data)?)?;
+        fs::rename(&temp, &path)
+    }
+
+    // Deletes the checkpoint, once every file has installed
+    pub fn remove(&self) {
+        if let Err(e) = fs::remove_file(self.output_dir.join(Self::FILE_NAME)) {
+            if e.kind() != io::ErrorKind::NotFound {
+                debug!("Failed to remove checkpoint: {}", e);
+            }
+        }
+    }
+}
+
+/// The settings an install ran with, written to the output directory as
+/// `modpack_run.json` once it's ready and again when the install ends, so a bug report
+/// can say what they were and a resumed install can tell whether they changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub modrinther_version: String,
+    /// The pack installed, as given
+    pub input: String,
+    /// When the install started and ended, in RFC 3339; `finished_at` stays null if
+    /// the run was interrupted
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    /// The effective options, by name; anything secret (like `--auth` tokens) is left out
+    pub settings: BTreeMap<String, Value>,
+}
+
+impl RunRecord {
+    pub const FILE_NAME: &'static str = "modpack_run.json";
+
+    // A record of a run starting now
+    pub fn start(input: &str, settings: BTreeMap<String, Value>) -> Self {
+        RunRecord {
+            modrinther_version: env!("CARGO_PKG_VERSION").to_string(),
+            input: input.to_string(),
+            started_at: now(),
+            finished_at: None,
+            settings,
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.finished_at = Some(now());
+    }
+
+    // The record an earlier run left in `dir`, if there is a readable one
+    pub fn load(dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(dir.join(Self::FILE_NAME)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let path = dir.join(Self::FILE_NAME);
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temp, &path)
+    }
+
+    // How this run's settings (and modrinther version) differ from `earlier`'s, one
+    // description per setting, sorted by name
+    pub fn differences(&self, earlier: &RunRecord) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.modrinther_version != earlier.modrinther_version {
+            differences.push(format!("modrinther {}, now {}", earlier.modrinther_version, self.modrinther_version));
+        }
+        let names: BTreeSet<&String> = self.settings.keys().chain(earlier.settings.keys()).collect();
+        for name in names {
+            let (before, now) = (earlier.settings.get(name).unwrap_or(&Value::Null), self.settings.get(name).unwrap_or(&Value::Null));
+            if before != now {
+                differences.push(format!("{}: {}, now {}", name, before, now));
+            }
+        }
+        differences
+    }
+}
+
+fn now() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+// What identifies a completed file's content: its sha512, else its sha1
+fn file_key(file: &ModFile) -> String {
+    file.hashes.get("sha512")
+        .or_else(|| file.hashes.get("sha1"))
+        .cloned()
+        .unwrap_or_default()
+}
@@ -0,0 +1,39 @@
+use std::path::{Path, PathBuf};
+use directories::ProjectDirs;
+
+/// The one directory holding everything modrinther keeps between runs, so all of
+/// its state can be relocated (`--data-dir`) or cleared at once:
+///
+/// - `cache/`: the download cache (see [`crate::cache::ContentCache`])
+/// - `logs/`: logs written by `--log-to-data-dir`
+///
+/// Per-pack artifacts (summaries, checkpoint, manifest) live in the pack's output
+/// directory instead.
+#[derive(Debug, Clone)]
+pub struct DataDir {
+    root: PathBuf,
+}
+
+impl DataDir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DataDir { root: root.into() }
+    }
+
+    // The platform's per-user data directory (e.g. ~/.local/share/modrinther), if it
+    // can be determined
+    pub fn default_root() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "modrinther").map(|dirs| dirs.data_dir().to_path_buf())
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.root.join("cache")
+    }
+
+    pub fn logs_dir(&self) -> PathBuf {
+        self.root.join("logs")
+    }
+}
@@ -0,0 +1,1866 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use futures::stream::{self, StreamExt};
+use hyper::client::connect::dns::Name;
+use log::{debug, info, warn};
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::redirect::Policy;
+use reqwest::StatusCode;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, OnceCell, OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use crate::cache::ContentCache;
+use crate::checkpoint::Checkpoint;
+use crate::error::InstallError;
+use crate::fsutil::{format_bytes, join_components, join_inside, long_path, part_path};
+use crate::hash::{digest_file, hash_problems, Digests, HashAlgorithm, HashPolicy, StreamHasher};
+use crate::index::{disabled_variants, ModFile};
+use crate::progress::ProgressSink;
+
+pub type DownloadResult = Result<Fetched, InstallError>;
+
+/// Where an installed file's bytes came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchSource {
+    Network,
+    Cache,
+    /// Completed by an earlier, interrupted run (see [`Checkpoint`]), or already on
+    /// disk with the right hash (see [`DownloadOptions::resume`])
+    Resumed,
+    /// Copied from another file of the pack with the same content (see
+    /// [`ModFile::content_key`]), or from the enabled copy of a `.disabled` file
+    Duplicate,
+}
+
+impl FetchSource {
+    /// A stable machine-readable name, used in the JSON outputs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FetchSource::Network => "network",
+            FetchSource::Cache => "cache",
+            FetchSource::Resumed => "resumed",
+            FetchSource::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// A successfully installed file: how many bytes, and where they came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fetched {
+    pub bytes: u64,
+    pub source: FetchSource,
+    /// The URL the bytes were downloaded from, after any `--mirror` rewrite, for
+    /// [`FetchSource::Network`] downloads
+    pub url: Option<String>,
+}
+
+/// Totals over a set of download results, separating network downloads from cache hits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadStats {
+    pub downloaded_files: usize,
+    pub downloaded_bytes: u64,
+    pub cache_hits: usize,
+    pub cache_bytes: u64,
+    pub resumed_files: usize,
+    /// Files copied from another file with the same content (by hash, or by URL for
+    /// files without one), i.e. requests saved
+    pub duplicate_urls: usize,
+}
+
+impl DownloadStats {
+    pub fn from_results(results: &[DownloadResult]) -> Self {
+        let mut stats = DownloadStats::default();
+        for fetched in results.iter().filter_map(|r| r.as_ref().ok()) {
+            match fetched.source {
+                FetchSource::Network => {
+                    stats.downloaded_files += 1;
+                    stats.downloaded_bytes += fetched.bytes;
+                }
+                FetchSource::Cache => {
+                    stats.cache_hits += 1;
+                    stats.cache_bytes += fetched.bytes;
+                }
+                FetchSource::Resumed => stats.resumed_files += 1,
+                FetchSource::Duplicate => stats.duplicate_urls += 1,
+            }
+        }
+        stats
+    }
+}
+
+/// The order files are handed to the downloader in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DownloadOrder {
+    /// As listed in the index
+    #[default]
+    Index,
+    /// Smallest first, for quick early feedback
+    SizeAsc,
+    /// Largest first, to saturate bandwidth early
+    SizeDesc,
+    /// Alphabetical by path
+    Path,
+}
+
+impl DownloadOrder {
+    // The value --order takes
+    pub fn name(self) -> &'static str {
+        match self {
+            DownloadOrder::Index => "index",
+            DownloadOrder::SizeAsc => "size-asc",
+            DownloadOrder::SizeDesc => "size-desc",
+            DownloadOrder::Path => "path",
+        }
+    }
+}
+
+impl FromStr for DownloadOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "index" => Ok(DownloadOrder::Index),
+            "size-asc" => Ok(DownloadOrder::SizeAsc),
+            "size-desc" => Ok(DownloadOrder::SizeDesc),
+            "path" => Ok(DownloadOrder::Path),
+            _ => Err(format!("unknown order '{}', expected index, size-asc, size-desc or path", s)),
+        }
+    }
+}
+
+/// Rewrites downloads from one host to another, e.g. `cdn.modrinth.com=mirror.lan:8080`.
+/// The target may also carry a scheme (`cdn.modrinth.com=http://mirror.lan`).
+#[derive(Debug, Clone)]
+pub struct MirrorRule {
+    pub from: String,
+    pub to: String,
+}
+
+impl FromStr for MirrorRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(MirrorRule {
+                from: from.trim().to_string(),
+                to: to.trim().to_string(),
+            }),
+            _ => Err(format!("expected FROM=TO, got '{}'", s)),
+        }
+    }
+}
+
+/// A setting for one download host that takes precedence over the global one, e.g.
+/// `slow.example.com=120`. The host is matched exactly (ignoring case), after mirrors.
+#[derive(Debug, Clone)]
+pub struct HostSetting<T> {
+    pub host: String,
+    pub value: T,
+}
+
+impl<T: FromStr> FromStr for HostSetting<T> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((host, value)) if !host.trim().is_empty() => match value.trim().parse() {
+                Ok(parsed) => Ok(HostSetting { host: host.trim().to_string(), value: parsed }),
+                Err(_) => Err(format!("invalid value '{}' for host '{}'", value, host.trim())),
+            },
+            _ => Err(format!("expected HOST=VALUE, got '{}'", s)),
+        }
+    }
+}
+
+// The value listed for `host`, if any
+fn setting_for<'a, T>(settings: &'a [HostSetting<T>], host: &str) -> Option<&'a T> {
+    settings.iter().find(|setting| setting.host.eq_ignore_ascii_case(host)).map(|setting| &setting.value)
+}
+
+/// Credentials sent to one download host, e.g. `files.example.com:TOKEN`. A token of
+/// the form `USER:PASSWORD` is sent as basic auth, anything else as a bearer token.
+#[derive(Clone)]
+pub struct HostAuth {
+    pub host: String,
+    pub credential: Credential,
+}
+
+#[derive(Clone)]
+pub enum Credential {
+    Bearer(String),
+    Basic { user: String, password: String },
+}
+
+// Never prints the secret, so options can be logged safely
+impl fmt::Debug for HostAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.credential {
+            Credential::Bearer(_) => "bearer",
+            Credential::Basic { .. } => "basic",
+        };
+        write!(f, "HostAuth {{ host: {:?}, credential: <{} redacted> }}", self.host, kind)
+    }
+}
+
+impl FromStr for HostAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The token isn't echoed in the error, it may end up in a terminal's scrollback
+        let (host, token) = match s.split_once(':') {
+            Some((host, token)) if !host.trim().is_empty() && !token.is_empty() => (host.trim(), token),
+            _ => return Err("expected HOST:TOKEN (or HOST:USER:PASSWORD)".to_string()),
+        };
+        let credential = match token.split_once(':') {
+            Some((user, password)) => Credential::Basic { user: user.to_string(), password: password.to_string() },
+            None => Credential::Bearer(token.to_string()),
+        };
+        Ok(HostAuth { host: host.to_string(), credential })
+    }
+}
+
+// Adds the credentials configured for the URL's host (exact match, any port) to `request`
+fn with_auth(request: reqwest::RequestBuilder, url: &reqwest::Url, auth: &[HostAuth]) -> reqwest::RequestBuilder {
+    let host = url.host_str().unwrap_or_default();
+    match auth.iter().find(|auth| auth.host.eq_ignore_ascii_case(host)).map(|auth| &auth.credential) {
+        Some(Credential::Bearer(token)) => request.bearer_auth(token),
+        Some(Credential::Basic { user, password }) => request.basic_auth(user, Some(password)),
+        None => request,
+    }
+}
+
+// Applies the first mirror rule matching the URL's host. Only the scheme, host and
+// port change; path and query string are kept as they are.
+pub fn apply_mirrors(url: &str, mirrors: &[MirrorRule]) -> Result<String, InstallError> {
+    let mut parsed = match reqwest::Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return Ok(url.to_string()),
+    };
+    let host = parsed.host_str().unwrap_or_default().to_string();
+    let rule = match mirrors.iter().find(|rule| rule.from.eq_ignore_ascii_case(&host)) {
+        Some(rule) => rule,
+        None => return Ok(url.to_string()),
+    };
+
+    let target = if rule.to.contains("://") {
+        rule.to.clone()
+    } else {
+        format!("{}://{}", parsed.scheme(), rule.to)
+    };
+    let invalid = || InstallError::Other(format!("Mirror '{}' does not form a valid URL for {}", rule.to, url));
+    let target = reqwest::Url::parse(&target).map_err(|_| invalid())?;
+    if target.host_str().is_none() {
+        return Err(invalid());
+    }
+    parsed.set_scheme(target.scheme()).map_err(|_| invalid())?;
+    parsed.set_host(target.host_str()).map_err(|_| invalid())?;
+    parsed.set_port(target.port()).map_err(|_| invalid())?;
+
+    debug!("Mirror rewrote {} to {}", url, parsed);
+    Ok(parsed.to_string())
+}
+
+/// Runs on every file right after it's verified and renamed into place (downloaded,
+/// restored from the cache or copied from a duplicate, not kept from an earlier run),
+/// given the file and where it is on disk, on a blocking thread. An error fails the
+/// file, which is then removed again, see [`DownloadOptions::on_file`].
+#[derive(Clone)]
+pub struct FileHook(pub Arc<FileHookFn>);
+
+/// The closure inside a [`FileHook`].
+pub type FileHookFn = dyn Fn(&ModFile, &Path) -> Result<(), String> + Send + Sync;
+
+impl fmt::Debug for FileHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FileHook")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    pub order: DownloadOrder,
+    /// How many downloads may hit the same host at once
+    pub per_host_connections: usize,
+    /// Hosts allowed more (or fewer) simultaneous downloads than `per_host_connections`
+    pub host_connections: Vec<HostSetting<usize>>,
+    /// How long one request may take, body included, before it fails as a (retried)
+    /// timeout; None waits as long as it takes
+    pub timeout: Option<Duration>,
+    /// Hosts with their own `timeout`
+    pub host_timeouts: Vec<HostSetting<Duration>>,
+    /// If not empty, only these hosts (and their subdomains) may be downloaded from,
+    /// including as the target of a redirect
+    pub allowed_hosts: Vec<String>,
+    /// Redirect hops followed before a download fails
+    pub max_redirects: usize,
+    /// Look every host up once and reuse its addresses for the whole run, instead of a
+    /// lookup for every new connection (see [`CachingResolver`])
+    pub dns_cache: bool,
+    /// Hosts connected to at a fixed address instead of looking them up
+    pub resolve: Vec<HostSetting<IpAddr>>,
+    /// How long an unused connection is kept open for reuse; None keeps reqwest's
+    /// default (90 seconds)
+    pub pool_idle_timeout: Option<Duration>,
+    /// How many unused connections per host are kept open for reuse; None keeps
+    /// reqwest's default (no limit)
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Host rewrites applied to every download URL before fetching
+    pub mirrors: Vec<MirrorRule>,
+    /// Parallel ranged requests per large file; 1 downloads every file as a single stream
+    pub connections_per_file: usize,
+    /// Files at least this many bytes are split across `connections_per_file` requests
+    pub chunk_threshold: u64,
+    /// Previously downloaded files are taken from here, and new downloads added to it
+    pub cache: Option<ContentCache>,
+    /// Never touch the network; every file must come from the cache
+    pub offline: bool,
+    /// Which of the index's hashes downloads are verified against
+    pub hash: HashAlgorithm,
+    /// What a hash mismatch leads to
+    pub hash_policy: HashPolicy,
+    /// Fail a file instead of falling back when it lacks the requested hash
+    pub strict: bool,
+    /// Accept invalid TLS certificates (e.g. a self-signed private mirror)
+    pub allow_insecure: bool,
+    /// Files completed by an earlier run are skipped, and completions recorded here
+    pub checkpoint: Option<Arc<Checkpoint>>,
+    /// Abort any download whose body grows past this many bytes, whatever the index declares
+    pub max_file_size: Option<u64>,
+    /// Credentials attached to requests for matching hosts (after mirrors are applied)
+    pub auth: Vec<HostAuth>,
+    /// How often a transiently failing download is retried; 0 never retries
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for every further one
+    pub retry_delay: Duration,
+    /// Only retry failures [`InstallError::is_transient`] calls transient; a download
+    /// whose hash doesn't match fails right away
+    pub retry_only_transient: bool,
+    /// Downloaded bytes are reported to the progress sink in batches of this many (or
+    /// every 100 ms, whichever comes first); 0 reports every chunk
+    pub progress_batch: u64,
+    /// Recover an interrupted install: files already on disk with the right hash are
+    /// kept, and `.part` files are continued with a range request instead of restarted
+    pub resume: bool,
+    /// Once a file has failed for good, cancel every other download (they fail with
+    /// [`InstallError::Cancelled`]) instead of finishing the rest
+    pub fail_fast: bool,
+    /// How many files are downloaded at once
+    pub net_concurrency: usize,
+    /// How many downloads may write to disk at once (see [`DiskWriters`])
+    pub disk_concurrency: usize,
+    /// For a file with several download URLs, try them in the order their servers answer
+    /// a quick HEAD request, fastest first, moving on when one fails (each server is
+    /// probed once per run, see [`HostLimiter`])
+    pub fastest_mirror: bool,
+    /// Called on every file once it's in place; a file it fails on is reported as
+    /// failed ([`InstallError::Hook`]), and with `strict` the other downloads are
+    /// cancelled as with `fail_fast`
+    pub on_file: Option<FileHook>,
+    /// Instead of `net_concurrency` downloads at a time, start with a few and add more while the total
+    /// throughput keeps improving, backing off when retries pile up (see [`AutoConcurrency`])
+    pub auto_concurrency: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            order: DownloadOrder::Index,
+            per_host_connections: 3,
+            host_connections: Vec::new(),
+            timeout: None,
+            host_timeouts: Vec::new(),
+            allowed_hosts: Vec::new(),
+            max_redirects: 5,
+            dns_cache: false,
+            resolve: Vec::new(),
+            pool_idle_timeout: None,
+            pool_max_idle_per_host: None,
+            mirrors: Vec::new(),
+            connections_per_file: 1,
+            chunk_threshold: 32 * 1024 * 1024,
+            cache: None,
+            offline: false,
+            hash: HashAlgorithm::Auto,
+            hash_policy: HashPolicy::Strict,
+            strict: false,
+            allow_insecure: false,
+            checkpoint: None,
+            max_file_size: None,
+            auth: Vec::new(),
+            progress_batch: 256 * 1024,
+            max_retries: 3,
+            retry_delay: Duration::from_millis(500),
+            retry_only_transient: false,
+            resume: false,
+            fail_fast: false,
+            fastest_mirror: false,
+            on_file: None,
+            net_concurrency: 5,
+            disk_concurrency: 4,
+            auto_concurrency: false,
+        }
+    }
+}
+
+impl DownloadOptions {
+    // The request timeout for `host`: its own if it has one, else the global one
+    fn timeout_for(&self, host: &str) -> Option<Duration> {
+        setting_for(&self.host_timeouts, host).copied().or(self.timeout)
+    }
+}
+
+pub fn is_host_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    allowed_hosts.is_empty() || allowed_hosts.iter().any(|allowed| {
+        host.eq_ignore_ascii_case(allowed)
+            || host.to_ascii_lowercase().ends_with(&format!(".{}", allowed.to_ascii_lowercase()))
+    })
+}
+
+// Builds the HTTP client shared by all downloads. Redirects are capped, and every
+// hop is checked against the host allowlist so a mirror can't bounce us elsewhere.
+pub fn build_client(options: &DownloadOptions) -> reqwest::Result<reqwest::Client> {
+    let allowed_hosts = options.allowed_hosts.clone();
+    let max_redirects = options.max_redirects;
+    let policy = Policy::custom(move |attempt| {
+        if attempt.previous().len() > max_redirects {
+            return attempt.error(format!("too many redirects (more than {})", max_redirects));
+        }
+        let host = attempt.url().host_str().unwrap_or_default().to_string();
+        if !is_host_allowed(&host, &allowed_hosts) {
+            return attempt.error(format!("redirected to disallowed host '{}'", host));
+        }
+        attempt.follow()
+    });
+    let mut builder = reqwest::Client::builder()
+        .user_agent(concat!("ArsenijN/modrinther/", env!("CARGO_PKG_VERSION")))
+        .redirect(policy)
+        .danger_accept_invalid_certs(options.allow_insecure);
+    if options.dns_cache {
+        builder = builder.dns_resolver(Arc::new(CachingResolver::default()));
+    }
+    for pinned in &options.resolve {
+        // The port is ignored, connections keep the URL's
+        builder = builder.resolve(&pinned.host, SocketAddr::new(pinned.value, 0));
+    }
+    if let Some(timeout) = options.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(timeout);
+    }
+    if let Some(max) = options.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    builder.build()
+}
+
+/// A DNS resolver that looks each host up once (with the system resolver) and hands
+/// out the same addresses for every later connection, for `--dns-cache`. Lookups that
+/// fail aren't cached. Hosts pinned with [`DownloadOptions::resolve`] never get here.
+#[derive(Default)]
+pub struct CachingResolver {
+    cache: Arc<Mutex<HashMap<String, Vec<SocketAddr>>>>,
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let cache = Arc::clone(&self.cache);
+        Box::pin(async move {
+            let cached = cache.lock().unwrap().get(&host).cloned();
+            let addrs = match cached {
+                Some(addrs) => addrs,
+                None => {
+                    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+                    debug!("Resolved {} to {:?}, reused for the rest of the run", host, addrs);
+                    cache.lock().unwrap().insert(host, addrs.clone());
+                    addrs
+                }
+            };
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// Downloads are written through a buffer this large, so servers sending tiny chunks
+// don't cost a write syscall per chunk
+const WRITE_BUFFER_SIZE: usize = 256 * 1024;
+// Downloaded bytes are reported at least this often, however slowly a batch fills up
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+// A download counts as stalled once nothing arrived for this long, and the progress
+// sink then hears about it every HEARTBEAT_INTERVAL
+const STALL_AFTER: Duration = Duration::from_secs(3);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+// A mirror that doesn't answer its probe within this long counts as unreachable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub fn host_of(url: &str) -> Option<String> {
+    reqwest::Url::parse(url).ok()?.host_str().map(str::to_string)
+}
+
+/// Caps how many downloads may talk to one host at the same time, on top of
+/// the global concurrency limit. Hosts listed in `overrides` get their own cap.
+/// Downloads through it also count their retries here, see [`AutoConcurrency`], and
+/// with [`DownloadOptions::fastest_mirror`] it remembers how fast each server answered.
+pub struct HostLimiter {
+    per_host: usize,
+    overrides: Vec<HostSetting<usize>>,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    retries: AtomicUsize,
+    latencies: Mutex<HashMap<String, Arc<OnceCell<Option<Duration>>>>>,
+}
+
+impl HostLimiter {
+    pub fn new(per_host: usize, overrides: Vec<HostSetting<usize>>) -> Self {
+        HostLimiter {
+            per_host: per_host.max(1),
+            overrides,
+            hosts: Mutex::new(HashMap::new()),
+            retries: AtomicUsize::new(0),
+            latencies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn note_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many download attempts have been retried so far
+    pub fn retries(&self) -> usize {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    pub async fn acquire(&self, url: &str) -> OwnedSemaphorePermit {
+        let host = host_of(url).unwrap_or_default().to_ascii_lowercase();
+        let limit = setting_for(&self.overrides, &host).map_or(self.per_host, |&limit| limit.max(1));
+        let semaphore = {
+            let mut hosts = self.hosts.lock().unwrap();
+            Arc::clone(hosts.entry(host).or_insert_with(|| Arc::new(Semaphore::new(limit))))
+        };
+        semaphore.acquire_owned().await.expect("host semaphore is never closed")
+    }
+
+    // How long the server of `url` took to answer a HEAD request for it, None if it
+    // didn't (in time, or with a 2xx or 3xx). Each server (scheme, host
+    // and port) is probed once; downloads asking at the same time share the probe.
+    async fn latency(&self, url: &reqwest::Url, client: &reqwest::Client, options: &DownloadOptions) -> Option<Duration> {
+        let origin = url.origin().ascii_serialization();
+        let cell = Arc::clone(self.latencies.lock().unwrap().entry(origin.clone()).or_default());
+        *cell.get_or_init(|| async {
+            let started = Instant::now();
+            let probe = with_auth(client.head(url.clone()), url, &options.auth).timeout(PROBE_TIMEOUT).send().await;
+            let latency = match probe {
+                // A quick 404 or 403 (an expired signature) means this server can't serve the file
+                Ok(response) if response.status().is_success() || response.status().is_redirection() => Some(started.elapsed()),
+                Ok(response) => {
+                    debug!("Mirror probe of {} answered HTTP {}", origin, response.status());
+                    None
+                }
+                Err(e) => {
+                    debug!("Mirror probe of {} failed: {}", origin, e);
+                    None
+                }
+            };
+            if let Some(latency) = latency {
+                debug!("Mirror probe of {} answered in {} ms", origin, latency.as_millis());
+            }
+            latency
+        }).await
+    }
+
+    /// Every server probed for [`DownloadOptions::fastest_mirror`], fastest first
+    /// (unreachable ones, None, last)
+    pub fn latencies(&self) -> Vec<(String, Option<Duration>)> {
+        let mut latencies: Vec<(String, Option<Duration>)> = self.latencies.lock().unwrap().iter()
+            .filter_map(|(origin, cell)| cell.get().map(|&latency| (origin.clone(), latency)))
+            .collect();
+        latencies.sort_by_key(|(origin, latency)| (latency.is_none(), *latency, origin.clone()));
+        latencies
+    }
+}
+
+/// Caps how many downloads write to disk at the same time, whatever the number being
+/// fetched. A download streams its body into a bounded queue of its own, drained by a
+/// writer that takes one of these slots per write: on a slow disk the writers wait
+/// their turn while the fetches go on, until a full queue makes them wait too, so
+/// memory stays bounded at a few dozen chunks per download.
+pub struct DiskWriters {
+    slots: Semaphore,
+}
+
+// How many received chunks a download may have queued for its writer
+const WRITE_QUEUE_CHUNKS: usize = 64;
+
+impl DiskWriters {
+    pub fn new(slots: usize) -> Self {
+        DiskWriters { slots: Semaphore::new(slots.max(1)) }
+    }
+
+    // Writes every chunk `queue` receives to `out`, in order, then flushes it
+    async fn drain<C: AsRef<[u8]>>(&self, mut out: BufWriter<File>, mut queue: mpsc::Receiver<C>) -> io::Result<()> {
+        while let Some(chunk) = queue.recv().await {
+            let _slot = self.slots.acquire().await.expect("disk writer semaphore is never closed");
+            out.write_all(chunk.as_ref()).await?;
+        }
+        let _slot = self.slots.acquire().await.expect("disk writer semaphore is never closed");
+        out.flush().await
+    }
+
+    // Runs `fetch` with the sending end of a write queue into `out`, next to the writer
+    // draining it. A failed write stops the fetch (its sends fail) and is the error
+    // reported; a failed fetch leaves whatever was written for the caller to clean up.
+    async fn write<T, C, F>(&self, out: BufWriter<File>, fetch: impl FnOnce(mpsc::Sender<C>) -> F) -> Result<T, AttemptFailure>
+    where
+        C: AsRef<[u8]>,
+        F: Future<Output = Result<T, AttemptFailure>>,
+    {
+        let (queue, queued) = mpsc::channel(WRITE_QUEUE_CHUNKS);
+        let (fetched, written) = tokio::join!(fetch(queue), self.drain(out, queued));
+        written.map_err(AttemptFailure::permanent)?;
+        fetched
+    }
+}
+
+// A chunk couldn't be queued because the writer stopped; its own error is what's reported
+fn writer_stopped<T>(_: mpsc::error::SendError<T>) -> AttemptFailure {
+    AttemptFailure::permanent(InstallError::Other("the disk writer stopped".to_string()))
+}
+
+// Where --auto-concurrency starts, and how far it may go
+const AUTO_CONCURRENCY_START: usize = 2;
+const AUTO_CONCURRENCY_MAX: usize = 32;
+// How often it measures the throughput and adjusts
+const AUTO_CONCURRENCY_INTERVAL: Duration = Duration::from_secs(2);
+// More retries than this in one interval halves the concurrency
+const AUTO_CONCURRENCY_RETRY_SPIKE: usize = 2;
+
+/// How many downloads may run at once with [`DownloadOptions::auto_concurrency`]. It
+/// starts at 2 and, every couple of seconds, grows by half while the total throughput
+/// improves by at least 10%; once it doesn't, it steps back to the last better value and
+/// stays there. A burst of retries (timeouts, 429s, 5xx) halves it.
+pub struct AutoConcurrency {
+    slots: Arc<Semaphore>,
+    /// Slots to take away as they're released, to shrink below how many are in use
+    excess: AtomicUsize,
+    active: AtomicUsize,
+    bytes: AtomicU64,
+    state: Mutex<AutoState>,
+}
+
+struct AutoState {
+    limit: usize,
+    /// The limit before the last increase, to go back to if it didn't help
+    previous: usize,
+    best: f64,
+    settled: bool,
+    last_bytes: u64,
+    last_retries: usize,
+}
+
+/// A download slot of an [`AutoConcurrency`], released (or taken away) when dropped.
+pub struct AutoSlot<'a> {
+    permit: Option<OwnedSemaphorePermit>,
+    auto: &'a AutoConcurrency,
+}
+
+impl Drop for AutoSlot<'_> {
+    fn drop(&mut self) {
+        self.auto.active.fetch_sub(1, Ordering::Relaxed);
+        let shrink = self.auto.excess.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1)).is_ok();
+        if let (true, Some(permit)) = (shrink, self.permit.take()) {
+            permit.forget();
+        }
+    }
+}
+
+impl Default for AutoConcurrency {
+    fn default() -> Self {
+        AutoConcurrency {
+            slots: Arc::new(Semaphore::new(AUTO_CONCURRENCY_START)),
+            excess: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            state: Mutex::new(AutoState {
+                limit: AUTO_CONCURRENCY_START,
+                previous: AUTO_CONCURRENCY_START,
+                best: 0.0,
+                settled: false,
+                last_bytes: 0,
+                last_retries: 0,
+            }),
+        }
+    }
+}
+
+impl AutoConcurrency {
+    pub async fn acquire(&self) -> AutoSlot<'_> {
+        let permit = Arc::clone(&self.slots).acquire_owned().await.expect("auto concurrency semaphore is never closed");
+        self.active.fetch_add(1, Ordering::Relaxed);
+        AutoSlot { permit: Some(permit), auto: self }
+    }
+
+    /// How many downloads may run at once, the best total throughput measured so far (in
+    /// bytes per second), and whether it has stopped ramping up
+    pub fn outcome(&self) -> (usize, f64, bool) {
+        let state = self.state.lock().unwrap();
+        (state.limit, state.best, state.settled)
+    }
+
+    fn set_limit(&self, state: &mut AutoState, limit: usize) {
+        if limit > state.limit {
+            self.slots.add_permits(limit - state.limit);
+        } else {
+            // Free slots go right away, the ones in use once their download ends
+            let shrink = state.limit - limit;
+            let forgotten = self.slots.forget_permits(shrink);
+            self.excess.fetch_add(shrink - forgotten, Ordering::Relaxed);
+        }
+        state.limit = limit;
+    }
+
+    // Called every AUTO_CONCURRENCY_INTERVAL with the retries counted so far
+    fn adjust(&self, retries: usize) {
+        let mut state = self.state.lock().unwrap();
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let throughput = (bytes - state.last_bytes) as f64 / AUTO_CONCURRENCY_INTERVAL.as_secs_f64();
+        let new_retries = retries - state.last_retries;
+        state.last_bytes = bytes;
+        state.last_retries = retries;
+
+        if new_retries > AUTO_CONCURRENCY_RETRY_SPIKE && state.limit > 1 {
+            let limit = (state.limit / 2).max(1);
+            debug!("Auto concurrency: {} retries in {}s, backing off to {}", new_retries, AUTO_CONCURRENCY_INTERVAL.as_secs(), limit);
+            self.set_limit(&mut state, limit);
+            state.settled = true;
+            return;
+        }
+        // With fewer downloads left than slots (the end of the queue), the throughput
+        // says nothing about the limit
+        if self.active.load(Ordering::Relaxed) < state.limit {
+            return;
+        }
+        if throughput > state.best * 1.1 {
+            state.best = throughput;
+            if !state.settled && state.limit < AUTO_CONCURRENCY_MAX {
+                let limit = (state.limit + (state.limit / 2).max(1)).min(AUTO_CONCURRENCY_MAX);
+                debug!("Auto concurrency: {}/s at {}, trying {}", format_bytes(throughput), state.limit, limit);
+                state.previous = state.limit;
+                self.set_limit(&mut state, limit);
+            }
+        } else if !state.settled {
+            let limit = state.previous;
+            debug!("Auto concurrency: {}/s at {} is no better, settling on {}", format_bytes(throughput), state.limit, limit);
+            self.set_limit(&mut state, limit);
+            state.settled = true;
+        }
+    }
+
+    // Adjusts the limit every interval until `done` fires
+    async fn run(&self, hosts: &HostLimiter, done: &CancellationToken) {
+        let mut ticks = tokio::time::interval(AUTO_CONCURRENCY_INTERVAL);
+        ticks.tick().await;
+        loop {
+            tokio::select! {
+                _ = done.cancelled() => return,
+                _ = ticks.tick() => self.adjust(hosts.retries()),
+            }
+        }
+    }
+}
+
+// Forwards every event, counting the downloaded bytes for AutoConcurrency
+struct MeteredProgress<'a> {
+    inner: &'a dyn ProgressSink,
+    auto: &'a AutoConcurrency,
+}
+
+impl ProgressSink for MeteredProgress<'_> {
+    fn on_start(&self, total_files: usize) {
+        self.inner.on_start(total_files);
+    }
+    fn on_extract_entry(&self, name: &str, done: usize, total: usize) {
+        self.inner.on_extract_entry(name, done, total);
+    }
+    fn on_copy_entry(&self, path: &str, done: usize, total: usize) {
+        self.inner.on_copy_entry(path, done, total);
+    }
+    fn on_verify_entry(&self, path: &str, done: usize, total: usize) {
+        self.inner.on_verify_entry(path, done, total);
+    }
+    fn on_file_start(&self, path: &str, size: u64) {
+        self.inner.on_file_start(path, size);
+    }
+    fn on_bytes(&self, path: &str, bytes: u64) {
+        self.auto.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.inner.on_bytes(path, bytes);
+    }
+    fn on_stalled(&self, path: &str, host: &str, idle: Duration) {
+        self.inner.on_stalled(path, host, idle);
+    }
+    fn on_file_done(&self, path: &str, error: Option<&str>) {
+        self.inner.on_file_done(path, error);
+    }
+    fn on_complete(&self) {
+        self.inner.on_complete();
+    }
+}
+
+// The delay a 429/503 response asks for, given either in seconds or as an HTTP date
+pub(crate) fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+}
+
+// Indices of `files` in the order they should be dispatched
+fn dispatch_order(files: &[ModFile], order: DownloadOrder) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..files.len()).collect();
+    match order {
+        DownloadOrder::Index => {}
+        DownloadOrder::SizeAsc => indices.sort_by_key(|&i| files[i].file_size),
+        DownloadOrder::SizeDesc => indices.sort_by_key(|&i| std::cmp::Reverse(files[i].file_size)),
+        DownloadOrder::Path => indices.sort_by(|&a, &b| files[a].path.cmp(&files[b].path)),
+    }
+    indices
+}
+
+// Downloads every file, `net_concurrency` at a time (or as many as AutoConcurrency allows), and
+// returns the results in input order, each with how long its download took. Files
+// with the same content (by hash, see `ModFile::content_key`), and `.disabled` copies
+// of other files, are fetched once and copied to the other paths.
+pub async fn download_files(
+    files: &[ModFile],
+    output_dir: &Path,
+    client: &reqwest::Client,
+    options: &DownloadOptions,
+    progress: &dyn ProgressSink,
+) -> Vec<(DownloadResult, Duration)> {
+    progress.on_start(files.len());
+
+    let hosts = HostLimiter::new(options.per_host_connections, options.host_connections.clone());
+    let hosts = &hosts;
+    let disk = DiskWriters::new(options.disk_concurrency);
+    let disk = &disk;
+    let order = dispatch_order(files, options.order);
+    let auto = AutoConcurrency::default();
+    let auto = &auto;
+    let metered = MeteredProgress { inner: progress, auto };
+    let progress: &dyn ProgressSink = if options.auto_concurrency { &metered } else { progress };
+
+    // The first file (in dispatch order) with each content key is downloaded; the rest
+    // follow it, even if their URLs differ (e.g. in a signed query string).
+    // A `.disabled` copy of another file follows that file (or the one it follows)
+    // whatever its URL, since the content is the same.
+    let twins: HashMap<usize, usize> = disabled_variants(files).into_iter().map(|(enabled, disabled)| (disabled, enabled)).collect();
+    if !twins.is_empty() {
+        info!("{} mod(s) ship both enabled and as a .disabled copy, each is downloaded once", twins.len());
+    }
+    let mut first_with_key: HashMap<String, usize> = HashMap::new();
+    let mut duplicates = Vec::new();
+    let mut leaders = Vec::new();
+    for &i in order.iter().filter(|i| !twins.contains_key(i)) {
+        match files[i].content_key() {
+            Some(key) => match first_with_key.get(&key) {
+                Some(&leader) => duplicates.push((i, leader)),
+                None => {
+                    first_with_key.insert(key, i);
+                    leaders.push(i);
+                }
+            },
+            None => leaders.push(i),
+        }
+    }
+    let followed: HashMap<usize, usize> = duplicates.iter().copied().collect();
+    for &i in order.iter().filter(|i| twins.contains_key(i)) {
+        // `x.jar.disabled.disabled` follows `x.jar` too
+        let mut enabled = twins[&i];
+        while let Some(&next) = twins.get(&enabled) {
+            enabled = next;
+        }
+        duplicates.push((i, followed.get(&enabled).copied().unwrap_or(enabled)));
+    }
+
+    // With fail_fast, the first file to fail for good cancels the downloads in flight,
+    // and the rest aren't started
+    let cancel = CancellationToken::new();
+    let cancel = &cancel;
+    let mut results: Vec<Option<(DownloadResult, Duration)>> = (0..files.len()).map(|_| None).collect();
+    let done = CancellationToken::new();
+    let downloads = async {
+        let downloaded = stream::iter(leaders.into_iter().map(|i| (i, &files[i])))
+            .map(|(i, file)| async move {
+                if cancel.is_cancelled() {
+                    return (i, (Err(InstallError::Cancelled), Duration::ZERO));
+                }
+                let _slot = match options.auto_concurrency {
+                    true => Some(auto.acquire().await),
+                    false => None,
+                };
+                progress.on_file_start(&file.path, file.file_size);
+                let started = Instant::now();
+                let result = cancellable(download_file(file, output_dir, client, options, hosts, disk, progress), options, cancel).await;
+                let duration = started.elapsed();
+                report_done(file, &result, progress);
+                (i, (result, duration))
+            })
+            // net_concurrency downloads at a time, unless AutoConcurrency's slots allow more
+            .buffer_unordered(if options.auto_concurrency { AUTO_CONCURRENCY_MAX } else { options.net_concurrency.max(1) })
+            .collect::<Vec<_>>()
+            .await;
+        done.cancel();
+        downloaded
+    };
+    let downloaded = if options.auto_concurrency {
+        let (downloaded, ()) = tokio::join!(downloads, auto.run(hosts, &done));
+        let (limit, best, settled) = auto.outcome();
+        match (best > 0.0, settled) {
+            (true, true) => info!("Auto concurrency settled on {} simultaneous download(s) ({}/s at best)", limit, format_bytes(best)),
+            (true, false) => info!("Auto concurrency was still ramping up at {} simultaneous download(s) ({}/s at best) when the downloads ran out", limit, format_bytes(best)),
+            (false, _) => info!("Auto concurrency stayed at {} simultaneous download(s), the downloads were over before it could measure", limit),
+        }
+        downloaded
+    } else {
+        downloads.await
+    };
+    for (i, result) in downloaded {
+        results[i] = Some(result);
+    }
+    let latencies = hosts.latencies();
+    if !latencies.is_empty() {
+        let measured: Vec<String> = latencies.iter()
+            .map(|(origin, latency)| match latency {
+                Some(latency) => format!("{} {} ms", origin, latency.as_millis()),
+                None => format!("{} unreachable", origin),
+            })
+            .collect();
+        info!("Mirror latency: {}", measured.join(", "));
+    }
+
+    if !duplicates.is_empty() {
+        debug!("{} file(s) have the same content as another file (by hash, or URL without one) and are copied instead of downloaded", duplicates.len());
+    }
+    for (i, leader) in duplicates {
+        let file = &files[i];
+        if cancel.is_cancelled() {
+            results[i] = Some((Err(InstallError::Cancelled), Duration::ZERO));
+            continue;
+        }
+        progress.on_file_start(&file.path, file.file_size);
+        let started = Instant::now();
+        // If the shared download failed, this file gets its own attempt
+        let result = match &results[leader] {
+            Some((Ok(_), _)) => copy_duplicate(&files[leader], file, output_dir, options, progress).await,
+            _ => cancellable(download_file(file, output_dir, client, options, hosts, disk, progress), options, cancel).await,
+        };
+        let duration = started.elapsed();
+        report_done(file, &result, progress);
+        results[i] = Some((result, duration));
+    }
+
+    progress.on_complete();
+
+    results.into_iter().map(|result| result.expect("every file has a result")).collect()
+}
+
+// Runs a file's download, giving up on it once `cancel` fires; with fail_fast, a
+// failure of its own fires `cancel` for every other download
+async fn cancellable(download: impl Future<Output = DownloadResult>, options: &DownloadOptions, cancel: &CancellationToken) -> DownloadResult {
+    let strict_hook = options.strict && options.on_file.is_some();
+    if !options.fail_fast && !strict_hook {
+        return download.await;
+    }
+    let result = tokio::select! {
+        result = download => result,
+        _ = cancel.cancelled() => Err(InstallError::Cancelled),
+    };
+    let stops_all = match &result {
+        Err(InstallError::Cancelled) | Ok(_) => false,
+        Err(InstallError::Hook(_)) => true,
+        Err(_) => options.fail_fast,
+    };
+    if stops_all {
+        cancel.cancel();
+    }
+    result
+}
+
+fn report_done(file: &ModFile, result: &DownloadResult, progress: &dyn ProgressSink) {
+    match result {
+        Ok(fetched) => {
+            debug!("Downloaded {} ({} bytes)", file.path, fetched.bytes);
+            progress.on_file_done(&file.path, None);
+        }
+        Err(e) => {
+            debug!("Failed to download {}: {}", file.path, e);
+            progress.on_file_done(&file.path, Some(&e.to_string()));
+        }
+    }
+}
+
+// Installs `file` by copying `leader`, which has the same content key (or is the
+// enabled copy of `file`, a `.disabled` one). The copy
+// is checked against `file`'s own hashes, which needn't match the leader's.
+async fn copy_duplicate(
+    leader: &ModFile,
+    file: &ModFile,
+    output_dir: &Path,
+    options: &DownloadOptions,
+    progress: &dyn ProgressSink,
+) -> DownloadResult {
+    check_inside(file, output_dir)?;
+    if let Some(checkpoint) = &options.checkpoint {
+        if checkpoint.is_complete(file) {
+            progress.on_bytes(&file.path, file.file_size);
+            return Ok(Fetched { bytes: file.file_size, source: FetchSource::Resumed, url: None });
+        }
+    }
+
+    let algorithm = hash_algorithm(file, options)?;
+    let source = join_components(&long_path(output_dir), &leader.path);
+    let file_path = join_components(&long_path(output_dir), &file.path);
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let part = part_path(&file_path);
+    let bytes = fs::copy(&source, &part)?;
+    if let Err(e) = check_hash(file, &digest_file(&part)?, algorithm, options.hash_policy) {
+        let _ = fs::remove_file(&part);
+        return Err(e);
+    }
+    fs::rename(&part, &file_path)?;
+    progress.on_bytes(&file.path, bytes);
+    if let Some(hook) = &options.on_file {
+        run_hook(hook, file, &file_path).await?;
+    }
+
+    if let Some(checkpoint) = &options.checkpoint {
+        if let Err(e) = checkpoint.record(file) {
+            debug!("Failed to update checkpoint for {}: {}", file.path, e);
+        }
+    }
+    Ok(Fetched { bytes, source: FetchSource::Duplicate, url: None })
+}
+
+// Runs the on_file hook on `file`, in place at `path`. The hook may well block (on a
+// command, say), so it gets a thread of its own. A file it fails on is removed again:
+// left in place with a good hash, the next run would take it for installed and never
+// run the hook on it.
+async fn run_hook(FileHook(hook): &FileHook, file: &ModFile, path: &Path) -> Result<(), InstallError> {
+    let (hook, owned, hooked) = (Arc::clone(hook), file.clone(), path.to_path_buf());
+    let result = match tokio::task::spawn_blocking(move || hook(&owned, &hooked)).await {
+        Ok(result) => result.map_err(InstallError::Hook),
+        Err(e) => Err(InstallError::Hook(e.to_string())),
+    };
+    if result.is_err() {
+        if let Err(e) = fs::remove_file(path) {
+            debug!("Failed to remove {} after its per-file command failed: {}", file.path, e);
+        }
+    }
+    result
+}
+
+// Refuses a file whose path (`../`, absolute, through a symlink, ...) would land outside `output_dir`
+fn check_inside(file: &ModFile, output_dir: &Path) -> Result<(), InstallError> {
+    match join_inside(output_dir, &file.path) {
+        Some(_) => Ok(()),
+        None => Err(InstallError::PathTraversal(file.path.clone())),
+    }
+}
+
+// Installs a single file, skipping it if the checkpoint says an earlier run already
+// did, and recording it in the checkpoint once done
+pub async fn download_file(
+    file: &ModFile, 
+    output_dir: &Path, 
+    client: &reqwest::Client,
+    options: &DownloadOptions,
+    hosts: &HostLimiter,
+    disk: &DiskWriters,
+    progress: &dyn ProgressSink,
+) -> DownloadResult {
+    check_inside(file, output_dir)?;
+    if let Some(checkpoint) = &options.checkpoint {
+        if checkpoint.is_complete(file) {
+            debug!("{} was completed by an earlier run", file.path);
+            progress.on_bytes(&file.path, file.file_size);
+            return Ok(Fetched { bytes: file.file_size, source: FetchSource::Resumed, url: None });
+        }
+    }
+
+    if options.resume && is_installed(file, output_dir, options.hash, options.hash_policy) {
+        debug!("{} is already installed with the right hash", file.path);
+        progress.on_bytes(&file.path, file.file_size);
+        if let Some(checkpoint) = &options.checkpoint {
+            if let Err(e) = checkpoint.record(file) {
+                debug!("Failed to update checkpoint for {}: {}", file.path, e);
+            }
+        }
+        return Ok(Fetched { bytes: file.file_size, source: FetchSource::Resumed, url: None });
+    }
+
+    let fetched = fetch_file(file, output_dir, client, options, hosts, disk, progress).await?;
+    if let Some(hook) = &options.on_file {
+        run_hook(hook, file, &join_components(&long_path(output_dir), &file.path)).await?;
+    }
+    if let Some(checkpoint) = &options.checkpoint {
+        if let Err(e) = checkpoint.record(file) {
+            debug!("Failed to update checkpoint for {}: {}", file.path, e);
+        }
+    }
+    Ok(fetched)
+}
+
+// Whether `file` is on disk at full size and its hash passes `policy`, as a download
+// would: with warn a mismatch is logged and the file counts, with off only the size is
+// checked. Otherwise a file without a usable hash never counts, since nothing vouches for it.
+pub(crate) fn is_installed(file: &ModFile, output_dir: &Path, algorithm: HashAlgorithm, policy: HashPolicy) -> bool {
+    let path = join_components(&long_path(output_dir), &file.path);
+    (policy == HashPolicy::Off || algorithm.resolve(&file.hashes).is_some())
+        && fs::metadata(&path).is_ok_and(|meta| meta.is_file() && meta.len() == file.file_size)
+        && (policy == HashPolicy::Off || digest_file(&path).is_ok_and(|digests| check_hash(file, &digests, algorithm, policy).is_ok()))
+}
+
+// Downloads a single file (or restores it from the cache). Transient
+// failures (network errors, 429 and 5xx responses) are retried with backoff,
+// and with fastest_mirror the file's other URLs are tried after that.
+async fn fetch_file(
+    file: &ModFile, 
+    output_dir: &Path, 
+    client: &reqwest::Client,
+    options: &DownloadOptions,
+    hosts: &HostLimiter,
+    disk: &DiskWriters,
+    progress: &dyn ProgressSink,
+) -> DownloadResult {
+    let file_path = join_components(&long_path(output_dir), &file.path);
+    let algorithm = hash_algorithm(file, options)?;
+    
+    // Create parent directories if they don't exist
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if let Some(cache) = &options.cache {
+        if cache.restore(file, &file_path, algorithm)? {
+            debug!("{} restored from cache", file.path);
+            progress.on_bytes(&file.path, file.file_size);
+            return Ok(Fetched { bytes: file.file_size, source: FetchSource::Cache, url: None });
+        }
+    }
+    if options.offline {
+        return Err(InstallError::NotCached);
+    }
+
+    // With fastest_mirror, a URL that fails (after its retries) gives way to the next one
+    let urls = download_urls(file, client, options, hosts).await?;
+    let target = Target { part: part_path(&file_path), path: &file_path, disk };
+    let mut error = InstallError::Other(format!("{} has no download URL", file.path));
+    for (i, url) in urls.iter().enumerate() {
+        if i > 0 {
+            warn!("{}: download failed ({}), trying {}", file.path, error, url);
+            progress.on_file_start(&file.path, file.file_size);
+        }
+        let host = host_of(url).unwrap_or_default();
+        if !is_host_allowed(&host, &options.allowed_hosts) {
+            return Err(InstallError::HostNotAllowed(host));
+        }
+
+        // Download using the shared client, holding one of the host's connection slots
+        let _permit = hosts.acquire(url).await;
+        let mut retries = 0;
+        error = loop {
+            let failure = match try_download(file, url, &target, client, options, algorithm, progress).await {
+                Ok(written) => {
+                    // The cache is keyed by the index hash, which only --hash-policy strict vouches for
+                    if let Some(cache) = options.cache.as_ref().filter(|_| options.hash_policy == HashPolicy::Strict) {
+                        if let Err(e) = cache.store(file, &file_path) {
+                            debug!("Failed to cache {}: {}", file.path, e);
+                        }
+                    }
+                    debug!("Downloaded {} from {}", file.path, url);
+                    return Ok(Fetched { bytes: written, source: FetchSource::Network, url: Some(url.clone()) });
+                }
+                Err(failure) => failure,
+            };
+            if !failure.retryable || retries >= options.max_retries {
+                break failure.error;
+            }
+            if options.retry_only_transient && !failure.error.is_transient() {
+                debug!("Not retrying {}, the failure is permanent ({}): {}", file.path, failure.error.kind(), failure.error);
+                break failure.error;
+            }
+
+            let delay = match failure.retry_after {
+                Some(delay) => {
+                    debug!("Honoring Retry-After of {}s for {}", delay.as_secs(), url);
+                    delay
+                }
+                None => options.retry_delay.saturating_mul(2u32.saturating_pow(retries)),
+            };
+            retries += 1;
+            hosts.note_retry();
+            debug!("Retrying {} in {}ms ({}/{}): {}", file.path, delay.as_millis(), retries, options.max_retries, failure.error);
+            tokio::time::sleep(delay).await;
+            progress.on_file_start(&file.path, file.file_size);
+        };
+        if matches!(error, InstallError::Cancelled) {
+            return Err(error);
+        }
+    }
+    Err(error)
+}
+
+// Where a download goes: the `.part` file it's written to (by `disk`'s writers) and
+// renamed from once complete
+struct Target<'a> {
+    path: &'a Path,
+    part: PathBuf,
+    disk: &'a DiskWriters,
+}
+
+// The URLs to download `file` from, in the order they're tried: its first one (after
+// mirrors) alone, or with fastest_mirror every allowed one by how quickly its server
+// answered its probe, unreachable ones last. Ties go to the earlier URL.
+async fn download_urls(file: &ModFile, client: &reqwest::Client, options: &DownloadOptions, hosts: &HostLimiter) -> Result<Vec<String>, InstallError> {
+    let first = apply_mirrors(&file.downloads[0], &options.mirrors)?;
+    if !options.fastest_mirror || file.downloads.len() < 2 {
+        return Ok(vec![first]);
+    }
+    let mut ranked: Vec<(Option<Duration>, usize, String)> = Vec::new();
+    for (i, url) in file.downloads.iter().enumerate() {
+        let Ok(url) = apply_mirrors(url, &options.mirrors) else { continue };
+        let Ok(parsed) = reqwest::Url::parse(&url) else { continue };
+        if !is_host_allowed(parsed.host_str().unwrap_or_default(), &options.allowed_hosts) {
+            continue;
+        }
+        let latency = hosts.latency(&parsed, client, options).await;
+        ranked.push((latency, i, url));
+    }
+    if ranked.is_empty() {
+        return Ok(vec![first]);
+    }
+    ranked.sort_by_key(|(latency, i, _)| (latency.is_none(), *latency, *i));
+    if ranked[0].2 != first {
+        debug!("{}: {} answered fastest, downloading from it instead of {}", file.path, ranked[0].2, first);
+    }
+    Ok(ranked.into_iter().map(|(_, _, url)| url).collect())
+}
+
+// Why one download attempt failed, and whether trying again could help
+struct AttemptFailure {
+    error: InstallError,
+    retryable: bool,
+    retry_after: Option<Duration>,
+}
+
+impl AttemptFailure {
+    fn permanent(error: impl Into<InstallError>) -> Self {
+        AttemptFailure { error: error.into(), retryable: false, retry_after: None }
+    }
+
+    fn transient(error: impl Into<InstallError>) -> Self {
+        AttemptFailure { error: error.into(), retryable: true, retry_after: None }
+    }
+}
+
+async fn try_download(
+    file: &ModFile,
+    url: &str,
+    target: &Target<'_>,
+    client: &reqwest::Client,
+    options: &DownloadOptions,
+    algorithm: HashAlgorithm,
+    progress: &dyn ProgressSink,
+) -> Result<u64, AttemptFailure> {
+    // With `resume`, a .part left by an interrupted run is continued from where it
+    // stopped, as a single stream. A 200 answer means the server ignored the range, so
+    // the file starts over.
+    let part = &target.part;
+    let resume_from = if options.resume { partial_len(part, file.file_size) } else { 0 };
+
+    // Large files start with a ranged request for the first chunk. A 206 answer means
+    // the server supports ranges and the remaining chunks are fetched in parallel;
+    // a 200 means it ignored the range, so the full body is streamed as usual.
+    let chunks = if resume_from > 0 { Vec::new() } else { chunk_ranges(file.file_size, options) };
+    let host = host_of(url).unwrap_or_default();
+    let mut request = match reqwest::Url::parse(url) {
+        Ok(parsed) => with_auth(client.get(parsed.clone()), &parsed, &options.auth),
+        Err(_) => client.get(url),
+    };
+    if let Some(timeout) = options.timeout_for(&host) {
+        request = request.timeout(timeout);
+    }
+    if let Some(&(start, end)) = chunks.first() {
+        request = request.header(RANGE, format!("bytes={}-{}", start, end));
+    } else if resume_from > 0 {
+        debug!("Resuming {} at byte {}", file.path, resume_from);
+        request = request.header(RANGE, format!("bytes={}-", resume_from));
+    }
+    let response = with_heartbeat(request.send(), |idle| progress.on_stalled(&file.path, &host, idle)).await.map_err(|e| match InstallError::from(e) {
+        error @ (InstallError::Redirect(_) | InstallError::Tls(_)) => AttemptFailure::permanent(error),
+        error => AttemptFailure::transient(error),
+    })?;
+    if response.url().as_str() != url {
+        debug!("{} redirected to {}", url, response.url());
+    }
+    
+    let status = response.status();
+    if !status.is_success() {
+        let error = match status {
+            StatusCode::NOT_FOUND | StatusCode::GONE => InstallError::Removed { status: status.as_u16() },
+            _ => InstallError::HttpStatus {
+                status: status.as_u16(),
+                reason: status.canonical_reason().unwrap_or_default().to_string(),
+            },
+        };
+        return Err(match status {
+            // Rate limited or temporarily down: wait as long as the server asks
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => AttemptFailure {
+                error,
+                retryable: true,
+                retry_after: retry_after(&response),
+            },
+            _ if status.is_server_error() => AttemptFailure::transient(error),
+            _ => AttemptFailure::permanent(error),
+        });
+    }
+    
+    // Write into a .part file and only rename it into place once complete,
+    // so an interrupted download never leaves a truncated file behind
+    let result = if status == StatusCode::PARTIAL_CONTENT && !chunks.is_empty() {
+        debug!("Downloading {} in {} ranged chunks", file.path, chunks.len());
+        download_chunked(file, target, response, client, options, algorithm, progress).await
+    } else if status == StatusCode::PARTIAL_CONTENT && resume_from > 0 {
+        match content_range_start(&response) {
+            Some(start) if start == resume_from => {
+                download_stream(file, target, response, resume_from, algorithm, options, progress).await
+            }
+            _ => {
+                // Start over on the next attempt instead of asking for the same range again
+                let _ = fs::remove_file(part);
+                Err(AttemptFailure::transient(InstallError::BadRange(format!(
+                    "Server answered the resume request for {} with the wrong range", file.path
+                ))))
+            }
+        }
+    } else {
+        if !chunks.is_empty() || resume_from > 0 {
+            debug!("{} doesn't support range requests, downloading as a single stream", url);
+        }
+        download_stream(file, target, response, 0, algorithm, options, progress).await
+    };
+
+    match result {
+        Ok(written) => {
+            tokio::fs::rename(part, target.path).await.map_err(AttemptFailure::permanent)?;
+            Ok(written)
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(part).await;
+            Err(e)
+        }
+    }
+}
+
+// How much of a file an interrupted run left in `part`, if it's worth continuing
+// (there, but not past the expected size); 0 otherwise
+fn partial_len(part: &Path, file_size: u64) -> u64 {
+    fs::metadata(part)
+        .ok()
+        .filter(|meta| meta.is_file() && meta.len() < file_size)
+        .map_or(0, |meta| meta.len())
+}
+
+// The first byte of a 206 response, from its `Content-Range: bytes START-END/SIZE`
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    range.strip_prefix("bytes ")?.split('-').next()?.trim().parse().ok()
+}
+
+// Splits a file into inclusive byte ranges, one per connection. Empty when the
+// file should be downloaded as a single stream.
+fn chunk_ranges(size: u64, options: &DownloadOptions) -> Vec<(u64, u64)> {
+    let connections = options.connections_per_file as u64;
+    if connections <= 1 || size == 0 || size < options.chunk_threshold {
+        return Vec::new();
+    }
+    let chunk = size.div_ceil(connections);
+    (0..size)
+        .step_by(chunk as usize)
+        .map(|start| (start, (start + chunk).min(size) - 1))
+        .collect()
+}
+
+// Awaits `future`, calling `stalled` with the time waited so far while it's stalled
+// (see STALL_AFTER), so a frozen progress bar can be told apart from a hung process
+async fn with_heartbeat<F: Future>(future: F, mut stalled: impl FnMut(Duration)) -> F::Output {
+    let mut future = pin!(future);
+    let started = Instant::now();
+    loop {
+        match tokio::time::timeout(HEARTBEAT_INTERVAL, &mut future).await {
+            Ok(output) => return output,
+            Err(_) if started.elapsed() >= STALL_AFTER => stalled(started.elapsed()),
+            Err(_) => {}
+        }
+    }
+}
+
+// Coalesces per-chunk progress into fewer `on_bytes` calls, since each one may take a
+// lock on a shared progress bar: bytes are reported once `batch` of them add up or
+// PROGRESS_INTERVAL has passed, and whatever is left when the batch is dropped
+struct ProgressBatch<'a> {
+    progress: &'a dyn ProgressSink,
+    path: &'a str,
+    batch: u64,
+    pending: u64,
+    last: Instant,
+}
+
+impl<'a> ProgressBatch<'a> {
+    fn new(progress: &'a dyn ProgressSink, path: &'a str, batch: u64) -> Self {
+        ProgressBatch { progress, path, batch, pending: 0, last: Instant::now() }
+    }
+
+    fn add(&mut self, bytes: u64) {
+        self.pending += bytes;
+        if self.pending >= self.batch || self.last.elapsed() >= PROGRESS_INTERVAL {
+            self.flush();
+        }
+    }
+
+    // Reports what has arrived so far, so the bar is current while nothing moves
+    fn stalled(&mut self, host: &str, idle: Duration) {
+        self.flush();
+        self.progress.on_stalled(self.path, host, idle);
+    }
+
+    fn flush(&mut self) {
+        if self.pending > 0 {
+            self.progress.on_bytes(self.path, self.pending);
+            self.pending = 0;
+        }
+        self.last = Instant::now();
+    }
+}
+
+impl Drop for ProgressBatch<'_> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// Streams the whole body into `part`, hashing it on the way, and gives up once it
+// grows past the size limit. With `resume_from`, the body is the rest of the file and
+// is appended to the first `resume_from` bytes already in `part`. Returns the bytes
+// received.
+async fn download_stream(
+    file: &ModFile,
+    target: &Target<'_>,
+    response: reqwest::Response,
+    resume_from: u64,
+    algorithm: HashAlgorithm,
+    options: &DownloadOptions,
+    progress: &dyn ProgressSink,
+) -> Result<u64, AttemptFailure> {
+    let part = &target.part;
+    let mut hasher = StreamHasher::new();
+    let out = if resume_from > 0 {
+        // The hash covers the whole file, so what's already there is hashed first
+        let mut existing = File::open(part).await.map_err(AttemptFailure::permanent)?;
+        let mut buffer = vec![0; WRITE_BUFFER_SIZE];
+        loop {
+            let read = existing.read(&mut buffer).await.map_err(AttemptFailure::permanent)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        progress.on_bytes(&file.path, resume_from);
+        OpenOptions::new().append(true).open(part).await.map_err(AttemptFailure::permanent)?
+    } else {
+        File::create(part).await.map_err(AttemptFailure::permanent)?
+    };
+    let out = BufWriter::with_capacity(WRITE_BUFFER_SIZE, out);
+    let host = response.url().host_str().unwrap_or_default().to_string();
+    let mut stream = response.bytes_stream();
+    let mut reported = ProgressBatch::new(progress, &file.path, options.progress_batch);
+    let limit = options.max_file_size;
+
+    let (written, hasher) = target.disk.write(out, |queue| async move {
+        let mut written = resume_from;
+        while let Some(chunk) = with_heartbeat(stream.next(), |idle| reported.stalled(&host, idle)).await {
+            let chunk = chunk.map_err(AttemptFailure::transient)?;
+            if let Some(limit) = limit.filter(|&limit| written + chunk.len() as u64 > limit) {
+                return Err(AttemptFailure::permanent(InstallError::TooLarge(limit)));
+            }
+            hasher.update(&chunk);
+            written += chunk.len() as u64;
+            reported.add(chunk.len() as u64);
+            queue.send(chunk).await.map_err(writer_stopped)?;
+        }
+        Ok((written, hasher))
+    }).await?;
+
+    // The digests were computed while streaming, so the file is never read back
+    check_size(file, written)?;
+    check_digests(file, &hasher.finish(), algorithm, options.hash_policy)?;
+    Ok(written - resume_from)
+}
+
+// Fetches every range in parallel, each written at its own offset of `part`.
+// `first` is the already-open response for the first range.
+async fn download_chunked(
+    file: &ModFile,
+    target: &Target<'_>,
+    first: reqwest::Response,
+    client: &reqwest::Client,
+    options: &DownloadOptions,
+    algorithm: HashAlgorithm,
+    progress: &dyn ProgressSink,
+) -> Result<u64, AttemptFailure> {
+    let part = &target.part;
+    let out = File::create(part).await.map_err(AttemptFailure::permanent)?;
+    out.set_len(file.file_size).await.map_err(AttemptFailure::permanent)?;
+    drop(out);
+
+    // Later chunks go straight to wherever the first request was redirected to
+    let chunks = chunk_ranges(file.file_size, options);
+    let url = first.url().clone();
+    let timeout = options.timeout_for(url.host_str().unwrap_or_default());
+    let mut first = Some(first);
+    let writes = chunks.iter().map(|&(start, end)| {
+        let response = first.take();
+        let url = url.clone();
+        async move {
+            let response = match response {
+                Some(response) => response,
+                None => {
+                    let mut request = with_auth(client.get(url.clone()), &url, &options.auth)
+                        .header(RANGE, format!("bytes={}-{}", start, end));
+                    if let Some(timeout) = timeout {
+                        request = request.timeout(timeout);
+                    }
+                    let response = request.send().await
+                        .map_err(AttemptFailure::transient)?;
+                    if response.status() != StatusCode::PARTIAL_CONTENT {
+                        return Err(AttemptFailure::transient(InstallError::BadRange(format!(
+                            "Range request for bytes {}-{} answered with HTTP {}", start, end, response.status()
+                        ))));
+                    }
+                    response
+                }
+            };
+            write_range(file, target, start, end - start + 1, response, options.progress_batch, progress).await
+        }
+    });
+    let written: u64 = futures::future::try_join_all(writes).await?.into_iter().sum();
+
+    // Chunks arrive out of order, so hash the reassembled file in one streaming pass
+    let path = part.to_path_buf();
+    let digests = tokio::task::spawn_blocking(move || digest_file(&path))
+        .await
+        .map_err(|e| AttemptFailure::permanent(InstallError::Other(e.to_string())))?
+        .map_err(AttemptFailure::permanent)?;
+    check_digests(file, &digests, algorithm, options.hash_policy)?;
+    Ok(written)
+}
+
+// Writes one ranged response at `offset` of `part`, failing if it isn't exactly `len` bytes
+async fn write_range(
+    file: &ModFile,
+    target: &Target<'_>,
+    offset: u64,
+    len: u64,
+    response: reqwest::Response,
+    progress_batch: u64,
+    progress: &dyn ProgressSink,
+) -> Result<u64, AttemptFailure> {
+    let mut out = OpenOptions::new().write(true).open(&target.part).await.map_err(AttemptFailure::permanent)?;
+    out.seek(SeekFrom::Start(offset)).await.map_err(AttemptFailure::permanent)?;
+    let out = BufWriter::with_capacity(WRITE_BUFFER_SIZE, out);
+    let host = response.url().host_str().unwrap_or_default().to_string();
+    let mut stream = response.bytes_stream();
+    let mut reported = ProgressBatch::new(progress, &file.path, progress_batch);
+
+    let written = target.disk.write(out, |queue| async move {
+        let mut written = 0u64;
+        while let Some(chunk) = with_heartbeat(stream.next(), |idle| reported.stalled(&host, idle)).await {
+            let chunk = chunk.map_err(AttemptFailure::transient)?;
+            if written + chunk.len() as u64 > len {
+                break;
+            }
+            written += chunk.len() as u64;
+            reported.add(chunk.len() as u64);
+            queue.send(chunk).await.map_err(writer_stopped)?;
+        }
+        Ok(written)
+    }).await?;
+
+    // A range that ended early is a download that ended early
+    if written != len {
+        debug!("Range at offset {} of {} returned {} of {} bytes", offset, file.path, written, len);
+        return Err(AttemptFailure::transient(InstallError::SizeMismatch { expected: len, actual: written }));
+    }
+    Ok(written)
+}
+
+// The hash a file is verified with. Falls back like `auto` (with a warning) when the
+// index lacks the requested one, unless strict. Malformed and unknown hashes are
+// warned about and ignored; a file left with none is installed unverified, unless strict.
+fn hash_algorithm(file: &ModFile, options: &DownloadOptions) -> Result<HashAlgorithm, InstallError> {
+    // Nothing is verified, so there's nothing to warn about either
+    if options.hash_policy == HashPolicy::Off {
+        return Ok(options.hash.resolve(&file.hashes).unwrap_or_default());
+    }
+    for problem in hash_problems(&file.hashes) {
+        warn!("{}: {}", file.path, problem);
+    }
+    let resolved = options.hash.resolve(&file.hashes);
+    if options.hash != HashAlgorithm::Auto && resolved != Some(options.hash) {
+        let message = format!("{} has no {} hash", file.path, options.hash.name());
+        if options.strict {
+            return Err(InstallError::Other(message));
+        }
+        if let Some(algorithm) = resolved {
+            warn!("{}, verifying with {} instead", message, algorithm.name());
+        }
+    }
+    match resolved {
+        Some(algorithm) => debug!("Verifying {} with {}", file.path, algorithm.name()),
+        None => {
+            let message = format!("{} has no usable sha1 or sha512 hash", file.path);
+            if options.strict {
+                return Err(InstallError::Other(message));
+            }
+            warn!("{}, installing it unverified", message);
+        }
+    }
+    Ok(resolved.unwrap_or_default())
+}
+
+// A server closing the connection early without an error status leaves a short file
+// that could still pass when the index has no usable hash, so the size the index
+// declares (if any) is checked too. Worth another try, like a corrupted transfer.
+fn check_size(file: &ModFile, actual: u64) -> Result<(), AttemptFailure> {
+    if file.file_size != 0 && actual != file.file_size {
+        return Err(AttemptFailure::transient(InstallError::SizeMismatch { expected: file.file_size, actual }));
+    }
+    Ok(())
+}
+
+// A corrupted transfer is worth another try
+fn check_digests(file: &ModFile, digests: &Digests, algorithm: HashAlgorithm, policy: HashPolicy) -> Result<(), AttemptFailure> {
+    check_hash(file, digests, algorithm, policy).map_err(AttemptFailure::transient)
+}
+
+// A mismatch fails the file with --hash-policy strict, is only logged with warn, and
+// isn't even looked for with off
+fn check_hash(file: &ModFile, digests: &Digests, algorithm: HashAlgorithm, policy: HashPolicy) -> Result<(), InstallError> {
+    let Some((algorithm, expected, actual)) = digests.mismatch(&file.hashes, algorithm).filter(|_| policy != HashPolicy::Off) else {
+        return Ok(());
+    };
+    if policy == HashPolicy::Warn {
+        warn!("{}: {} mismatch (expected {}, got {}), keeping it (--hash-policy warn)", file.path, algorithm, expected, actual);
+        return Ok(());
+    }
+    Err(InstallError::HashMismatch {
+        algorithm: algorithm.to_string(),
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::NoProgress;
+    use crate::test_util::{mod_file, temp_dir};
+
+    #[test]
+    fn installed_files_are_judged_by_the_hash_policy() {
+        let dir = temp_dir("is-installed");
+        fs::write(dir.join("a.jar"), b"content").unwrap();
+        let stale = mod_file("a.jar", "https://cdn.example.com/a.jar", &[("sha1", &"0".repeat(40))], 7);
+        assert!(!is_installed(&stale, &dir, HashAlgorithm::Auto, HashPolicy::Strict));
+        assert!(is_installed(&stale, &dir, HashAlgorithm::Auto, HashPolicy::Warn));
+        assert!(is_installed(&stale, &dir, HashAlgorithm::Auto, HashPolicy::Off));
+        // The size is checked under every policy
+        let short = mod_file("a.jar", "https://cdn.example.com/a.jar", &[("sha1", &"0".repeat(40))], 8);
+        assert!(!is_installed(&short, &dir, HashAlgorithm::Auto, HashPolicy::Off));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn escaping_paths_are_refused_without_writing() {
+        let base = temp_dir("check-inside");
+        let output_dir = base.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let client = reqwest::Client::new();
+        let options = DownloadOptions::default();
+        let hosts = HostLimiter::new(1, Vec::new());
+        let disk = DiskWriters::new(1);
+        for path in ["../x", "a/../../x", "/etc/x", "C:\\x"] {
+            // Never fetched: the path is refused first
+            let file = mod_file(path, "http://127.0.0.1:9/x", &[("sha1", &"0".repeat(40))], 1);
+            assert!(matches!(check_inside(&file, &output_dir), Err(InstallError::PathTraversal(p)) if p == path), "{:?}", path);
+            let result = download_file(&file, &output_dir, &client, &options, &hosts, &disk, &NoProgress).await;
+            assert!(matches!(result, Err(InstallError::PathTraversal(_))), "{:?}", path);
+        }
+        assert_eq!(fs::read_dir(&output_dir).unwrap().count(), 0);
+        assert!(!base.join("x").exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    // Serves every request with `body`, cut to `sent` bytes and without a Content-Length,
+    // closing the connection as a server that dies mid-transfer would. Returns the
+    // server's `http://host:port` and how many requests it got.
+    async fn test_server(status: &'static str, body: Vec<u8>, sent: usize) -> (String, Arc<AtomicUsize>) {
+        use tokio::io::AsyncReadExt;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counted.fetch_add(1, Ordering::SeqCst);
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match socket.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let _ = socket.write_all(format!("HTTP/1.1 {}\r\nConnection: close\r\n\r\n", status).as_bytes()).await;
+                let _ = socket.write_all(&body[..sent]).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        (format!("http://{}", address), requests)
+    }
+
+    #[tokio::test]
+    async fn truncated_body_fails_and_leaves_nothing_behind() {
+        use sha1::{Digest, Sha1};
+        let output_dir = temp_dir("truncated");
+        let body = vec![7u8; 1000];
+        let (server, _) = test_server("200 OK", body.clone(), 500).await;
+        let sha1 = format!("{:x}", Sha1::digest(&body));
+        let file = mod_file("mods/a.jar", &format!("{}/a.jar", server), &[("sha1", &sha1)], body.len() as u64);
+        let options = DownloadOptions { max_retries: 0, ..DownloadOptions::default() };
+
+        let result = download_file(
+            &file, &output_dir, &reqwest::Client::new(), &options,
+            &HostLimiter::new(1, Vec::new()), &DiskWriters::new(1), &NoProgress,
+        ).await;
+        assert!(matches!(result, Err(InstallError::SizeMismatch { expected: 1000, actual: 500 })), "{:?}", result);
+        let file_path = output_dir.join("mods").join("a.jar");
+        assert!(!file_path.exists());
+        assert!(!part_path(&file_path).exists());
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn mirrors_and_allowlist_keep_the_query_string() {
+        let signed = "https://cdn.example.com/data/a.jar?sig=AbC%2B1%2F%3D&expires=1700000000&x=a=b";
+        let mirrors = vec![MirrorRule { from: "cdn.example.com".to_string(), to: "mirror.lan:8080".to_string() }];
+        assert_eq!(
+            apply_mirrors(signed, &mirrors).unwrap(),
+            "https://mirror.lan:8080/data/a.jar?sig=AbC%2B1%2F%3D&expires=1700000000&x=a=b"
+        );
+        let mirrors = vec![MirrorRule { from: "cdn.example.com".to_string(), to: "http://other.example".to_string() }];
+        assert_eq!(apply_mirrors(signed, &mirrors).unwrap(), "http://other.example/data/a.jar?sig=AbC%2B1%2F%3D&expires=1700000000&x=a=b");
+        // Not matching: left exactly as it was
+        assert_eq!(apply_mirrors(signed, &[]).unwrap(), signed);
+
+        // The allowlist only sees the host, whatever the query names
+        let host = host_of("https://cdn.example.com/a.jar?redirect=https://evil.example/").unwrap();
+        assert_eq!(host, "cdn.example.com");
+        assert!(is_host_allowed(&host, &["example.com".to_string()]));
+        assert!(!is_host_allowed(&host, &["evil.example".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn signed_urls_of_the_same_file_are_fetched_once() {
+        use sha1::{Digest, Sha1};
+        let output_dir = temp_dir("signed-dedup");
+        let body = vec![3u8; 2000];
+        let (server, requests) = test_server("200 OK", body.clone(), body.len()).await;
+        let sha1 = format!("{:x}", Sha1::digest(&body));
+        let files = vec![
+            mod_file("mods/a.jar", &format!("{}/a.jar?sig=a&expires=1", server), &[("sha1", &sha1)], body.len() as u64),
+            mod_file("mods/copy/a.jar", &format!("{}/a.jar?sig=b&expires=2", server), &[("sha1", &sha1)], body.len() as u64),
+        ];
+        let results = download_files(&files, &output_dir, &reqwest::Client::new(), &DownloadOptions::default(), &NoProgress).await;
+
+        assert!(matches!(&results[0].0, Ok(Fetched { source: FetchSource::Network, .. })), "{:?}", results[0].0);
+        assert!(matches!(&results[1].0, Ok(Fetched { source: FetchSource::Duplicate, .. })), "{:?}", results[1].0);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(fs::read(output_dir.join("mods/copy/a.jar")).unwrap(), body);
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fastest_mirror_falls_back_past_unusable_mirrors() {
+        use sha1::{Digest, Sha1};
+        let output_dir = temp_dir("fastest-mirror");
+        let body = vec![5u8; 1000];
+        let sha1 = format!("{:x}", Sha1::digest(&body));
+        let (good, _) = test_server("200 OK", body.clone(), body.len()).await;
+        // answers its probe quickly but can't serve the file
+        let (missing, _) = test_server("404 Not Found", Vec::new(), 0).await;
+        // answers its probe with 200 but breaks off the download
+        let (broken, _) = test_server("200 OK", body.clone(), 100).await;
+        let mut file = mod_file("mods/a.jar", &format!("{}/a.jar", missing), &[("sha1", &sha1)], body.len() as u64);
+        file.downloads.push(format!("{}/a.jar", broken));
+        file.downloads.push(format!("{}/a.jar", good));
+        let options = DownloadOptions { fastest_mirror: true, max_retries: 0, ..DownloadOptions::default() };
+
+        let fetched = download_file(
+            &file, &output_dir, &reqwest::Client::new(), &options,
+            &HostLimiter::new(1, Vec::new()), &DiskWriters::new(1), &NoProgress,
+        ).await.unwrap();
+        assert_eq!(fetched.url, Some(format!("{}/a.jar", good)));
+        assert_eq!(fs::read(output_dir.join("mods").join("a.jar")).unwrap(), body);
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_a_hook_fails_on_is_removed() {
+        use sha1::{Digest, Sha1};
+        let output_dir = temp_dir("hook-fails");
+        let body = vec![9u8; 300];
+        let (server, _) = test_server("200 OK", body.clone(), body.len()).await;
+        let sha1 = format!("{:x}", Sha1::digest(&body));
+        let files = vec![
+            mod_file("mods/a.jar", &format!("{}/a.jar", server), &[("sha1", &sha1)], body.len() as u64),
+            mod_file("mods/copy/a.jar", &format!("{}/a.jar", server), &[("sha1", &sha1)], body.len() as u64),
+        ];
+        let hook = FileHook(Arc::new(|file: &ModFile, _: &Path| match file.path.as_str() {
+            "mods/copy/a.jar" => Err("rejected".to_string()),
+            _ => Ok(()),
+        }));
+        let options = DownloadOptions { on_file: Some(hook), ..DownloadOptions::default() };
+        let results = download_files(&files, &output_dir, &reqwest::Client::new(), &options, &NoProgress).await;
+
+        assert!(results[0].0.is_ok(), "{:?}", results[0].0);
+        assert!(matches!(&results[1].0, Err(InstallError::Hook(_))), "{:?}", results[1].0);
+        assert!(output_dir.join("mods/a.jar").is_file());
+        assert!(!output_dir.join("mods/copy/a.jar").exists());
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why installing a single file failed.
+#[derive(Debug)]
+pub enum InstallError {
+    /// The server answered with a non-success status
+    HttpStatus { status: u16, reason: String },
+    /// The server says the file doesn't exist (404) or is gone (410): the pack
+    /// references a file that was taken down
+    Removed { status: u16 },
+    /// The request or body timed out
+    Timeout(reqwest::Error),
+    /// The host name couldn't be resolved
+    Dns(reqwest::Error),
+    /// The server's TLS certificate was rejected
+    Tls(reqwest::Error),
+    /// Connecting failed or the connection broke mid-transfer
+    Connection(reqwest::Error),
+    /// A redirect was refused (too many hops, or a disallowed host)
+    Redirect(String),
+    /// The download URL's host isn't on the allowlist
+    HostNotAllowed(String),
+    /// Offline mode, and the file isn't in the content cache
+    NotCached,
+    /// The download ended early (or ran long) without an error: the bytes received
+    /// don't add up to the size the index declares
+    SizeMismatch { expected: u64, actual: u64 },
+    /// A range request was answered with another range or without a 206, which a
+    /// server or proxy in between may not do next time
+    BadRange(String),
+    /// The downloaded bytes don't match the hash in the index
+    HashMismatch { algorithm: String, expected: String, actual: String },
+    /// The download grew past the size limit (the limit, in bytes)
+    TooLarge(u64),
+    /// The file's path would put it outside the output directory
+    PathTraversal(String),
+    /// Reading or writing a local file failed
+    Io(io::Error),
+    /// Not downloaded (or abandoned halfway) because another file had already failed,
+    /// with [`crate::download::DownloadOptions::fail_fast`]
+    Cancelled,
+    /// The file was installed, but the post-download hook
+    /// ([`crate::download::DownloadOptions::on_file`]) failed on it
+    Hook(String),
+    Other(String),
+}
+
+impl InstallError {
+    /// Whether the failure may go away on its own (a timeout, a dropped connection, a 5xx
+    /// or 429 answer, a short or mismatched range), unlike one a retry can't fix (a 404, a hash mismatch, a disallowed
+    /// host). See [`crate::download::DownloadOptions::retry_only_transient`].
+    pub fn is_transient(&self) -> bool {
+        match self {
+            InstallError::HttpStatus { status, .. } => *status >= 500 || *status == 429,
+            InstallError::Timeout(_) | InstallError::Dns(_) | InstallError::Connection(_) => true,
+            InstallError::SizeMismatch { .. } | InstallError::BadRange(_) => true,
+            _ => false,
+        }
+    }
+
+    /// A stable machine-readable category, used in the JSON outputs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InstallError::HttpStatus { .. } => "http_status",
+            InstallError::Removed { .. } => "removed",
+            InstallError::Timeout(_) => "timeout",
+            InstallError::Dns(_) => "dns",
+            InstallError::Tls(_) => "tls",
+            InstallError::Connection(_) => "connection",
+            InstallError::Redirect(_) => "redirect",
+            InstallError::HostNotAllowed(_) => "host_not_allowed",
+            InstallError::NotCached => "not_cached",
+            InstallError::SizeMismatch { .. } => "size_mismatch",
+            InstallError::BadRange(_) => "bad_range",
+            InstallError::HashMismatch { .. } => "hash_mismatch",
+            InstallError::TooLarge(_) => "too_large",
+            InstallError::PathTraversal(_) => "path_traversal",
+            InstallError::Io(_) => "io",
+            InstallError::Cancelled => "cancelled",
+            InstallError::Hook(_) => "hook",
+            InstallError::Other(_) => "other",
+        }
+    }
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallError::HttpStatus { status, reason } => write!(f, "Failed to download: HTTP {} {}", status, reason),
+            InstallError::Removed { status } => write!(
+                f,
+                "File no longer available (HTTP {}), the pack may be outdated (see --check-updates)",
+                status
+            ),
+            InstallError::Timeout(e) => write!(f, "Timed out: {}", e),
+            InstallError::Dns(e) => write!(f, "Could not resolve host: {}", e),
+            InstallError::Tls(e) => write!(
+                f,
+                "TLS certificate rejected: {} (for a private mirror with a self-signed certificate, see --allow-insecure)",
+                e
+            ),
+            InstallError::Connection(e) => write!(f, "Connection failed: {}", e),
+            InstallError::Redirect(message) => write!(f, "Redirect failed: {}", message),
+            InstallError::HostNotAllowed(host) => write!(f, "Download host '{}' is not allowed", host),
+            InstallError::NotCached => write!(f, "Not in the cache, and offline mode is on"),
+            InstallError::SizeMismatch { expected, actual } => {
+                write!(f, "Size mismatch: expected {} bytes, got {} (the connection may have closed early)", expected, actual)
+            }
+            InstallError::BadRange(message) => write!(f, "{}", message),
+            InstallError::HashMismatch { algorithm, expected, actual } => {
+                write!(f, "{} mismatch: expected {}, got {}", algorithm, expected, actual)
+            }
+            InstallError::TooLarge(limit) => write!(f, "Download exceeds the size limit of {} bytes (--max-file-size)", limit),
+            InstallError::PathTraversal(path) => write!(f, "Path '{}' points outside the output directory, refusing to write it", path),
+            InstallError::Io(e) => write!(f, "{}", e),
+            InstallError::Cancelled => write!(f, "Cancelled after another file failed (--fail-fast, or --per-file-command with --strict)"),
+            InstallError::Hook(message) => write!(f, "Per-file command failed: {}", message),
+            InstallError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for InstallError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InstallError::Timeout(e) | InstallError::Dns(e) | InstallError::Tls(e) | InstallError::Connection(e) => Some(e),
+            InstallError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for InstallError {
+    fn from(e: io::Error) -> Self {
+        InstallError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for InstallError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return InstallError::Timeout(e);
+        }
+        if e.is_redirect() {
+            // The reason (e.g. the disallowed host) is in the source, not the message
+            let reason = e.source().map(|s| s.to_string()).unwrap_or_else(|| e.to_string());
+            return InstallError::Redirect(reason);
+        }
+        let mut source = e.source();
+        while let Some(inner) = source {
+            let message = inner.to_string();
+            if message.contains("dns error") {
+                return InstallError::Dns(e);
+            }
+            if message.contains("certificate") {
+                return InstallError::Tls(e);
+            }
+            source = inner.source();
+        }
+        InstallError::Connection(e)
+    }
+}
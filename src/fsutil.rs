@@ -0,0 +1,842 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use log::warn;
+use rayon::prelude::*;
+use sha2::{Digest, Sha512};
+
+use crate::hash::{sha512_file, to_hex, HASH_BUFFER_SIZE};
+use crate::index::ModrinthIndex;
+use crate::manifest::portable_path;
+use crate::progress::ProgressSink;
+
+pub fn sanitize_filename(name: &str) -> String {
+    let invalid_chars = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+    let mut sanitized = name.to_string();
+    
+    for c in invalid_chars {
+        sanitized = sanitized.replace(c, "_");
+    }
+    
+    sanitized
+}
+
+// Folds a name to plain ASCII (--ascii-names): full-width forms become their ASCII
+// counterparts, common accented Latin letters lose their accents, and anything else
+// that isn't ASCII (emoji, CJK, ...) is dropped, along with the gaps it leaves
+pub fn ascii_name(name: &str) -> String {
+    let mut folded = String::new();
+    for c in name.chars() {
+        match c {
+            _ if c.is_ascii() => folded.push(c),
+            // Full-width ASCII (U+FF01 to U+FF5E) and the ideographic space
+            '\u{FF01}'..='\u{FF5E}' => folded.extend(char::from_u32(c as u32 - 0xFEE0)),
+            '\u{3000}' => folded.push(' '),
+            _ => folded.push_str(latin_ascii(c)),
+        }
+    }
+    folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// The ASCII spelling of an accented Latin letter, or "" for anything else
+fn latin_ascii(c: char) -> &'static str {
+    match c {
+        'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Č' => "C",
+        'ç' | 'ć' | 'č' => "c",
+        'Ð' | 'Ď' | 'Đ' => "D",
+        'ð' | 'ď' | 'đ' => "d",
+        'È'..='Ë' | 'Ē' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è'..='ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'Ğ' => "G",
+        'ğ' => "g",
+        'Ì'..='Ï' | 'Ī' | 'İ' => "I",
+        'ì'..='ï' | 'ī' | 'ı' => "i",
+        'Ł' => "L",
+        'ł' => "l",
+        'Ñ' | 'Ń' | 'Ň' => "N",
+        'ñ' | 'ń' | 'ň' => "n",
+        'Ò'..='Ö' | 'Ø' | 'Ō' | 'Ő' => "O",
+        'ò'..='ö' | 'ø' | 'ō' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ř' => "R",
+        'ř' => "r",
+        'Ś' | 'Ş' | 'Š' => "S",
+        'ś' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'Ť' => "T",
+        'ť' => "t",
+        'Þ' => "Th",
+        'þ' => "th",
+        'Ù'..='Ü' | 'Ū' | 'Ů' | 'Ű' => "U",
+        'ù'..='ü' | 'ū' | 'ů' | 'ű' => "u",
+        'Ý' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => "",
+    }
+}
+
+// Makes `path` absolute and resolves `.` and `..` lexically, without touching the
+// filesystem (the path may not exist yet). An empty path is the current directory.
+pub fn normalize_path(path: &Path) -> io::Result<PathBuf> {
+    let path = if path.as_os_str().is_empty() { Path::new(".") } else { path };
+    let mut normalized = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    Ok(normalized)
+}
+
+// Prefixes an absolute path with `\\?\` so Windows accepts paths longer than MAX_PATH
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+    use std::ffi::OsString;
+
+    let absolute = match std::path::absolute(path) {
+        Ok(absolute) => absolute,
+        Err(_) => return path.to_path_buf(),
+    };
+    match absolute.to_str() {
+        Some(s) if s.starts_with(r"\\?\") => absolute,
+        Some(s) if s.starts_with(r"\\") => PathBuf::from(format!(r"\\?\UNC\{}", &s[2..])),
+        _ => {
+            let mut prefixed = OsString::from(r"\\?\");
+            prefixed.push(absolute.as_os_str());
+            PathBuf::from(prefixed)
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+// Joins a `/`-separated relative path one component at a time. Unlike `join`,
+// this never leaves a `/` in the result, which `\\?\` paths don't allow.
+pub fn join_components(base: &Path, relative: &str) -> PathBuf {
+    let mut joined = base.to_path_buf();
+    for component in Path::new(relative).components() {
+        joined.push(component);
+    }
+    joined
+}
+
+// Whether `relative` names something below a directory without leaving it: at least
+// one normal component, and no `..`, root or drive. Backslashes and drive letters are
+// refused on every platform, since on Windows they'd make `C:\x` or `..\x` escape.
+pub fn is_plain_relative(relative: &str) -> bool {
+    let bytes = relative.as_bytes();
+    if relative.contains('\\') || (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':') {
+        return false;
+    }
+    let components: Vec<Component> = Path::new(relative).components().collect();
+    components.iter().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        && components.iter().any(|c| matches!(c, Component::Normal(_)))
+}
+
+// Like join_components, but only if the result stays inside `root`: the relative path
+// may not contain `..`, a root or a drive, and no existing (symlinked or otherwise
+// aliased) directory on the way may resolve to somewhere outside `root`
+pub fn join_inside(root: &Path, relative: &str) -> Option<PathBuf> {
+    if !is_plain_relative(relative) {
+        return None;
+    }
+
+    let joined = join_components(root, relative);
+    let canonical_root = fs::canonicalize(root).ok()?;
+    let existing = joined.ancestors().find(|ancestor| ancestor.exists())?;
+    fs::canonicalize(existing).ok()?.starts_with(&canonical_root).then_some(joined)
+}
+
+// Creates `dir` if needed and checks a file can be written into it, so a permission
+// problem shows up before anything is downloaded rather than halfway through
+pub fn ensure_writable(dir: &Path) -> Result<(), Box<dyn Error>> {
+    probe_writable(dir).map_err(|e| {
+        format!(
+            "Output directory '{}' is not writable ({}). Choose another location, or run modrinther with permissions that allow writing there",
+            dir.display(), e
+        ).into()
+    })
+}
+
+// The check behind ensure_writable, returning the plain io error
+pub fn probe_writable(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".modrinther-write-test");
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)
+}
+
+// The space left for an unprivileged user on the filesystem holding `dir`
+#[cfg(unix)]
+pub fn available_space(dir: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a valid C string and `stat` is a writable statvfs
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(windows)]
+pub fn available_space(dir: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = dir.as_os_str().encode_wide().chain([0]).collect();
+    let mut available = 0u64;
+    // SAFETY: `path` is NUL-terminated and the other outputs may be null
+    if unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+/// An advisory lock on an output directory, held for the duration of an install so two
+/// modrinther processes can't write into the same directory at once. The OS releases
+/// the lock if the process dies; on drop (or `release_all`) only the lock is released.
+/// The lock file stays: removing it while locked would let a process still waiting on
+/// the old file and one that creates a new file both believe they hold the lock.
+pub struct InstallLock {
+    file: fs::File,
+    path: PathBuf,
+}
+
+// Every lock this process holds, so the Ctrl-C handler can release them before exiting
+static HELD_LOCKS: Mutex<Vec<(PathBuf, fs::File)>> = Mutex::new(Vec::new());
+
+impl InstallLock {
+    pub const FILE_NAME: &'static str = ".modrinther.lock";
+
+    pub fn acquire(output_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = output_dir.join(Self::FILE_NAME);
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(fs::TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                return Err(format!(
+                    "Another modrinther process (pid {}) is already installing into '{}'. Wait for it to finish, or pick another output directory",
+                    holder.trim(), output_dir.display()
+                ).into());
+            }
+            Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        if let Ok(handle) = file.try_clone() {
+            HELD_LOCKS.lock().unwrap_or_else(|e| e.into_inner()).push((path.clone(), handle));
+        }
+        Ok(InstallLock { file, path })
+    }
+
+    // Releases every lock still held, for the Ctrl-C cleanup before the process exits
+    pub fn release_all() {
+        for (_, file) in HELD_LOCKS.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+            let _ = file.unlock();
+        }
+    }
+}
+
+impl Drop for InstallLock {
+    fn drop(&mut self) {
+        HELD_LOCKS.lock().unwrap_or_else(|e| e.into_inner()).retain(|(path, _)| *path != self.path);
+        let _ = self.file.unlock();
+    }
+}
+
+/// A scratch directory (such as an archive's extraction directory) that is deleted
+/// when dropped, unless `keep` is set.
+pub struct ScratchDir {
+    pub path: PathBuf,
+    pub keep: bool,
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(long_path(&self.path));
+        }
+    }
+}
+
+// The in-progress name of a download: `mods/foo.jar` is written as `mods/foo.jar.part`
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+// Deletes every *.part file under `dir` (without following symlinks) and returns how many were removed
+pub fn remove_partial_files(dir: &Path) -> std::io::Result<usize> {
+    let mut removed = 0;
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            removed += remove_partial_files(&path)?;
+        } else if file_type.is_file() && path.extension().is_some_and(|ext| ext == "part") {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+// The total size of every file under `dir`, in bytes (symlinks count as themselves,
+// never followed)
+pub fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            total += dir_size(&entry.path())?;
+        } else {
+            total += entry.metadata()?.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Directory trees nested deeper than this (in overrides, or around an archive's index)
+/// are treated as malformed, unless a different limit is given.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// A directory tree nested deeper than the limit allows, likely a malformed or malicious
+/// archive (or a symlink loop).
+#[derive(Debug)]
+pub struct TreeTooDeep {
+    /// The first directory past the limit
+    pub path: PathBuf,
+    pub max_depth: usize,
+}
+
+impl fmt::Display for TreeTooDeep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Directory tree too deep (over {} levels) at '{}'; pass --max-depth to allow deeper trees",
+            self.max_depth, self.path.display()
+        )
+    }
+}
+
+impl Error for TreeTooDeep {}
+
+#[derive(Debug, Default, Clone)]
+pub struct CopyOptions {
+    /// Fail on symlinks instead of skipping them
+    pub strict: bool,
+    /// Compute the SHA-512 of every copied file
+    pub hash: bool,
+    /// Copy files on several threads, for trees of many small files
+    pub parallel: bool,
+    /// Stop at the first file that can't be copied, instead of reporting it and going on
+    pub fail_fast: bool,
+    /// If not empty, only these top-level directories are copied (e.g. `["config"]`)
+    pub only_dirs: Vec<String>,
+    /// If not empty, only files matching one of these globs (see [`glob_matches`]), or
+    /// inside a directory matching one, are copied
+    pub include: Vec<String>,
+    /// Files and directories matching one of these globs are left out, even if included
+    pub exclude: Vec<String>,
+    /// Only copy files that are missing from the destination or older there than in
+    /// the source (by modification time); the rest are counted as up to date
+    pub newer_only: bool,
+    /// How deep the tree may be nested; None is [`DEFAULT_MAX_DEPTH`]
+    pub max_depth: Option<usize>,
+}
+
+impl CopyOptions {
+    // Whether a file at `relative` passes `include` and `exclude`; directories are
+    // checked against `exclude` on the way down, see collect_copy_jobs
+    pub fn selects(&self, relative: &Path) -> bool {
+        let path = portable_path(relative);
+        let matches = |patterns: &[String]| patterns.iter().any(|pattern| {
+            // A pattern matching a directory takes in everything below it
+            path.match_indices('/').map(|(i, _)| &path[..i]).chain([path.as_str()])
+                .any(|prefix| glob_matches(pattern, prefix))
+        });
+        (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+    }
+}
+
+/// A file written by [`copy_directory_contents`].
+#[derive(Debug, Clone)]
+pub struct CopiedFile {
+    /// Path relative to the destination directory
+    pub relative_path: PathBuf,
+    pub size: u64,
+    /// Hash of the source as it was read, when [`CopyOptions::hash`] is set
+    pub sha512: Option<String>,
+}
+
+/// A file (or directory) [`copy_directory_contents`] couldn't copy.
+#[derive(Debug)]
+pub struct FailedCopy {
+    /// Path relative to the destination directory
+    pub relative_path: PathBuf,
+    pub error: io::Error,
+}
+
+/// The outcome of [`copy_directory_contents`], all lists in traversal order.
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    pub copied: Vec<CopiedFile>,
+    pub failed: Vec<FailedCopy>,
+    /// Files (and whole excluded directories) left out by [`CopyOptions::include`]
+    /// and [`CopyOptions::exclude`], relative to the destination directory
+    pub skipped: Vec<PathBuf>,
+    /// Files not copied because the destination was already as new, with [`CopyOptions::newer_only`]
+    pub up_to_date: usize,
+}
+
+// A file found by collect_copy_jobs, still to be copied
+struct CopyJob {
+    src: PathBuf,
+    dst: PathBuf,
+    relative_path: PathBuf,
+}
+
+// Copies `src` into `dst` recursively. Symlinks are never followed: they are skipped
+// with a warning, or rejected when `strict` is set. The tree is walked (and its
+// directories created) first, then the files are copied. Files that fail are reported
+// in the CopyReport, unless `fail_fast` is set.
+pub fn copy_directory_contents(
+    src: &Path,
+    dst: &Path,
+    options: &CopyOptions,
+    progress: &dyn ProgressSink,
+) -> Result<CopyReport, Box<dyn Error>> {
+    let mut jobs = Vec::new();
+    let mut report = CopyReport::default();
+    collect_copy_jobs(&long_path(src), &long_path(dst), options, &mut jobs, &mut report)?;
+
+    let done = AtomicUsize::new(0);
+    let copy = |job: &CopyJob| {
+        let copied = copy_file(job, options.hash);
+        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+        progress.on_copy_entry(&job.relative_path.to_string_lossy(), done, jobs.len());
+        copied.map_err(|error| FailedCopy { relative_path: job.relative_path.clone(), error })
+    };
+    let results: Vec<Result<CopiedFile, FailedCopy>> = if options.parallel {
+        jobs.par_iter().map(copy).collect()
+    } else if options.fail_fast {
+        // No point copying the rest once one has failed
+        let mut results = Vec::new();
+        for job in &jobs {
+            let result = copy(job);
+            let failed = result.is_err();
+            results.push(result);
+            if failed {
+                break;
+            }
+        }
+        results
+    } else {
+        jobs.iter().map(copy).collect()
+    };
+
+    for result in results {
+        match result {
+            Ok(copied) => report.copied.push(copied),
+            Err(failed) => report.failed.push(failed),
+        }
+    }
+    if options.fail_fast {
+        if let Some(failed) = report.failed.first() {
+            return Err(format!("Failed to copy override '{}': {}", failed.relative_path.display(), failed.error).into());
+        }
+    }
+    Ok(report)
+}
+
+// A directory collect_copy_jobs is partway through
+struct CopyFrame {
+    entries: std::vec::IntoIter<fs::DirEntry>,
+    dst: PathBuf,
+    relative: PathBuf,
+    depth: usize,
+}
+
+// Walks the tree depth-first with an explicit stack, in the order a recursive walk
+// would, so no tree is deep enough to overflow the call stack
+fn collect_copy_jobs(
+    src: &Path,
+    dst: &Path,
+    options: &CopyOptions,
+    jobs: &mut Vec<CopyJob>,
+    report: &mut CopyReport,
+) -> Result<(), Box<dyn Error>> {
+    let mut stack: Vec<CopyFrame> = open_copy_dir(src, dst.to_path_buf(), PathBuf::new(), 0, options, report)?
+        .into_iter()
+        .collect();
+
+    while let Some(frame) = stack.last_mut() {
+        let Some(entry) = frame.entries.next() else {
+            stack.pop();
+            continue;
+        };
+        let depth = frame.depth;
+        if depth == 0 && !options.only_dirs.is_empty() && !options.only_dirs.iter().any(|dir| entry.file_name() == dir.as_str()) {
+            continue;
+        }
+        let src_path = entry.path();
+        let dst_path = frame.dst.join(entry.file_name());
+        let relative_path = frame.relative.join(entry.file_name());
+        let file_type = match fs::symlink_metadata(&src_path) {
+            Ok(meta) => meta.file_type(),
+            Err(error) if !options.fail_fast => {
+                report.failed.push(FailedCopy { relative_path, error });
+                continue;
+            }
+            Err(error) => return Err(format!("Failed to copy override '{}': {}", relative_path.display(), error).into()),
+        };
+
+        if file_type.is_symlink() {
+            if options.strict {
+                return Err(format!("Refusing to copy symlink '{}'", src_path.display()).into());
+            }
+            warn!("skipping symlink '{}'", src_path.display());
+        } else if file_type.is_dir() {
+            // A directory nothing can be selected from isn't even created; for the
+            // others, the globs are checked file by file
+            let path = portable_path(&relative_path);
+            let excluded = options.exclude.iter().any(|pattern| glob_matches(pattern, &path));
+            let includable = options.include.is_empty() || options.include.iter().any(|pattern| glob_may_match_below(pattern, &path));
+            if excluded || !includable {
+                report.skipped.push(relative_path);
+                continue;
+            }
+            stack.extend(open_copy_dir(&src_path, dst_path, relative_path, depth + 1, options, report)?);
+        } else if !options.selects(&relative_path) {
+            report.skipped.push(relative_path);
+        } else if options.newer_only && is_up_to_date(&src_path, &dst_path) {
+            report.up_to_date += 1;
+        } else {
+            jobs.push(CopyJob { src: src_path, dst: dst_path, relative_path });
+        }
+    }
+
+    Ok(())
+}
+
+// Creates `dst` and lists `src` for collect_copy_jobs. None if `src` couldn't be read:
+// an unreadable directory is one failure, like an unreadable file.
+fn open_copy_dir(
+    src: &Path,
+    dst: PathBuf,
+    relative: PathBuf,
+    depth: usize,
+    options: &CopyOptions,
+    report: &mut CopyReport,
+) -> Result<Option<CopyFrame>, Box<dyn Error>> {
+    let max_depth = options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+    if depth > max_depth {
+        return Err(Box::new(TreeTooDeep { path: src.to_path_buf(), max_depth }));
+    }
+    match create_and_list(&dst, src) {
+        Ok(entries) => Ok(Some(CopyFrame { entries: entries.into_iter(), dst, relative, depth })),
+        Err(error) if !options.fail_fast => {
+            report.failed.push(FailedCopy { relative_path: relative, error });
+            Ok(None)
+        }
+        Err(error) => Err(format!("Failed to copy override directory '{}': {}", relative.display(), error).into()),
+    }
+}
+
+// Whether `dst` exists and was modified no earlier than `src`. An unreadable time
+// counts as out of date, so the file is copied.
+fn is_up_to_date(src: &Path, dst: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+    matches!((modified(src), modified(dst)), (Ok(src), Ok(dst)) if src <= dst)
+}
+
+/// Matches a `/`-separated relative path against a glob: `*` matches within one path
+/// component, `?` one character of it, and `**` any number of whole components, so
+/// `config/*.toml`, `kubejs/**` and `**/*.json` all work. A trailing `/` is ignored.
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_components(&pattern, &path)
+}
+
+// Whether `pattern` could match `dir` itself or something below it
+fn glob_may_match_below(pattern: &str, dir: &str) -> bool {
+    let pattern: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+    let dir: Vec<&str> = dir.split('/').collect();
+    match_prefix(&pattern, &dir)
+}
+
+// Like match_components, but a path that runs out first (the rest of the pattern may
+// match below it) or outlasts the pattern (it's inside a matching directory) matches
+fn match_prefix(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, _) | (_, None) => true,
+        (Some((&"**", _)), _) => true,
+        (Some((first, rest)), Some((component, path_rest))) => {
+            let (first, component): (Vec<char>, Vec<char>) = (first.chars().collect(), component.chars().collect());
+            match_component(&first, &component) && match_prefix(rest, path_rest)
+        }
+    }
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((component, path_rest)) => {
+                let (first, component): (Vec<char>, Vec<char>) = (first.chars().collect(), component.chars().collect());
+                match_component(&first, &component) && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_component(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| match_component(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && match_component(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_component(rest, &name[1..]),
+    }
+}
+
+// Creates `dst` if needed and lists the entries of `src`
+fn create_and_list(dst: &Path, src: &Path) -> io::Result<Vec<fs::DirEntry>> {
+    if !dst.exists() {
+        fs::create_dir_all(dst)?;
+    }
+    fs::read_dir(src)?.collect()
+}
+
+fn copy_file(job: &CopyJob, hash: bool) -> io::Result<CopiedFile> {
+    let (size, sha512) = if hash {
+        let (size, sha512) = copy_and_hash(&job.src, &job.dst)?;
+        (size, Some(sha512))
+    } else {
+        (fs::copy(&job.src, &job.dst)?, None)
+    };
+    Ok(CopiedFile { relative_path: job.relative_path.clone(), size, sha512 })
+}
+
+// Copies a file while hashing the bytes read, so the source is only read once
+fn copy_and_hash(src: &Path, dst: &Path) -> io::Result<(u64, String)> {
+    let mut reader = fs::File::open(src)?;
+    let mut writer = fs::File::create(dst)?;
+    let mut hasher = Sha512::new();
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+    let mut size = 0u64;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+        size += read as u64;
+    }
+    fs::set_permissions(dst, reader.metadata()?.permissions())?;
+    Ok((size, to_hex(&hasher.finalize())))
+}
+
+// Re-reads copied files from `dst` and returns the ones whose hash differs from the source
+pub fn verify_copied_files(dst: &Path, copied: &[CopiedFile]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut mismatched = Vec::new();
+    for file in copied {
+        if let Some(expected) = &file.sha512 {
+            if &sha512_file(&dst.join(&file.relative_path))? != expected {
+                mismatched.push(file.relative_path.clone());
+            }
+        }
+    }
+    Ok(mismatched)
+}
+
+// Formats a byte count with decimal units, e.g. "842 MB" or "11.7 MB"
+pub fn format_bytes(bytes: f64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < units.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 || value >= 100.0 {
+        format!("{:.0} {}", value, units[unit])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+// Parses a byte size like "500M", "2GiB" or "1048576". K, M and G (optionally followed
+// by "B" or "iB") are binary units, i.e. 500M is 500 MiB.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let number: u64 = number.parse().map_err(|_| format!("invalid size '{}', expected e.g. 500M", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(format!("invalid size unit in '{}', expected K, M or G", s)),
+    };
+    number.checked_mul(multiplier).ok_or_else(|| format!("size '{}' is too large", s))
+}
+
+// Formats a duration compactly, e.g. "1m12s" or "4.2s"
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m{:02}s", secs / 3600, (secs % 3600) / 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+// Expands environment variables in `s`: `$VAR` and `${VAR}`, and `%VAR%` on Windows.
+// `$$` (and `%%` on Windows) stand for the character itself. Fails if a variable isn't set.
+pub fn expand_env_vars(s: &str) -> Result<String, String> {
+    let lookup = |name: &str| {
+        std::env::var(name).map_err(|_| format!("Environment variable '{}' used in '{}' is not set", name, s))
+    };
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find(|c| c == '$' || (cfg!(windows) && c == '%')) {
+        expanded.push_str(&rest[..pos]);
+        let sigil = rest[pos..].chars().next().unwrap_or('$');
+        let after = &rest[pos + 1..];
+        if after.starts_with(sigil) {
+            expanded.push(sigil);
+            rest = &after[1..];
+        } else if sigil == '%' {
+            match after.find('%') {
+                Some(end) if end > 0 && after[..end].chars().all(is_name_char) => {
+                    expanded.push_str(&lookup(&after[..end])?);
+                    rest = &after[end + 1..];
+                }
+                _ => {
+                    expanded.push('%');
+                    rest = after;
+                }
+            }
+        } else if let Some(braced) = after.strip_prefix('{') {
+            let end = braced.find('}').ok_or_else(|| format!("Unterminated '${{' in '{}'", s))?;
+            expanded.push_str(&lookup(&braced[..end])?);
+            rest = &braced[end + 1..];
+        } else {
+            // Names can't start with a digit; a lone `$` is kept as it is
+            let end = if after.starts_with(|c: char| c.is_ascii_digit()) {
+                0
+            } else {
+                after.find(|c: char| !is_name_char(c)).unwrap_or(after.len())
+            };
+            if end == 0 {
+                expanded.push('$');
+            } else {
+                expanded.push_str(&lookup(&after[..end])?);
+            }
+            rest = &after[end..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// Expands {name}, {mc_version}, {loader} and {version_id} in an output directory
+// template. Each substituted value is sanitized (and folded to ASCII with `ascii`), the
+// template's own separators are kept. `name` replaces the pack's own name for {name},
+// if given; a name with nothing usable left after sanitizing gives way to the versionId.
+pub fn render_output_template(template: &str, index: &ModrinthIndex, name: Option<&str>, ascii: bool) -> PathBuf {
+    let clean = |value: &str| {
+        let value = if ascii { ascii_name(value) } else { value.to_string() };
+        sanitize_filename(&value)
+    };
+    let version_id = clean(&index.version_id);
+    let mut pack_name = clean(name.unwrap_or(&index.name));
+    if pack_name.trim().chars().all(|c| c == '_') && !version_id.trim().is_empty() {
+        warn!("The pack name '{}' leaves nothing usable for a directory name, using its versionId '{}' instead",
+            name.unwrap_or(&index.name), version_id);
+        pack_name = version_id.clone();
+    }
+    let (loader, _) = index.loader();
+    let rendered = template
+        .replace("{name}", &pack_name)
+        .replace("{mc_version}", &clean(index.minecraft_version().unwrap_or("unknown")))
+        .replace("{loader}", &clean(loader))
+        .replace("{version_id}", &version_id);
+    PathBuf::from(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn join_inside_rejects_escaping_paths() {
+        let root = temp_dir("join-inside");
+        for path in ["../x", "a/../../x", "/etc/x", "C:\\x", "..\\x", ""] {
+            assert_eq!(join_inside(&root, path), None, "{:?}", path);
+        }
+        assert_eq!(join_inside(&root, "mods/a.jar"), Some(root.join("mods").join("a.jar")));
+        assert_eq!(join_inside(&root, "./config/x.toml"), Some(root.join("config").join("x.toml")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn install_lock_is_released_but_its_file_kept() {
+        let dir = temp_dir("install-lock");
+        let lock = InstallLock::acquire(&dir).unwrap();
+        assert!(InstallLock::acquire(&dir).is_err());
+        drop(lock);
+        assert!(dir.join(InstallLock::FILE_NAME).is_file());
+        let again = InstallLock::acquire(&dir).unwrap();
+        InstallLock::release_all();
+        InstallLock::acquire(&dir).unwrap();
+        drop(again);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use sha1::Sha1;
+use sha2::{Digest, Sha512};
+
+// Files are hashed in chunks of this size, never read into memory whole
+pub const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Appended to a file's name for its hash file, see write_sidecar
+pub const SIDECAR_EXTENSION: &str = ".sha512";
+
+// Writes `<path>.sha512` next to the file, in the format `sha512sum -c` checks (run
+// from the file's directory)
+pub fn write_sidecar(path: &Path, sha512: &str) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(SIDECAR_EXTENSION);
+    fs::write(sidecar, format!("{}  {}\n", sha512.to_ascii_lowercase(), name))
+}
+
+// Reads a checksum file in `sha512sum`/`sha1sum` format, one `<hash>  <path>` per line
+// (`*<path>` too, as binary mode writes it), into the hashes of each path, keyed like a
+// file's `hashes` in the index. The algorithm is told by the digest's length, so a path
+// may have a line of each. Blank lines and `#` comments are ignored.
+pub fn load_hash_list(path: &Path) -> Result<HashMap<String, HashMap<String, String>>, Box<dyn Error>> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        format!("Failed to read hash list '{}': {}", path.display(), e)
+    })?;
+    let mut hashes: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((digest, file)) = line.split_once(char::is_whitespace) else {
+            return Err(format!("Hash list '{}', line {}: expected '<hash>  <path>'", path.display(), number + 1).into());
+        };
+        let algorithm = [HashAlgorithm::Sha512, HashAlgorithm::Sha1].into_iter()
+            .find(|algorithm| algorithm.is_valid_digest(digest))
+            .ok_or_else(|| format!("Hash list '{}', line {}: '{}' is neither a sha1 nor a sha512 digest", path.display(), number + 1, digest))?;
+        let file = file.trim_start().trim_start_matches('*').replace('\\', "/");
+        let file = file.trim_start_matches("./");
+        hashes.entry(file.to_string()).or_default().insert(algorithm.name().to_string(), digest.to_ascii_lowercase());
+    }
+    Ok(hashes)
+}
+
+/// Hex digests of one file, in the algorithms modrinth.index.json uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digests {
+    pub sha1: String,
+    pub sha512: String,
+}
+
+/// Which of the index's hashes files are verified against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    /// sha512 if the index has it, otherwise sha1
+    #[default]
+    Auto,
+    Sha1,
+    Sha512,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(HashAlgorithm::Auto),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            _ => Err(format!("unknown hash algorithm '{}' (expected auto, sha1 or sha512)", s)),
+        }
+    }
+}
+
+/// What a download whose hash doesn't match the index leads to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashPolicy {
+    /// The file fails (after retries)
+    #[default]
+    Strict,
+    /// The mismatch is logged and the file kept, for packs with stale hashes
+    Warn,
+    /// Hashes aren't checked at all (sizes still are)
+    Off,
+}
+
+impl HashPolicy {
+    // The value --hash-policy takes
+    pub fn name(self) -> &'static str {
+        match self {
+            HashPolicy::Strict => "strict",
+            HashPolicy::Warn => "warn",
+            HashPolicy::Off => "off",
+        }
+    }
+}
+
+impl FromStr for HashPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(HashPolicy::Strict),
+            "warn" => Ok(HashPolicy::Warn),
+            "off" => Ok(HashPolicy::Off),
+            _ => Err(format!("unknown hash policy '{}' (expected strict, warn or off)", s)),
+        }
+    }
+}
+
+impl HashAlgorithm {
+    // The key used in modrinth.index.json `hashes`
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Auto => "auto",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    // Hex digits in a digest of this algorithm
+    fn hex_len(self) -> usize {
+        match self {
+            HashAlgorithm::Auto => 0,
+            HashAlgorithm::Sha1 => 40,
+            HashAlgorithm::Sha512 => 128,
+        }
+    }
+
+    // Whether `digest` is a well-formed hex digest of this algorithm
+    pub fn is_valid_digest(self, digest: &str) -> bool {
+        self != HashAlgorithm::Auto && digest.len() == self.hex_len() && digest.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    // The algorithm actually checked against these hashes: the requested one if
+    // present, otherwise sha512 then sha1. Malformed digests don't count. None if
+    // the index has neither.
+    pub fn resolve(self, expected: &HashMap<String, String>) -> Option<HashAlgorithm> {
+        [self, HashAlgorithm::Sha512, HashAlgorithm::Sha1]
+            .into_iter()
+            .find(|algorithm| expected.get(algorithm.name()).is_some_and(|digest| algorithm.is_valid_digest(digest)))
+    }
+}
+
+// Describes what's wrong with an index `hashes` map: sha1/sha512 values that aren't
+// hex digests of the right length, and algorithms modrinther can't compute
+pub fn hash_problems(hashes: &HashMap<String, String>) -> Vec<String> {
+    let mut keys: Vec<&String> = hashes.keys().collect();
+    keys.sort();
+    keys.into_iter()
+        .filter_map(|key| match key.parse::<HashAlgorithm>() {
+            Ok(algorithm) if algorithm != HashAlgorithm::Auto => (!algorithm.is_valid_digest(&hashes[key])).then(|| {
+                format!("{} hash '{}' is not {} hex digits, ignoring it", key, hashes[key], algorithm.hex_len())
+            }),
+            _ => Some(format!("unknown hash algorithm '{}', ignoring it", key)),
+        })
+        .collect()
+}
+
+impl Digests {
+    // Compares the digest chosen by `algorithm` against an index `hashes` map;
+    // returns a mismatch as (algorithm, expected, actual)
+    pub fn mismatch<'a>(
+        &'a self,
+        expected: &'a HashMap<String, String>,
+        algorithm: HashAlgorithm,
+    ) -> Option<(&'static str, &'a str, &'a str)> {
+        let algorithm = algorithm.resolve(expected)?;
+        let actual = match algorithm {
+            HashAlgorithm::Sha1 => &self.sha1,
+            _ => &self.sha512,
+        };
+        let expected = &expected[algorithm.name()];
+        (!expected.eq_ignore_ascii_case(actual)).then_some((algorithm.name(), expected.as_str(), actual.as_str()))
+    }
+}
+
+/// Feeds data chunk by chunk into every digest at once, so a file only has to be
+/// read (or downloaded) a single time.
+#[derive(Default)]
+pub struct StreamHasher {
+    sha1: Sha1,
+    sha512: Sha512,
+}
+
+impl StreamHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.sha1.update(chunk);
+        self.sha512.update(chunk);
+    }
+
+    pub fn finish(self) -> Digests {
+        Digests {
+            sha1: to_hex(&self.sha1.finalize()),
+            sha512: to_hex(&self.sha512.finalize()),
+        }
+    }
+}
+
+// Streams a reader through every digest using a fixed-size buffer
+pub fn digest_reader(mut reader: impl Read) -> io::Result<Digests> {
+    let mut hasher = StreamHasher::new();
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finish())
+}
+
+pub fn digest_file(path: &Path) -> io::Result<Digests> {
+    digest_reader(fs::File::open(path)?)
+}
+
+// Streams a reader through SHA-512 and returns the hex digest
+pub fn sha512_reader(reader: impl Read) -> io::Result<String> {
+    digest_reader(reader).map(|digests| digests.sha512)
+}
+
+pub fn sha512_file(path: &Path) -> io::Result<String> {
+    sha512_reader(fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hands out at most `limit` bytes per read, so reads straddle the hash buffer
+    struct Trickle<'a> {
+        data: &'a [u8],
+        limit: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.data.len().min(self.limit).min(buf.len());
+            buf[..read].copy_from_slice(&self.data[..read]);
+            self.data = &self.data[read..];
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn streamed_digests_match_one_shot() {
+        let data: Vec<u8> = (0..3 * HASH_BUFFER_SIZE + 17).map(|i| (i * 31 % 251) as u8).collect();
+        let expected = Digests {
+            sha1: to_hex(&Sha1::digest(&data)),
+            sha512: to_hex(&Sha512::digest(&data)),
+        };
+
+        assert_eq!(digest_reader(data.as_slice()).unwrap(), expected);
+        for limit in [1000, 4099, HASH_BUFFER_SIZE - 1, HASH_BUFFER_SIZE + 3] {
+            assert_eq!(digest_reader(Trickle { data: &data, limit }).unwrap(), expected, "reads of {}", limit);
+        }
+        assert_eq!(sha512_reader(Trickle { data: &data, limit: 4099 }).unwrap(), expected.sha512);
+
+        // Chunks that don't line up with the buffer, as downloads arrive
+        let mut hasher = StreamHasher::new();
+        for chunk in data.chunks(HASH_BUFFER_SIZE / 3 + 5) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), expected);
+    }
+}
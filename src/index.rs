@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use serde_json::error::Category;
+
+use crate::archive::DEFAULT_INDEX_NAME;
+use crate::hash::HashAlgorithm;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ModrinthIndex {
+    pub dependencies: HashMap<String, String>,
+    pub files: Vec<ModFile>,
+    #[serde(rename = "formatVersion")]
+    pub format_version: u32,
+    pub game: String,
+    pub name: String,
+    #[serde(rename = "versionId")]
+    pub version_id: String,
+    #[serde(skip)]
+    pub overrides_path: Option<PathBuf>,
+    /// A loader chosen with [`ModrinthIndex::choose_loader`], reported instead of the detected one
+    #[serde(skip)]
+    pub loader_override: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ModFile {
+    pub downloads: Vec<String>,
+    pub env: HashMap<String, String>,
+    #[serde(rename = "fileSize")]
+    pub file_size: u64,
+    pub hashes: HashMap<String, String>,
+    pub path: String,
+}
+
+// Reads and parses a modrinth.index.json from disk, gzip-compressed or not
+pub fn load_index(index_path: &Path) -> Result<ModrinthIndex, Box<dyn Error>> {
+    let bytes = fs::read(index_path).map_err(|e| {
+        format!("Failed to read index file '{}': {}", index_path.display(), e)
+    })?;
+    parse_index(&bytes, index_path)
+}
+
+// Parses an index read from `index_path` (a file, or an archive entry), gzip-compressed or not
+pub fn parse_index(bytes: &[u8], index_path: &Path) -> Result<ModrinthIndex, Box<dyn Error>> {
+    let index_content = if is_gzip(index_path, bytes) {
+        let mut decompressed = String::new();
+        GzDecoder::new(bytes).read_to_string(&mut decompressed).map_err(|e| {
+            format!("Failed to decompress index file '{}': {}", index_path.display(), e)
+        })?;
+        decompressed
+    } else {
+        String::from_utf8(bytes.to_vec()).map_err(|e| {
+            format!("Index file '{}' is not valid UTF-8: {}", index_path.display(), e)
+        })?
+    };
+
+    // Some Windows editors save the index with a UTF-8 BOM, which serde_json rejects
+    let index_content = index_content.strip_prefix('\u{FEFF}').unwrap_or(&index_content);
+
+    let index: ModrinthIndex = serde_json::from_str(index_content)
+        .map_err(|e| parse_error(index_path, index_content, &e))?;
+
+    Ok(index)
+}
+
+// An index that doesn't parse is most often a truncated download or extraction, so that
+// case is told apart from a file that simply isn't an index
+fn parse_error(index_path: &Path, content: &str, e: &serde_json::Error) -> String {
+    if content.trim().is_empty() {
+        return format!(
+            "Index file '{}' is empty ({} bytes read); it may be incomplete, or not a Modrinth index",
+            index_path.display(), content.len()
+        );
+    }
+    match e.classify() {
+        Category::Eof => format!(
+            "Index file '{}' ends abruptly at line {}, column {} ({} bytes read); it was probably not downloaded or extracted completely",
+            index_path.display(), e.line(), e.column(), content.len()
+        ),
+        Category::Syntax => format!("Index file '{}' is not valid JSON: {}", index_path.display(), e),
+        Category::Data | Category::Io => format!("Index file '{}' is not a valid Modrinth index: {}", index_path.display(), e),
+    }
+}
+
+// Writes the index as `modrinth.index.json` into `dir`, so the directory can be installed
+// from again later. Returns the written path.
+pub fn save_index(index: &ModrinthIndex, dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let path = dir.join(DEFAULT_INDEX_NAME);
+    fs::write(&path, serde_json::to_string_pretty(index)?).map_err(|e| {
+        format!("Failed to write index file '{}': {}", path.display(), e)
+    })?;
+    Ok(path)
+}
+
+impl ModFile {
+    // The last component of the path, e.g. "sodium.jar" for "mods/sodium.jar"
+    pub fn file_name(&self) -> String {
+        Path::new(&self.path).file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    }
+
+    // What tells this file's content apart when deduplicating downloads: its sha512 (else
+    // sha1) as `<algorithm>:<hex>`, or its first download URL if it has no usable hash.
+    // Signed URLs that differ only in their query string thus still count as one file.
+    pub fn content_key(&self) -> Option<String> {
+        match HashAlgorithm::Auto.resolve(&self.hashes) {
+            Some(algorithm) => Some(format!("{}:{}", algorithm.name(), self.hashes[algorithm.name()].to_ascii_lowercase())),
+            None => self.downloads.first().cloned(),
+        }
+    }
+
+    // Whether `other` has the same content, going by the strongest hash both list
+    pub fn same_content(&self, other: &ModFile) -> bool {
+        ["sha512", "sha1"].iter()
+            .find_map(|algorithm| Some(self.hashes.get(*algorithm)?.eq_ignore_ascii_case(other.hashes.get(*algorithm)?)))
+            .unwrap_or(false)
+    }
+
+    // Marked unsupported on both client and server: leftover metadata no install ever needs
+    pub fn is_unsupported_everywhere(&self) -> bool {
+        ["client", "server"].iter().all(|side| self.env.get(*side).is_some_and(|v| v == "unsupported"))
+    }
+}
+
+/// How many of a pack's files declare each `env` value for one side, see [`ModrinthIndex::env_tally`].
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SideTally {
+    /// Files without an `env` entry for the side count as required, as launchers treat them
+    pub required: usize,
+    pub optional: usize,
+    pub unsupported: usize,
+    /// Values the Modrinth format doesn't define
+    pub other: usize,
+}
+
+/// The pack's client/server split: [`SideTally`] for each side.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct EnvTally {
+    pub client: SideTally,
+    pub server: SideTally,
+}
+
+/// A kind of pack content, told apart by the top-level directory it installs into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentKind {
+    Mods,
+    ResourcePacks,
+    ShaderPacks,
+    DataPacks,
+    Config,
+}
+
+impl ContentKind {
+    // The directory this kind of content lives in, relative to the game directory
+    pub fn dir(self) -> &'static str {
+        match self {
+            ContentKind::Mods => "mods",
+            ContentKind::ResourcePacks => "resourcepacks",
+            ContentKind::ShaderPacks => "shaderpacks",
+            ContentKind::DataPacks => "datapacks",
+            ContentKind::Config => "config",
+        }
+    }
+
+    // Whether `path` (relative to the game directory) is this kind of content
+    pub fn contains(self, path: &str) -> bool {
+        path.split(['/', '\\']).next() == Some(self.dir())
+    }
+}
+
+impl FromStr for ContentKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mods" => Ok(ContentKind::Mods),
+            "resourcepacks" => Ok(ContentKind::ResourcePacks),
+            "shaderpacks" => Ok(ContentKind::ShaderPacks),
+            "datapacks" => Ok(ContentKind::DataPacks),
+            "config" => Ok(ContentKind::Config),
+            _ => Err(format!("unknown content '{}', expected mods, resourcepacks, shaderpacks, datapacks or config", s)),
+        }
+    }
+}
+
+// Either a .gz extension or the gzip magic bytes mark a compressed index
+fn is_gzip(path: &Path, bytes: &[u8]) -> bool {
+    let has_gz_ext = path.extension()
+        .map(|ext| ext.to_string_lossy().eq_ignore_ascii_case("gz"))
+        .unwrap_or(false);
+    has_gz_ext || bytes.starts_with(&[0x1f, 0x8b])
+}
+
+// A version string we'd be comfortable putting in a URL: non-empty, short and
+// made of the characters real Minecraft and loader versions use
+pub fn is_plausible_version(version: &str) -> bool {
+    !version.is_empty()
+        && version.len() <= 64
+        && version.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '+'))
+}
+
+// The `game` values modrinther can install
+const SUPPORTED_GAMES: [&str; 1] = ["minecraft"];
+
+// Loaders a Minecraft pack can declare in `dependencies`: (display name, key). Most
+// specific first, since Quilt packs often declare fabric-loader too, and NeoForge forge.
+// A new loader only needs a line here.
+const MINECRAFT_LOADERS: &[(&str, &str)] = &[
+    ("NeoForge", "neoforge"),
+    ("Quilt", "quilt-loader"),
+    ("Forge", "forge"),
+    ("Fabric", "fabric-loader"),
+];
+
+// The loaders of a game, or None if modrinther doesn't support it
+fn game_loaders(game: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    match game {
+        "minecraft" => Some(MINECRAFT_LOADERS),
+        _ => None,
+    }
+}
+
+impl ModrinthIndex {
+    // Takes the files nobody can use out of the index and returns them
+    pub fn remove_unsupported(&mut self) -> Vec<ModFile> {
+        let (unsupported, files) = std::mem::take(&mut self.files)
+            .into_iter()
+            .partition(ModFile::is_unsupported_everywhere);
+        self.files = files;
+        unsupported
+    }
+
+    // Counts the files by their `env` value on each side
+    pub fn env_tally(&self) -> EnvTally {
+        let mut tally = EnvTally::default();
+        for file in &self.files {
+            for (side, counts) in [("client", &mut tally.client), ("server", &mut tally.server)] {
+                match file.env.get(side).map(String::as_str) {
+                    None | Some("required") => counts.required += 1,
+                    Some("optional") => counts.optional += 1,
+                    Some("unsupported") => counts.unsupported += 1,
+                    Some(_) => counts.other += 1,
+                }
+            }
+        }
+        tally
+    }
+
+    // Adds hashes known from elsewhere (see load_hash_list) to the files at the same
+    // paths, replacing the index's where they disagree. Returns how many files gained a
+    // hash they lacked, and a description of each disagreement.
+    pub fn supplement_hashes(&mut self, known: &HashMap<String, HashMap<String, String>>) -> (usize, Vec<String>) {
+        let mut supplemented = 0;
+        let mut disagreements = Vec::new();
+        for file in &mut self.files {
+            let Some(hashes) = known.get(&file.path) else {
+                continue;
+            };
+            let mut gained = false;
+            let mut algorithms: Vec<&String> = hashes.keys().collect();
+            algorithms.sort();
+            for algorithm in algorithms {
+                let digest = &hashes[algorithm];
+                match file.hashes.insert(algorithm.clone(), digest.clone()) {
+                    None => gained = true,
+                    Some(listed) if !listed.eq_ignore_ascii_case(digest) => disagreements.push(format!(
+                        "{}: the index lists {} {}, the hash list {}; using the hash list's", file.path, algorithm, listed, digest
+                    )),
+                    Some(_) => {}
+                }
+            }
+            supplemented += usize::from(gained);
+        }
+        (supplemented, disagreements)
+    }
+
+    // Keeps only the files of the given kinds, returning the dropped ones
+    pub fn retain_content(&mut self, kinds: &[ContentKind]) -> Vec<ModFile> {
+        let (kept, dropped) = std::mem::take(&mut self.files).into_iter()
+            .partition(|file| kinds.iter().any(|kind| kind.contains(&file.path)));
+        self.files = kept;
+        dropped
+    }
+
+    pub fn minecraft_version(&self) -> Option<&str> {
+        self.dependencies.get("minecraft").map(String::as_str)
+    }
+
+    // Fails for packs made for a game modrinther doesn't know how to install
+    pub fn check_game(&self) -> Result<(), String> {
+        match game_loaders(&self.game) {
+            Some(_) => Ok(()),
+            None => Err(format!(
+                "Unsupported game '{}': modrinther can only install packs for {}",
+                self.game, SUPPORTED_GAMES.join(", ")
+            )),
+        }
+    }
+
+    // Every known loader the dependencies declare, with its version, most specific first
+    pub fn declared_loaders(&self) -> Vec<(&'static str, &str)> {
+        let loaders = game_loaders(&self.game).unwrap_or_default();
+        loaders.iter()
+            .filter_map(|&(name, key)| self.dependencies.get(key).map(|version| (name, version.as_str())))
+            .collect()
+    }
+
+    // Dependency keys that are neither the game version nor a known loader (a new
+    // loader, say), sorted, so they can be logged and reported
+    pub fn unknown_dependencies(&self) -> Vec<(&str, &str)> {
+        let loaders = game_loaders(&self.game).unwrap_or_default();
+        let mut unknown: Vec<(&str, &str)> = self.dependencies.iter()
+            .filter(|&(key, _)| key != "minecraft" && !loaders.iter().any(|&(_, loader_key)| loader_key == key))
+            .map(|(key, version)| (key.as_str(), version.as_str()))
+            .collect();
+        unknown.sort();
+        unknown
+    }
+
+    // Detect loader type and version from the dependencies: the chosen loader if any,
+    // else the most specific one declared
+    pub fn loader(&self) -> (&'static str, Option<&str>) {
+        self.declared_loaders().into_iter()
+            .find(|&(name, _)| self.loader_override.is_none_or(|chosen| chosen == name))
+            .map(|(name, version)| (name, Some(version)))
+            .unwrap_or(("Unknown", None))
+    }
+
+    // Makes loader() report `name` (case-insensitive, e.g. "fabric"), which must be
+    // one of the loaders the pack declares
+    pub fn choose_loader(&mut self, name: &str) -> Result<(), String> {
+        let declared = self.declared_loaders();
+        match declared.iter().find(|(loader, _)| loader.eq_ignore_ascii_case(name)) {
+            Some(&(loader, _)) => {
+                self.loader_override = Some(loader);
+                Ok(())
+            }
+            None => {
+                let names: Vec<&str> = declared.iter().map(|(loader, _)| *loader).collect();
+                Err(format!(
+                    "The pack doesn't declare the '{}' loader (it declares: {})",
+                    name, if names.is_empty() { "none".to_string() } else { names.join(", ") }
+                ))
+            }
+        }
+    }
+
+    // Describes each Minecraft/loader version in the dependencies that looks malformed
+    pub fn version_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(version) = self.minecraft_version() {
+            if !is_plausible_version(version) {
+                problems.push(format!("Minecraft version '{}' looks malformed", version));
+            }
+        }
+        if let (loader, Some(version)) = self.loader() {
+            if !is_plausible_version(version) {
+                problems.push(format!("{} loader version '{}' looks malformed", loader, version));
+            }
+        }
+        problems
+    }
+
+    // Heuristic signs that the loader version doesn't belong to the pack's Minecraft
+    // version, as in a hand-edited pack: a Forge/NeoForge version embedding another
+    // Minecraft version ("1.19.2-43.2.0"), or a NeoForge version numbered for another
+    // one (20.4.x is for 1.20.4). Fabric and Quilt versions don't encode it.
+    pub fn compatibility_problems(&self) -> Vec<String> {
+        let (Some(minecraft), (loader, Some(version))) = (self.minecraft_version(), self.loader()) else {
+            return Vec::new();
+        };
+        if !matches!(loader, "Forge" | "NeoForge") {
+            return Vec::new();
+        }
+        let expected = match version.split_once('-') {
+            Some((prefix, _)) if prefix.starts_with("1.") => Some(prefix.to_string()),
+            _ if loader == "NeoForge" => neoforge_minecraft_version(version),
+            _ => None,
+        };
+        match expected {
+            Some(expected) if expected != minecraft => vec![format!(
+                "{} {} is for Minecraft {}, but the pack declares Minecraft {}; the pack may not launch",
+                loader, version, expected, minecraft
+            )],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// The Minecraft version a NeoForge version is numbered for: 20.4.x is for 1.20.4 and
+// 21.0.x for 1.21. Versions outside that scheme (NeoForge 47.x for 1.20.1, or the
+// year-based versions) give None.
+fn neoforge_minecraft_version(version: &str) -> Option<String> {
+    let mut parts = version.split(['.', '-']);
+    let major: u32 = parts.next()?.parse().ok()?;
+    let minor: u32 = parts.next()?.parse().ok()?;
+    match (major, minor) {
+        (major, _) if !(20..26).contains(&major) => None,
+        (major, 0) => Some(format!("1.{}", major)),
+        (major, minor) => Some(format!("1.{}.{}", major, minor)),
+    }
+}
+
+// The suffix launchers toggle mods off with: `mods/x.jar.disabled` is `mods/x.jar`, turned off
+pub const DISABLED_SUFFIX: &str = ".disabled";
+
+// Files shipped both enabled and as a `.disabled` copy with the same content, so the
+// user can toggle them, as (enabled, disabled) positions in `files`, in `files` order
+pub fn disabled_variants(files: &[ModFile]) -> Vec<(usize, usize)> {
+    let positions: HashMap<&str, usize> = files.iter().enumerate().map(|(i, file)| (file.path.as_str(), i)).collect();
+    files.iter().enumerate()
+        .filter_map(|(i, file)| {
+            let enabled = *positions.get(file.path.strip_suffix(DISABLED_SUFFIX)?)?;
+            files[enabled].same_content(file).then_some((enabled, i))
+        })
+        .collect()
+}
+
+// Pairs of paths that differ only in case: one file on a case-insensitive filesystem
+// (Windows, macOS), where the second overwrites the first, but two files on Linux.
+// Identical paths aren't reported, they overwrite each other everywhere.
+pub fn case_collisions<'a>(paths: impl IntoIterator<Item = &'a str>) -> Vec<(&'a str, &'a str)> {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    let mut collisions = Vec::new();
+    for path in paths {
+        if let Some(previous) = seen.insert(path.to_lowercase(), path) {
+            if previous != path {
+                collisions.push((previous, path));
+            }
+        }
+    }
+    collisions
+}
+
+// Rewrites every file path to `mods/<file name>`, for launchers that expect all jars in
+// one folder. Fails if two files would end up with the same name.
+pub fn flatten_paths(files: &mut [ModFile]) -> Result<(), Box<dyn Error>> {
+    let mut seen: HashMap<String, String> = HashMap::new();
+    let mut collisions = Vec::new();
+    for file in files.iter() {
+        let name = file.file_name();
+        if let Some(previous) = seen.insert(name.clone(), file.path.clone()) {
+            collisions.push(format!("'{}' and '{}'", previous, file.path));
+        }
+    }
+    if !collisions.is_empty() {
+        return Err(format!("Cannot flatten, files share a name: {}", collisions.join(", ")).into());
+    }
+
+    for file in files.iter_mut() {
+        file.path = format!("mods/{}", file.file_name());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::mod_file;
+
+    #[test]
+    fn content_key_ignores_signed_query_strings() {
+        let sha1 = "a".repeat(40);
+        let sha512 = "b".repeat(128);
+        let hashes = [("sha1", sha1.as_str()), ("sha512", sha512.as_str())];
+        let a = mod_file("mods/a.jar", "https://cdn.example.com/a.jar?sig=a", &hashes, 1);
+        let b = mod_file("mods/a.jar", "https://cdn.example.com/a.jar?sig=b", &hashes, 1);
+        assert_eq!(a.content_key(), b.content_key());
+        assert_eq!(a.content_key(), Some(format!("sha512:{}", sha512)));
+
+        // Only sha1, in another case: still one key
+        let upper = sha1.to_ascii_uppercase();
+        let c = mod_file("mods/a.jar", "https://cdn.example.com/a.jar?sig=c", &[("sha1", sha1.as_str())], 1);
+        let d = mod_file("mods/a.jar", "https://mirror.example/a.jar?sig=d", &[("sha1", upper.as_str())], 1);
+        assert_eq!(c.content_key(), d.content_key());
+
+        // Without a usable hash, only an identical URL is the same file
+        let e = mod_file("mods/a.jar", "https://cdn.example.com/a.jar?sig=e", &[], 1);
+        let f = mod_file("mods/a.jar", "https://cdn.example.com/a.jar?sig=f", &[], 1);
+        assert_ne!(e.content_key(), f.content_key());
+        assert_eq!(e.content_key(), mod_file("mods/a.jar", "https://cdn.example.com/a.jar?sig=e", &[], 1).content_key());
+    }
+
+    const MINIMAL_INDEX: &[u8] = br#"{"formatVersion": 1, "game": "minecraft", "versionId": "1.0.0", "name": "Pack",
+        "files": [{"path": "mods/a.jar", "hashes": {"sha1": "aa"}, "env": {"client": "required"},
+        "downloads": ["https://cdn.example.com/a.jar"], "fileSize": 1}],
+        "dependencies": {"minecraft": "1.20.1", "fabric-loader": "0.15.0"}}"#;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn parses_an_index_with_a_bom() {
+        let expected = serde_json::to_value(parse_index(MINIMAL_INDEX, Path::new("modrinth.index.json")).unwrap()).unwrap();
+        let with_bom = [b"\xEF\xBB\xBF".as_slice(), MINIMAL_INDEX].concat();
+
+        let index = parse_index(&with_bom, Path::new("modrinth.index.json")).unwrap();
+        assert_eq!(serde_json::to_value(index).unwrap(), expected);
+        // The BOM is inside the compressed data, stripped once decompressed
+        let index = parse_index(&gzip(&with_bom), Path::new("modrinth.index.json.gz")).unwrap();
+        assert_eq!(serde_json::to_value(index).unwrap(), expected);
+        // Told apart by the gzip magic bytes, not the name
+        let index = parse_index(&gzip(&with_bom), Path::new("modrinth.index.json")).unwrap();
+        assert_eq!(serde_json::to_value(index).unwrap(), expected);
+    }
+}
@@ -0,0 +1,877 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use time::OffsetDateTime;
+
+use crate::checkpoint::{Checkpoint, RunRecord};
+use crate::download::{build_client, download_files, is_installed, DownloadOptions, DownloadStats, FetchSource, Fetched};
+use crate::error::InstallError;
+use crate::fsutil::{copy_directory_contents, dir_size, ensure_writable, join_components, long_path, remove_partial_files, verify_copied_files, CopiedFile, CopyOptions, FailedCopy, InstallLock};
+use crate::hash::{sha512_reader, write_sidecar, HashAlgorithm, HashPolicy};
+use crate::index::{case_collisions, flatten_paths, save_index, ContentKind, ModFile, ModrinthIndex};
+use crate::manifest::{FileSource, Manifest, ManifestHashes};
+use crate::policy::{modrinth_project_id, Decision, ProjectPolicy};
+use crate::progress::{NoProgress, ProgressSink};
+use crate::verify::{list_files, verify_files, VerifyFailure};
+
+/// What to do when the output directory already exists and isn't empty.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExistingOutput {
+    /// Install over the existing content
+    #[default]
+    Merge,
+    /// Move it aside to `<dir>.bak.<timestamp>` and install into a fresh directory
+    Rename,
+    /// Refuse to install
+    Fail,
+}
+
+/// What to do with a download whose path already holds a different file (by size or
+/// hash), when installing over an existing directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Replace it with the pack's version
+    #[default]
+    Overwrite,
+    /// Leave it as it is and skip the download
+    Keep,
+}
+
+impl ConflictPolicy {
+    // The value --conflict-policy takes
+    pub fn name(self) -> &'static str {
+        match self {
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::Keep => "keep",
+        }
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "keep" => Ok(ConflictPolicy::Keep),
+            _ => Err(format!("unknown conflict policy '{}' (expected overwrite or keep)", s)),
+        }
+    }
+}
+
+/// An answer to a [`ConflictPrompt`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictChoice {
+    Keep,
+    Overwrite,
+    /// Overwrite this and every further conflict without asking
+    OverwriteAll,
+    /// Skip downloading this and every further conflicting file without asking, keeping
+    /// what's there
+    SkipAll,
+}
+
+/// Asks what to do about one conflicting path (as the index lists it); None if there
+/// is no one to ask after all (e.g. stdin was closed), leaving the rest to the policy.
+pub type ConflictPrompt = Arc<dyn Fn(&str) -> Option<ConflictChoice> + Send + Sync>;
+
+/// What to install a pack into, and how.
+#[derive(Clone)]
+pub struct InstallOptions {
+    /// The pack is installed directly into this directory
+    pub output_dir: PathBuf,
+    pub download: DownloadOptions,
+    pub existing: ExistingOutput,
+    /// What a merge does with downloads that would replace a different existing file
+    pub conflicts: ConflictPolicy,
+    /// Asks about each such conflict instead, when set
+    pub conflict_prompt: Option<ConflictPrompt>,
+    /// Reuse an existing client (and its connection pool); built from `download` if unset
+    pub client: Option<reqwest::Client>,
+    /// Put every download directly into `mods/` (see [`flatten_paths`])
+    pub flatten: bool,
+    /// Install the downloads and overrides under this `/`-separated subdirectory of
+    /// `output_dir` (e.g. `.minecraft`), for launchers that expect them there.
+    /// modrinther's own files (summaries, index, manifest) stay in `output_dir`.
+    pub root_prefix: Option<String>,
+    /// Treat questionable pack contents (malformed versions, symlinked overrides) as errors
+    pub strict: bool,
+    /// Delete leftover `.part` files in the output directory first
+    pub clean_partials: bool,
+    /// Re-hash the copied overrides and fail if any differs from its source
+    pub verify_overrides: bool,
+    /// Fail if the pack has an overrides directory without any files in it
+    pub require_overrides: bool,
+    /// Abort on the first override that can't be copied, instead of reporting it and going on
+    pub strict_overrides: bool,
+    /// Once downloaded, re-read every installed file and check its size and hash again
+    pub verify_after: bool,
+    /// Files [`InstallOptions::verify_after`] hashes at once; None for one per CPU core
+    pub verify_threads: Option<usize>,
+    /// Only copy the overrides, downloading nothing (e.g. to re-sync configs)
+    pub overrides_only: bool,
+    /// Copy the overrides on several threads (see [`CopyOptions::parallel`])
+    pub parallel_overrides: bool,
+    /// If not empty, only install these kinds of content, downloads and overrides alike
+    pub only: Vec<ContentKind>,
+    /// Hashes from a checksum file (see [`load_hash_list`](crate::hash::load_hash_list)),
+    /// by path as the index lists it, added to the index's (and used instead on disagreement)
+    pub known_hashes: HashMap<String, HashMap<String, String>>,
+    /// Which Modrinth projects may be installed; files of others are skipped or fail the install
+    pub projects: ProjectPolicy,
+    /// Only copy the overrides matching one of these globs (see [`CopyOptions::include`])
+    pub overrides_include: Vec<String>,
+    /// Leave out the overrides matching one of these globs (see [`CopyOptions::exclude`])
+    pub overrides_exclude: Vec<String>,
+    /// Only copy the overrides newer than the installed ones (see [`CopyOptions::newer_only`])
+    pub overrides_newer_only: bool,
+    /// How deep the overrides may be nested (see [`CopyOptions::max_depth`])
+    pub max_depth: Option<usize>,
+    /// The Minecraft version the pack should be for; a different one is warned about
+    /// (or fails the install with `strict`) before anything is installed
+    pub expect_minecraft: Option<String>,
+    /// Install even if the output directory already holds the whole pack, instead of
+    /// stopping early (see [`InstallReport::already_installed`])
+    pub force: bool,
+    /// Write `modpack_manifest.json` listing every installed file
+    pub write_manifest: bool,
+    /// Which hashes the manifest records per file
+    pub manifest_hashes: ManifestHashes,
+    /// Write a `<file>.sha512` next to every installed file, for `sha512sum -c`
+    pub write_sidecars: bool,
+    pub progress: Arc<dyn ProgressSink>,
+    /// Written to the output directory before anything is installed, see [`RunRecord`]
+    pub run_record: Option<RunRecord>,
+}
+
+impl InstallOptions {
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        InstallOptions {
+            output_dir: output_dir.into(),
+            download: DownloadOptions::default(),
+            existing: ExistingOutput::default(),
+            conflicts: ConflictPolicy::default(),
+            conflict_prompt: None,
+            client: None,
+            flatten: false,
+            root_prefix: None,
+            strict: false,
+            clean_partials: false,
+            verify_overrides: false,
+            require_overrides: false,
+            strict_overrides: false,
+            verify_after: false,
+            verify_threads: None,
+            overrides_only: false,
+            parallel_overrides: false,
+            only: Vec::new(),
+            known_hashes: HashMap::new(),
+            projects: ProjectPolicy::default(),
+            overrides_include: Vec::new(),
+            overrides_exclude: Vec::new(),
+            overrides_newer_only: false,
+            max_depth: None,
+            expect_minecraft: None,
+            force: false,
+            write_manifest: false,
+            manifest_hashes: ManifestHashes::default(),
+            write_sidecars: false,
+            progress: Arc::new(NoProgress),
+            run_record: None,
+        }
+    }
+}
+
+/// A file that was installed.
+#[derive(Debug)]
+pub struct InstalledFile {
+    pub file: ModFile,
+    pub fetched: Fetched,
+    /// How long the file took to install (download, cache copy, ...)
+    pub duration: Duration,
+}
+
+/// A file that couldn't be installed.
+#[derive(Debug)]
+pub struct FailedFile {
+    pub file: ModFile,
+    pub error: InstallError,
+    /// How long was spent on the file before giving up, retries included
+    pub duration: Duration,
+}
+
+/// Why a file was deliberately not installed, see [`SkippedFile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SkipReason {
+    /// Not one of the kinds of content [`InstallOptions::only`] asks for
+    NotSelected,
+    /// Unsupported on both client and server
+    Unsupported,
+    /// Left out by [`InstallOptions::projects`]
+    NotAllowed,
+    /// Declared larger than [`DownloadOptions::max_file_size`]
+    TooLarge,
+    /// A different file was already at its path and kept (see [`ConflictPolicy::Keep`])
+    KeptExisting,
+}
+
+impl SkipReason {
+    /// A stable name, used in the summaries and JSON outputs.
+    pub fn name(self) -> &'static str {
+        match self {
+            SkipReason::NotSelected => "not selected",
+            SkipReason::Unsupported => "unsupported",
+            SkipReason::NotAllowed => "not allowed",
+            SkipReason::TooLarge => "too large",
+            SkipReason::KeptExisting => "kept existing",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A file that was deliberately not installed.
+#[derive(Debug)]
+pub struct SkippedFile {
+    pub file: ModFile,
+    pub reason: SkipReason,
+}
+
+/// The outcome of [`install`]. The file lists are in index order.
+#[derive(Debug)]
+pub struct InstallReport {
+    pub output_dir: PathBuf,
+    pub succeeded: Vec<InstalledFile>,
+    pub failed: Vec<FailedFile>,
+    pub skipped: Vec<SkippedFile>,
+    pub overrides: Vec<CopiedFile>,
+    /// Overrides that couldn't be copied (the install went on without them)
+    pub override_failures: Vec<FailedCopy>,
+    /// Overrides left out by [`InstallOptions::overrides_include`] and [`InstallOptions::overrides_exclude`]
+    pub overrides_skipped: Vec<PathBuf>,
+    /// Installed files that no longer matched the index when re-read, with [`InstallOptions::verify_after`]
+    pub verify_failures: Vec<VerifyFailure>,
+    pub stats: DownloadStats,
+    /// How long the downloads took
+    pub elapsed: Duration,
+    /// What the output directory takes up once installed: downloads, overrides and
+    /// anything else in it. None if it couldn't be measured.
+    pub disk_usage: Option<u64>,
+    /// The output directory already held every file (with a matching hash) and every
+    /// override, so nothing was copied or downloaded. `succeeded` lists the files as
+    /// [`FetchSource::Resumed`].
+    pub already_installed: bool,
+}
+
+impl InstallReport {
+    // Every file that was meant to be downloaded, whether or not it succeeded
+    pub fn total_files(&self) -> usize {
+        self.succeeded.len() + self.failed.len()
+    }
+}
+
+// Installs a pack into `opts.output_dir`: copies its overrides and downloads every file.
+// Per-file failures are reported in the InstallReport; an error is only returned when
+// the install can't proceed at all (unsupported game, unwritable output, ...).
+pub async fn install(index: &ModrinthIndex, opts: &InstallOptions) -> Result<InstallReport, Box<dyn Error>> {
+    index.check_game()?;
+    if let Some(expected) = &opts.expect_minecraft {
+        let problem = match index.minecraft_version() {
+            Some(version) if version == expected => None,
+            Some(version) => Some(format!("The pack is for Minecraft {}, not {} (--expect-mc-version)", version, expected)),
+            None => Some(format!("The pack doesn't declare a Minecraft version, expected {} (--expect-mc-version)", expected)),
+        };
+        if let Some(problem) = problem {
+            if opts.strict {
+                return Err(problem.into());
+            }
+            warn!("{}", problem);
+        }
+    }
+    let original = index;
+    let output_dir = &opts.output_dir;
+    let (mut index, mut skipped) = select_files(original, opts)?;
+    let content_dir = match &opts.root_prefix {
+        Some(prefix) => join_components(output_dir, prefix),
+        None => output_dir.clone(),
+    };
+
+    // Paths that collide only on case-insensitive filesystems, checked on every platform
+    // (and before anything is written) so a pack that only installs right on Linux is
+    // noticed there too
+    let mut override_paths = Vec::new();
+    if let Some(overrides_path) = &index.overrides_path {
+        if let Err(e) = list_files(overrides_path, Path::new(""), &mut override_paths) {
+            debug!("Couldn't list the overrides to check for case collisions: {}", e);
+        }
+    }
+    // The downloads' paths already carry --root-prefix, the overrides' don't yet
+    if let Some(prefix) = &opts.root_prefix {
+        for path in &mut override_paths {
+            *path = format!("{}/{}", prefix, path);
+        }
+    }
+    let paths = index.files.iter().map(|file| file.path.as_str()).chain(override_paths.iter().map(String::as_str));
+    for (first, second) in case_collisions(paths) {
+        let problem = format!("'{}' and '{}' differ only in case, one overwrites the other on Windows and macOS", first, second);
+        if opts.strict {
+            return Err(problem.into());
+        }
+        warn!("{}", problem);
+    }
+
+    // A rerun over a complete install has nothing to do; moving it aside or refusing to
+    // install over it is still up to --existing
+    if !opts.force && !opts.overrides_only && opts.existing == ExistingOutput::Merge
+        && is_fully_installed(&index, output_dir, &content_dir, opts.download.hash, opts.download.hash_policy)
+    {
+        info!("Pack already fully installed and verified: {}", output_dir.display());
+        let succeeded = index.files.into_iter()
+            .map(|file| InstalledFile {
+                fetched: Fetched { bytes: file.file_size, source: FetchSource::Resumed, url: None },
+                file,
+                duration: Duration::ZERO,
+            })
+            .collect();
+        return Ok(InstallReport {
+            output_dir: output_dir.clone(),
+            succeeded,
+            failed: Vec::new(),
+            skipped,
+            overrides: Vec::new(),
+            override_failures: Vec::new(),
+            overrides_skipped: Vec::new(),
+            verify_failures: Vec::new(),
+            stats: DownloadStats::default(),
+            elapsed: Duration::ZERO,
+            disk_usage: disk_usage(output_dir),
+            already_installed: true,
+        });
+    }
+
+    set_aside_existing(output_dir, opts.existing)?;
+
+    // Create output directory if it doesn't exist, and fail early if we can't write there
+    ensure_writable(output_dir)?;
+    let _lock = InstallLock::acquire(output_dir)?;
+
+    if opts.clean_partials {
+        let removed = remove_partial_files(output_dir)?;
+        info!("Removed {} leftover .part file(s) from a previous run", removed);
+    }
+
+    // Keep the index as the pack shipped it (not as filtered above), so the output
+    // directory records where it came from and can be installed from again
+    if !opts.overrides_only {
+        let saved = save_index(original, output_dir)?;
+        debug!("Saved the pack's index to {}", saved.display());
+    }
+
+    // Pick up where an interrupted install of the same pack left off
+    let checkpoint = Arc::new(Checkpoint::open(output_dir, &index));
+    if checkpoint.completed_count() > 0 {
+        info!("Resuming: {} file(s) were completed by an earlier run", checkpoint.completed_count());
+    }
+
+    // What this run is set up with, compared against the interrupted one it resumes
+    if let Some(record) = &opts.run_record {
+        if let (true, Some(earlier)) = (checkpoint.completed_count() > 0, RunRecord::load(output_dir)) {
+            for difference in record.differences(&earlier) {
+                warn!("Resuming with a different setting than the earlier run: {}", difference);
+            }
+        }
+        if let Err(e) = record.save(output_dir) {
+            debug!("Failed to write {}: {}", RunRecord::FILE_NAME, e);
+        }
+    }
+
+    info!("Installing modpack: {}", index.name);
+    info!("Output directory: {}", output_dir.display());
+    info!("Game: {}", index.game);
+    info!("Minecraft version: {}", index.minecraft_version().unwrap_or("unknown"));
+    let (loader_type, loader_version) = index.loader();
+    info!("Loader: {} {}", loader_type, loader_version.unwrap_or("unknown"));
+    for (key, version) in index.unknown_dependencies() {
+        debug!("Unrecognized dependency '{}' (version {}), possibly a loader modrinther doesn't know yet", key, version);
+    }
+    let declared = index.declared_loaders();
+    if declared.len() > 1 && index.loader_override.is_none() {
+        let names: Vec<&str> = declared.iter().map(|(name, _)| *name).collect();
+        info!("The pack declares several loaders ({}), using {}; pass --loader to choose", names.join(", "), loader_type);
+    }
+
+    for problem in index.version_problems() {
+        if opts.strict {
+            return Err(problem.into());
+        }
+        warn!("{}", problem);
+    }
+    if opts.existing == ExistingOutput::Merge && !opts.overrides_only {
+        skipped.extend(resolve_conflicts(&mut index, output_dir, &checkpoint, opts));
+    }
+    // What was left out above (--only, unsupported, too large, kept) makes this differ from
+    // the count in the index, which is what users see on the pack's page
+    if index.files.len() == original.files.len() || opts.overrides_only {
+        info!("Total files to download: {}", index.files.len());
+    } else {
+        info!("Total files to download: {} ({} in the pack, {} left out)",
+            index.files.len(), original.files.len(), original.files.len() - index.files.len());
+    }
+
+    // Offline, check up front that every file can be installed before copying anything
+    if let (true, Some(cache)) = (opts.download.offline, &opts.download.cache) {
+        let missing: Vec<&str> = index.files.iter()
+            .filter(|file| !checkpoint.is_complete(file) && !cache.contains(file))
+            .map(|file| file.path.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Offline install impossible, {} file(s) are not in the cache ({}): {}",
+                missing.len(), cache.dir().display(), missing.join(", ")
+            ).into());
+        }
+    }
+
+    // Copy overrides if they exist
+    let mut overrides = Vec::new();
+    let mut override_failures = Vec::new();
+    let mut overrides_skipped = Vec::new();
+    if let Some(overrides_path) = &index.overrides_path {
+        info!("Copying overrides...");
+        let copy_options = CopyOptions {
+            strict: opts.strict,
+            hash: opts.write_manifest || opts.verify_overrides || opts.write_sidecars,
+            parallel: opts.parallel_overrides,
+            fail_fast: opts.strict_overrides,
+            only_dirs: opts.only.iter().map(|kind| kind.dir().to_string()).collect(),
+            include: opts.overrides_include.clone(),
+            exclude: opts.overrides_exclude.clone(),
+            newer_only: opts.overrides_newer_only,
+            max_depth: opts.max_depth,
+        };
+        let mut copy = copy_directory_contents(overrides_path, &content_dir, &copy_options, opts.progress.as_ref())?;
+        if let Some(prefix) = &opts.root_prefix {
+            copy.copied.iter_mut().for_each(|copied| copied.relative_path = Path::new(prefix).join(&copied.relative_path));
+            copy.failed.iter_mut().for_each(|failed| failed.relative_path = Path::new(prefix).join(&failed.relative_path));
+            copy.skipped.iter_mut().for_each(|skipped| *skipped = Path::new(prefix).join(&*skipped));
+        }
+        if opts.overrides_newer_only {
+            info!("Overrides: {} file(s) copied, {} already up to date", copy.copied.len(), copy.up_to_date);
+        }
+        overrides = copy.copied;
+        override_failures = copy.failed;
+        overrides_skipped = copy.skipped;
+        for failed in &override_failures {
+            warn!("Failed to copy override '{}': {}", failed.relative_path.display(), failed.error);
+        }
+        if !opts.overrides_include.is_empty() || !opts.overrides_exclude.is_empty() {
+            info!("Overrides filter: {} file(s) copied, {} path(s) left out", overrides.len(), overrides_skipped.len());
+            for copied in &overrides {
+                debug!("Override included: {}", copied.relative_path.display());
+            }
+            for skipped in &overrides_skipped {
+                debug!("Override left out: {}", skipped.display());
+            }
+        }
+
+        // An overrides directory with nothing in it usually means a corrupt or truncated
+        // download (with --only or an overrides filter, the selection may simply not
+        // include any overrides, and with --overrides-newer-only they may all be up to date)
+        if overrides.is_empty() && override_failures.is_empty() && overrides_skipped.is_empty() && copy.up_to_date == 0 && opts.only.is_empty() {
+            let problem = format!("The pack's overrides directory is empty ({}), the pack may be corrupt or incomplete", overrides_path.display());
+            if opts.require_overrides {
+                return Err(problem.into());
+            }
+            warn!("{}", problem);
+        }
+
+        if opts.verify_overrides {
+            let mismatched = verify_copied_files(output_dir, &overrides)?;
+            if !mismatched.is_empty() {
+                let paths: Vec<String> = mismatched.iter().map(|p| p.display().to_string()).collect();
+                return Err(format!("Overrides copy is not faithful, hash mismatch for: {}", paths.join(", ")).into());
+            }
+            info!("Verified {} override files", overrides.len());
+        }
+    }
+
+    if opts.overrides_only {
+        info!("Copied {} override file(s), skipping the downloads", overrides.len());
+        return Ok(InstallReport {
+            output_dir: output_dir.clone(),
+            succeeded: Vec::new(),
+            failed: Vec::new(),
+            skipped: Vec::new(),
+            overrides,
+            override_failures,
+            overrides_skipped,
+            verify_failures: Vec::new(),
+            stats: DownloadStats::default(),
+            elapsed: Duration::ZERO,
+            disk_usage: disk_usage(output_dir),
+            already_installed: false,
+        });
+    }
+
+    // Process files in parallel
+    let client = match &opts.client {
+        Some(client) => client.clone(),
+        None => build_client(&opts.download)?,
+    };
+    let download = DownloadOptions { checkpoint: Some(checkpoint.clone()), ..opts.download.clone() };
+    let started = Instant::now();
+    let (results, durations): (Vec<_>, Vec<_>) = download_files(&index.files, output_dir, &client, &download, opts.progress.as_ref())
+        .await
+        .into_iter()
+        .unzip();
+    let elapsed = started.elapsed();
+    let stats = DownloadStats::from_results(&results);
+
+    let mut succeeded = Vec::new();
+    let mut failed = Vec::new();
+    for ((file, result), duration) in index.files.into_iter().zip(results).zip(durations) {
+        match result {
+            Ok(fetched) => succeeded.push(InstalledFile { file, fetched, duration }),
+            Err(error) => failed.push(FailedFile { file, error, duration }),
+        }
+    }
+
+    // Re-read everything from disk, to catch corruption after the per-download checks
+    let mut verify_failures = Vec::new();
+    if opts.verify_after {
+        info!("Verifying {} installed file(s)...", succeeded.len());
+        let installed: Vec<ModFile> = succeeded.iter().map(|installed| installed.file.clone()).collect();
+        verify_failures = verify_files(&installed, output_dir, opts.download.hash, opts.download.hash_policy, opts.verify_threads, opts.progress.as_ref());
+        for failure in &verify_failures {
+            warn!("Verification failed for {}: {}", failure.path, failure.problem);
+            if let Err(e) = checkpoint.forget(&failure.path) {
+                debug!("Failed to update checkpoint for {}: {}", failure.path, e);
+            }
+        }
+        if verify_failures.is_empty() {
+            info!("Verified {} installed file(s)", succeeded.len());
+        }
+    }
+
+    if failed.is_empty() && verify_failures.is_empty() {
+        checkpoint.remove();
+    }
+
+    if opts.write_sidecars {
+        write_sidecars(output_dir, &succeeded, &overrides)?;
+        info!("Wrote {} .sha512 file(s)", succeeded.len() + overrides.len());
+    }
+
+    if opts.write_manifest {
+        let mut manifest = Manifest::new(&index.name);
+        for installed in &succeeded {
+            manifest.add_download(&installed.file);
+        }
+        for copied in &overrides {
+            manifest.add_override(copied);
+        }
+        manifest.apply_hashes(&long_path(output_dir), &opts.manifest_hashes)
+            .map_err(|e| format!("Failed to hash the installed files for the manifest: {}", e))?;
+        let manifest_path = output_dir.join(Manifest::FILE_NAME);
+        std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+        info!("Created manifest at: {}", manifest_path.display());
+    }
+
+    Ok(InstallReport {
+        output_dir: output_dir.clone(),
+        succeeded,
+        failed,
+        skipped,
+        overrides,
+        override_failures,
+        overrides_skipped,
+        verify_failures,
+        stats,
+        elapsed,
+        disk_usage: disk_usage(output_dir),
+        already_installed: false,
+    })
+}
+
+// What installs where: `index` with only the files `opts` selects for download (--only,
+// project lists, size limit, ...), at the paths they're installed to (flattened,
+// prefixed), and the files left out. Fails if the pack includes a denied project.
+pub fn select_files(index: &ModrinthIndex, opts: &InstallOptions) -> Result<(ModrinthIndex, Vec<SkippedFile>), Box<dyn Error>> {
+    let mut index = index.clone();
+    if !opts.known_hashes.is_empty() {
+        let (supplemented, disagreements) = index.supplement_hashes(&opts.known_hashes);
+        for disagreement in &disagreements {
+            warn!("{}", disagreement);
+        }
+        info!("Hash list: {} file(s) given hashes the index lacked, {} disagreeing with the index", supplemented, disagreements.len());
+    }
+    if opts.overrides_only {
+        if index.overrides_path.is_none() {
+            return Err("The pack has no overrides to copy".into());
+        }
+        index.files.clear();
+    }
+
+    let mut skipped = Vec::new();
+    if !opts.only.is_empty() {
+        let dropped = index.retain_content(&opts.only);
+        let dirs: Vec<&str> = opts.only.iter().map(|kind| kind.dir()).collect();
+        info!("Installing only {}: {} file(s) selected, {} left out", dirs.join(", "), index.files.len(), dropped.len());
+        for file in dropped {
+            debug!("Skipping {} (not in {})", file.path, dirs.join(", "));
+            skipped.push(SkippedFile { file, reason: SkipReason::NotSelected });
+        }
+    }
+
+    // Files unsupported on both sides are never downloaded, whatever the launcher
+    for file in index.remove_unsupported() {
+        info!("Skipping {} (unsupported on both client and server)", file.path);
+        skipped.push(SkippedFile { file, reason: SkipReason::Unsupported });
+    }
+
+    // A denied project fails the whole install, before anything is written; the ones
+    // missing from an allowlist are just left out
+    if !opts.projects.is_empty() {
+        let mut denied = Vec::new();
+        for file in std::mem::take(&mut index.files) {
+            let project = match modrinth_project_id(&file) {
+                Some(id) => format!("project {}", id),
+                None => "not from Modrinth".to_string(),
+            };
+            match opts.projects.decide(&file) {
+                Decision::Allowed => {
+                    debug!("Allowed: {} ({})", file.path, project);
+                    index.files.push(file);
+                }
+                Decision::NotAllowed => {
+                    info!("Skipping {} ({}, not on the allowlist)", file.path, project);
+                    skipped.push(SkippedFile { file, reason: SkipReason::NotAllowed });
+                }
+                Decision::Denied => {
+                    info!("Denied: {} ({})", file.path, project);
+                    denied.push(file.path);
+                }
+            }
+        }
+        if !denied.is_empty() {
+            return Err(format!("The pack includes {} file(s) of denied projects (--deny-projects): {}", denied.len(), denied.join(", ")).into());
+        }
+    }
+
+    // Nor are files declared larger than the size limit
+    if let Some(limit) = opts.download.max_file_size {
+        let (too_large, files): (Vec<ModFile>, Vec<ModFile>) = std::mem::take(&mut index.files)
+            .into_iter()
+            .partition(|file| file.file_size > limit);
+        index.files = files;
+        for file in too_large {
+            warn!("Skipping {} ({} bytes, over the {} byte size limit)", file.path, file.file_size, limit);
+            skipped.push(SkippedFile { file, reason: SkipReason::TooLarge });
+        }
+    }
+
+    if opts.flatten {
+        flatten_paths(&mut index.files)?;
+    }
+    // Paths stay relative to the output directory, so everything after this (checkpoint,
+    // manifest, summaries) lists them with the prefix
+    if let Some(prefix) = &opts.root_prefix {
+        for file in &mut index.files {
+            file.path = format!("{}/{}", prefix, file.path);
+        }
+    }
+    Ok((index, skipped))
+}
+
+/// A file an install would write, see [`planned_paths`].
+#[derive(Debug, Clone)]
+pub struct PlannedPath {
+    /// Relative to the output directory, with `/` separators
+    pub path: String,
+    pub source: FileSource,
+}
+
+// Every file installing `index` with `opts` would write, downloads (as selected by
+// select_files) and overrides (as filtered by --only and the overrides globs), in that
+// order, without writing anything. Overrides that are symlinks are listed, though the
+// copy skips them. An override at the same path as a download is left out, since the
+// download replaces it.
+pub fn planned_paths(index: &ModrinthIndex, opts: &InstallOptions) -> Result<Vec<PlannedPath>, Box<dyn Error>> {
+    let (selected, _) = select_files(index, opts)?;
+    let mut paths: Vec<PlannedPath> = selected.files.into_iter()
+        .map(|file| PlannedPath { path: file.path, source: FileSource::Download })
+        .collect();
+    if let Some(overrides_path) = &selected.overrides_path {
+        let downloads: HashSet<String> = paths.iter().map(|planned| planned.path.clone()).collect();
+        let filter = CopyOptions {
+            include: opts.overrides_include.clone(),
+            exclude: opts.overrides_exclude.clone(),
+            ..CopyOptions::default()
+        };
+        let mut overrides = Vec::new();
+        list_files(overrides_path, Path::new(""), &mut overrides)
+            .map_err(|e| format!("Failed to list the overrides in '{}': {}", overrides_path.display(), e))?;
+        for path in overrides {
+            let top = path.split('/').next().unwrap_or_default();
+            if !opts.only.is_empty() && !opts.only.iter().any(|kind| kind.dir() == top) {
+                continue;
+            }
+            if !filter.selects(Path::new(&path)) {
+                continue;
+            }
+            let path = match &opts.root_prefix {
+                Some(prefix) => format!("{}/{}", prefix, path),
+                None => path,
+            };
+            if !downloads.contains(&path) {
+                paths.push(PlannedPath { path, source: FileSource::Override });
+            }
+        }
+    }
+    Ok(paths)
+}
+
+// Takes the downloads that would replace a different file already in the output
+// directory out of `index`, as the prompt (or else the policy) decides, returning the
+// ones kept. A file without a usable hash can't be told apart and is never a conflict.
+fn resolve_conflicts(index: &mut ModrinthIndex, output_dir: &Path, checkpoint: &Checkpoint, opts: &InstallOptions) -> Vec<SkippedFile> {
+    let algorithm = opts.download.hash;
+    let conflicts: Vec<bool> = index.files.par_iter()
+        .map(|file| {
+            !checkpoint.is_complete(file)
+                && algorithm.resolve(&file.hashes).is_some()
+                && fs::metadata(join_components(&long_path(output_dir), &file.path)).is_ok_and(|meta| meta.is_file())
+                && !is_installed(file, output_dir, algorithm, opts.download.hash_policy)
+        })
+        .collect();
+    let count = conflicts.iter().filter(|&&conflict| conflict).count();
+    if count == 0 {
+        return Vec::new();
+    }
+
+    let mut prompt = opts.conflict_prompt.as_ref();
+    let mut policy = opts.conflicts;
+    let mut keep = Vec::with_capacity(conflicts.len());
+    for (file, conflict) in index.files.iter().zip(conflicts) {
+        if !conflict {
+            keep.push(false);
+            continue;
+        }
+        let kept = match prompt.and_then(|prompt| prompt(&file.path)) {
+            Some(ConflictChoice::Keep) => true,
+            Some(ConflictChoice::Overwrite) => false,
+            // An "all" answer, or none, settles this and every further conflict
+            choice => {
+                prompt = None;
+                match choice {
+                    Some(ConflictChoice::SkipAll) => policy = ConflictPolicy::Keep,
+                    Some(ConflictChoice::OverwriteAll) => policy = ConflictPolicy::Overwrite,
+                    _ => {}
+                }
+                policy == ConflictPolicy::Keep
+            }
+        };
+        debug!("Conflict: {} differs from the pack's version, {}", file.path, if kept { "kept" } else { "overwritten" });
+        keep.push(kept);
+    }
+
+    let files = std::mem::take(&mut index.files);
+    let mut kept = Vec::new();
+    for (file, keep) in files.into_iter().zip(keep) {
+        if keep {
+            kept.push(SkippedFile { file, reason: SkipReason::KeptExisting });
+        } else {
+            index.files.push(file);
+        }
+    }
+    info!("Conflicts: {} file(s) differ from the pack's version, {} kept, {} to be overwritten", count, kept.len(), count - kept.len());
+    kept
+}
+
+// Whether every file of `index` is in `output_dir` with a matching hash, and every
+// override exists in `content_dir` (overrides aren't hashed, the game may rewrite configs)
+fn is_fully_installed(index: &ModrinthIndex, output_dir: &Path, content_dir: &Path, algorithm: HashAlgorithm, policy: HashPolicy) -> bool {
+    output_dir.is_dir()
+        && index.overrides_path.as_deref().is_none_or(|overrides| overrides_present(overrides, &long_path(content_dir)))
+        && index.files.par_iter().all(|file| is_installed(file, output_dir, algorithm, policy))
+}
+
+fn overrides_present(src: &Path, dest: &Path) -> bool {
+    fs::read_dir(src).is_ok_and(|mut entries| {
+        entries.all(|entry| match entry.and_then(|entry| Ok((entry.file_type()?, entry))) {
+            Ok((kind, entry)) if kind.is_dir() => overrides_present(&entry.path(), &dest.join(entry.file_name())),
+            Ok((_, entry)) => dest.join(entry.file_name()).exists(),
+            Err(_) => false,
+        })
+    })
+}
+
+fn disk_usage(output_dir: &Path) -> Option<u64> {
+    dir_size(&long_path(output_dir))
+        .inspect_err(|e| debug!("Failed to measure the size of {}: {}", output_dir.display(), e))
+        .ok()
+}
+
+// Applies the ExistingOutput policy to a non-empty `output_dir`
+fn set_aside_existing(output_dir: &Path, policy: ExistingOutput) -> Result<(), Box<dyn Error>> {
+    // The lock file a finished install leaves behind doesn't count
+    let non_empty = fs::read_dir(output_dir).is_ok_and(|entries| {
+        entries.flatten().any(|entry| entry.file_name() != InstallLock::FILE_NAME)
+    });
+    if !non_empty || policy == ExistingOutput::Merge {
+        return Ok(());
+    }
+    if policy == ExistingOutput::Fail {
+        return Err(format!("Output directory '{}' already exists and is not empty", output_dir.display()).into());
+    }
+
+    // Don't pull the directory away from under another modrinther still installing there
+    drop(InstallLock::acquire(output_dir)?);
+
+    let now = OffsetDateTime::now_utc();
+    let mut backup_name = output_dir.file_name().unwrap_or_default().to_os_string();
+    backup_name.push(format!(
+        ".bak.{:04}{:02}{:02}-{:02}{:02}{:02}",
+        now.year(), u8::from(now.month()), now.day(), now.hour(), now.minute(), now.second()
+    ));
+    let backup = output_dir.with_file_name(backup_name);
+    fs::rename(output_dir, &backup).map_err(|e| {
+        format!("Failed to move the existing output directory to '{}': {}", backup.display(), e)
+    })?;
+    info!("Moved the existing output directory to: {}", backup.display());
+    Ok(())
+}
+
+// Writes a `.sha512` sidecar for each installed file, with the index's hash (or the
+// file's own, if the index only has sha1)
+fn write_sidecars(output_dir: &Path, downloads: &[InstalledFile], overrides: &[CopiedFile]) -> Result<(), Box<dyn Error>> {
+    let output_dir = long_path(output_dir);
+    for installed in downloads {
+        let path = join_components(&output_dir, &installed.file.path);
+        let sha512 = match installed.file.hashes.get("sha512") {
+            Some(sha512) => sha512.clone(),
+            None => sha512_reader(fs::File::open(&path)?)?,
+        };
+        write_sidecar(&path, &sha512).map_err(|e| format!("Failed to write hash file for {}: {}", installed.file.path, e))?;
+    }
+    for copied in overrides {
+        let path = output_dir.join(&copied.relative_path);
+        let sha512 = match &copied.sha512 {
+            Some(sha512) => sha512.clone(),
+            None => sha512_reader(fs::File::open(&path)?)?,
+        };
+        write_sidecar(&path, &sha512).map_err(|e| format!("Failed to write hash file for {}: {}", copied.relative_path.display(), e))?;
+    }
+    Ok(())
+}
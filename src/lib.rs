@@ -0,0 +1,27 @@
+//! Installs Modrinth modpacks (`.mrpack`, `.zip` or a bare `modrinth.index.json`),
+//! regardless of broken CRCs in the archive.
+//!
+//! The `modrinther` binary is a thin CLI over this library: [`install::install`]
+//! installs a parsed index and returns an [`install::InstallReport`]. Progress is
+//! reported through [`progress::ProgressSink`], so other frontends can supply
+//! their own UI.
+
+pub mod archive;
+pub mod cache;
+pub mod checkpoint;
+pub mod datadir;
+pub mod download;
+pub mod error;
+pub mod fsutil;
+pub mod hash;
+pub mod index;
+pub mod install;
+pub mod manifest;
+pub mod policy;
+pub mod profile;
+pub mod progress;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod updates;
+pub mod validate;
+pub mod verify;
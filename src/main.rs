@@ -1,405 +1,2370 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::io::{self};
-use std::path::{Path, PathBuf};
+use std::io::{self, IsTerminal, Write};
+use std::net::IpAddr;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use futures::stream::{self, StreamExt};
-use serde::{Deserialize, Serialize};
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use directories::BaseDirs;
 use console::Term;
-use zip::ZipArchive;
-
-#[derive(Debug, Deserialize, Serialize)]
-struct ModrinthIndex {
-    dependencies: HashMap<String, String>,
-    files: Vec<ModFile>,
-    #[serde(rename = "formatVersion")]
-    format_version: u32,
-    game: String,
+use clap::{Parser, Subcommand};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use log::{debug, info, warn, Level, LevelFilter, Log, Metadata, Record};
+use percent_encoding::percent_decode_str;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use modrinther::archive::{export_zip, find_index_json, find_overrides, is_archive_file, process_archive_file, ExportOptions, ExtractOptions, ExtractionIncomplete, ZipCompression, DEFAULT_INDEX_NAME, OVERRIDES_DIRS};
+use modrinther::cache::ContentCache;
+use modrinther::checkpoint::RunRecord;
+use modrinther::datadir::DataDir;
+use modrinther::download::{apply_mirrors, build_client, host_of, DownloadOptions, DownloadOrder, DownloadStats, FileHook, HostAuth, HostSetting, MirrorRule};
+use modrinther::fsutil::{available_space, copy_directory_contents, expand_env_vars, join_components, InstallLock, CopyOptions, normalize_path, parse_size, probe_writable, sanitize_filename, ScratchDir, format_bytes, format_duration, render_output_template, TreeTooDeep, DEFAULT_MAX_DEPTH};
+use modrinther::hash::{load_hash_list, HashAlgorithm, HashPolicy};
+use modrinther::index::{disabled_variants, load_index, ContentKind, EnvTally, ModFile, ModrinthIndex, SideTally};
+use modrinther::error::InstallError;
+use modrinther::install::{install, planned_paths, ConflictChoice, ConflictPolicy, ConflictPrompt, ExistingOutput, FailedFile, InstallOptions, InstallReport, PlannedPath};
+use modrinther::manifest::{FileSource, Manifest, ManifestHashes};
+use modrinther::policy::{load_project_list, ProjectPolicy};
+use modrinther::profile::{default_launcher_dir, write_vanilla_profile, LAUNCHER_PROFILES_FILE};
+use modrinther::progress::{IndicatifProgress, JsonLinesProgress, NoProgress, ProgressFormat, ProgressSink, TitleProgress};
+use modrinther::updates::{check_updates, ModUpdate, MODRINTH_API};
+use modrinther::validate::{validate_pack, PackProblem, PackValidation};
+use modrinther::verify::{compare_dirs, verify_manifest};
+
+// `modrinther validate PACK...`, the same as `--validate PACK...`. The options a
+// validation uses are global, so they may come after the subcommand too.
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check each pack for problems and report them all, like --validate; downloads and writes nothing
+    Validate {
+        /// Modrinth .json, .zip, or .mrpack files, or directories of extracted packs
+        #[arg(required = true)]
+        packs: Vec<PathBuf>,
+    },
+}
+
+/// Installs a Modrinth modpack, regardless of broken CRCs in the archive
+#[derive(Debug, Parser)]
+#[command(name = "modrinther", version, about)]
+struct Cli {
+    /// Modrinth .json, .zip, or .mrpack files to install, or directories of extracted packs
+    inputs: Vec<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Output directory, relative to the input file's directory (or the pack directory itself).
+    /// Placeholders: {name}, {mc_version}, {loader}, {version_id}; environment variables ($VAR, ${VAR}) are expanded
+    #[arg(long, default_value = "{name}", global = true)]
+    output_template: String,
+
+    /// Use NAME instead of the pack's name for {name} in the output directory (the summaries keep the pack's name)
+    #[arg(long, value_name = "NAME", global = true)]
+    name: Option<String>,
+
+    /// Fold the placeholders of the output directory to plain ASCII (accents removed, emoji and other symbols dropped)
+    #[arg(long, global = true)]
+    ascii_names: bool,
+
+    /// Number of packs to install at the same time when several are given
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    parallel_packs: u16,
+
+    /// Order to download files in: index, size-asc, size-desc or path
+    #[arg(long, default_value = "index")]
+    order: DownloadOrder,
+
+    /// Use this loader (e.g. fabric or quilt) when the pack declares several [default: the most specific]
+    #[arg(long, value_name = "NAME")]
+    loader: Option<String>,
+
+    /// Put every downloaded file directly into mods/, ignoring the pack's folder structure
+    #[arg(long)]
+    flatten: bool,
+
+    /// Install the mods and overrides under PATH inside the output directory (e.g. minecraft or .minecraft), where the launcher expects them
+    #[arg(long, value_name = "PATH", value_parser = parse_root_prefix)]
+    root_prefix: Option<String>,
+
+    /// Maximum simultaneous downloads from a single host
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u16).range(1..))]
+    per_host: u16,
+
+    /// Allow this host a different number of simultaneous downloads than --per-host (e.g. cdn.modrinth.com=8). Repeatable
+    #[arg(long, value_name = "HOST=N")]
+    host_connections: Vec<HostSetting<NonZeroUsize>>,
+
+    /// Fail a request (to be retried) that takes longer than SECS, body included [default: no limit]
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// Use a different --timeout for this host (e.g. slow.example.com=600). Repeatable
+    #[arg(long, value_name = "HOST=SECS")]
+    host_timeout: Vec<HostSetting<NonZeroU64>>,
+
+    /// Look every host up once and reuse its addresses for the whole run, instead of a lookup per new connection
+    #[arg(long)]
+    dns_cache: bool,
+
+    /// Connect to HOST at this address instead of looking it up (e.g. cdn.modrinth.com=203.0.113.7). Repeatable
+    #[arg(long, value_name = "HOST=IP")]
+    resolve: Vec<HostSetting<IpAddr>>,
+
+    /// Close connections that were unused for SECS instead of keeping them for reuse [default: 90]
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pool_idle_timeout: Option<u64>,
+
+    /// Keep at most N unused connections per host open for reuse; 0 never reuses one [default: no limit]
+    #[arg(long, value_name = "N")]
+    pool_max_idle: Option<usize>,
+
+    /// Only download from this host (and its subdomains), also after redirects. Repeatable
+    #[arg(long, value_name = "HOST", global = true)]
+    allow_host: Vec<String>,
+
+    /// Split files of 32 MiB or more into N parallel ranged requests, if the server supports it
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    concurrency_per_file: u16,
+
+    /// Skip files the index declares larger than SIZE (e.g. 500M), and abort downloads that grow past it
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Retry a transiently failing download up to N times (0 disables retries)
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    max_retries: u32,
+
+    /// Wait MS milliseconds before the first retry, doubling for every further one
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    retry_delay: u64,
+
+    /// Adapt the number of simultaneous downloads to the connection (starting at 2 and ramping up while throughput improves) instead of --net-concurrency
+    #[arg(long)]
+    auto_concurrency: bool,
+
+    /// For files with several download URLs, probe each server once (a HEAD request) and try them fastest first, instead of only the first
+    #[arg(long)]
+    fastest_mirror: bool,
+
+    /// Run COMMAND (through sh, or cmd on Windows) on each file once it's installed, with {path} replaced by the file's path; a failure fails that file (and stops the install with --strict)
+    #[arg(long, value_name = "COMMAND")]
+    per_file_command: Option<String>,
+
+    /// Download up to N files at once
+    #[arg(long, value_name = "N", default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..), conflicts_with = "auto_concurrency")]
+    net_concurrency: u16,
+
+    /// Let up to N downloads write to disk at once; the others keep receiving into a bounded buffer meanwhile
+    #[arg(long, value_name = "N", default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    disk_concurrency: u16,
+
+    /// Only retry transient failures (timeouts, dropped connections, 5xx, 429); a hash mismatch fails the file right away
+    #[arg(long)]
+    retry_only_transient: bool,
+
+    /// Update download progress once per SIZE received (e.g. 1M) instead of every chunk; 0 updates on every chunk
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "256K")]
+    progress_batch: u64,
+
+    /// Keep modrinther's state (download cache, logs) under DIR [default: the user data directory]
+    #[arg(long, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Keep downloaded files in DIR and reuse them across installs [default: <data dir>/cache]
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Neither read from nor add to the download cache
+    #[arg(long, conflicts_with_all = ["cache_dir", "offline"])]
+    no_cache: bool,
+
+    /// Never use the network: every file must already be in the cache
+    #[arg(long)]
+    offline: bool,
+
+    /// Hash to verify downloads with: sha1, sha512 or auto (sha512, else sha1)
+    #[arg(long, value_name = "ALGORITHM", default_value = "auto")]
+    hash: HashAlgorithm,
+
+    /// What a hash mismatch does: strict fails the file, warn logs it and keeps the file (for packs with stale hashes), off skips hash checks
+    #[arg(long, value_name = "POLICY", default_value = "strict")]
+    hash_policy: HashPolicy,
+
+    /// Checksum file (`sha512sum`/`sha1sum` output, `<hash>  <path>` per line) whose hashes supplement the index's, and win where they disagree
+    #[arg(long, value_name = "FILE")]
+    hashes: Option<PathBuf>,
+
+    /// Accept invalid TLS certificates, e.g. a private mirror's self-signed one. Dangerous
+    #[arg(long)]
+    allow_insecure: bool,
+
+    /// Rewrite downloads from host FROM to host TO (e.g. cdn.modrinth.com=mirror.lan:8080). Repeatable
+    #[arg(long, value_name = "FROM=TO")]
+    mirror: Vec<MirrorRule>,
+
+    /// Send credentials to a private download host: a bearer token, or USER:PASSWORD for basic auth. Repeatable
+    #[arg(long, value_name = "HOST:TOKEN")]
+    auth: Vec<HostAuth>,
+
+    /// File name of the index inside archives, for nonstandard packs
+    #[arg(long, value_name = "FILENAME", default_value = DEFAULT_INDEX_NAME, global = true)]
+    index_name: String,
+
+    /// Report archive entries that don't belong in a standard .mrpack (stray READMEs, .DS_Store, ...), and loader versions that don't match the Minecraft version
+    #[arg(short = 'v', long)]
+    audit: bool,
+
+    /// Treat questionable pack contents (like symlinks in overrides) as errors
+    #[arg(long)]
+    strict: bool,
+
+    /// Delete *.part files left in the output directory by an interrupted run before starting
+    #[arg(long)]
+    clean_partials: bool,
+
+    /// Continue interrupted installs: the inputs are output directories of earlier runs, installed again from their modrinth.index.json,
+    /// keeping files that are already complete and continuing .part files
+    #[arg(long, alias = "resume-all", conflicts_with_all = ["clean_partials", "rename_existing", "fail_if_exists", "output_template", "name", "overrides_only"])]
+    resume: bool,
+
+    /// Write modpack_manifest.json listing every installed file with its size and hash
+    #[arg(long)]
+    manifest: bool,
+
+    /// Record every hash of each file in the manifest, as a `hashes` object keyed by algorithm (the index's, for downloads)
+    #[arg(long, requires = "manifest")]
+    include_hashes_in_manifest: bool,
+
+    /// Compute these hashes (sha1, sha512) from the installed files for the manifest instead of taking them from the index. Comma-separated
+    #[arg(long, value_name = "ALGORITHMS", value_delimiter = ',', requires = "include_hashes_in_manifest")]
+    manifest_compute_hashes: Vec<HashAlgorithm>,
+
+    /// Write <file>.sha512 next to every installed file, checkable with sha512sum -c
+    #[arg(long)]
+    write_sidecar_hashes: bool,
+
+    /// Re-read and re-hash copied overrides to confirm the copy was faithful
+    #[arg(long)]
+    verify_overrides: bool,
+
+    /// If the output directory exists and isn't empty, move it aside to <dir>.bak.<timestamp> first
+    #[arg(long, conflicts_with = "fail_if_exists")]
+    rename_existing: bool,
+
+    /// Abort if the output directory exists and isn't empty, instead of installing over it
+    #[arg(long)]
+    fail_if_exists: bool,
+
+    /// What installing over a directory does with files that differ from the pack's: overwrite or keep them. Without it, an interactive run asks for each
+    #[arg(long, value_name = "POLICY")]
+    conflict_policy: Option<ConflictPolicy>,
+
+    /// Warn (or fail, with --strict) before installing if the pack isn't for Minecraft VERSION, e.g. 1.20.1
+    #[arg(long, value_name = "VERSION")]
+    expect_mc_version: Option<String>,
+
+    /// Install even if the output directory already holds every file with a matching hash and every override,
+    /// instead of stopping with "Pack already fully installed and verified"
+    #[arg(long)]
+    force: bool,
+
+    /// Fail if the pack's overrides directory exists but has no files (a sign of a truncated download)
+    #[arg(long)]
+    require_overrides: bool,
+
+    /// Abort on the first override file that can't be copied, instead of reporting it at the end
+    #[arg(long)]
+    strict_overrides: bool,
+
+    /// Stop at the first file that fails for good, cancelling the other downloads, and exit with an error naming it
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// After downloading, re-read every installed file and check its size and hash; fails if any doesn't match
+    #[arg(long)]
+    verify_after: bool,
+
+    /// Only copy the pack's overrides (configs, resource packs, ...), downloading no mods
+    #[arg(long)]
+    overrides_only: bool,
+
+    /// Only install this kind of content (mods, resourcepacks, shaderpacks, datapacks or config), downloads and overrides alike. Repeatable
+    #[arg(long, value_name = "KIND", value_delimiter = ',')]
+    only: Vec<ContentKind>,
+
+    /// Only install mods of the Modrinth projects listed in FILE (one project ID per line), skipping the rest
+    #[arg(long, value_name = "FILE")]
+    allow_projects: Option<PathBuf>,
+
+    /// Refuse to install a pack with a mod of any Modrinth project listed in FILE (one project ID per line)
+    #[arg(long, value_name = "FILE")]
+    deny_projects: Option<PathBuf>,
+
+    /// Only copy the overrides matching GLOB (e.g. 'config/**'), relative to the overrides directory. Repeatable
+    #[arg(long, value_name = "GLOB")]
+    overrides_include: Vec<String>,
+
+    /// Leave out the overrides matching GLOB (e.g. 'kubejs/**'), even if included. Repeatable
+    #[arg(long, value_name = "GLOB")]
+    overrides_exclude: Vec<String>,
+
+    /// Only copy the overrides that are missing from the output directory or newer than the installed ones (by modification time)
+    #[arg(long, conflicts_with = "manifest")]
+    overrides_newer_only: bool,
+
+    /// Deepest directory nesting accepted in the overrides, and when looking for the index inside an archive or directory
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_DEPTH, global = true)]
+    max_depth: usize,
+
+    /// Copy overrides on several threads, for packs with many small config files
+    #[arg(long)]
+    parallel_overrides: bool,
+
+    /// Hash files on N threads when verifying (--verify-after, --verify-manifest) [default: one per CPU core]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    parallel_hash_verify: Option<u16>,
+
+    /// Extract archives on N threads, for packs with many or large override files
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    extract_concurrency: u16,
+
+    /// Skip archive entries that can't be read and go on, instead of aborting (a corrupt index still aborts)
+    #[arg(long)]
+    skip_corrupt_entries: bool,
+
+    /// Extract archives under PATH instead of the system temp directory (also MODRINTHER_TEMP)
+    #[arg(long, value_name = "PATH")]
+    temp_dir: Option<PathBuf>,
+
+    /// Keep the extracted archive for inspection instead of deleting it, optionally extracting into DIR/<pack file name>
+    #[arg(long, value_name = "DIR", num_args = 0..=1, require_equals = true)]
+    keep_extracted: Option<Option<PathBuf>>,
+
+    /// Summaries to write into the output directory: text, json, md (Markdown, for issue trackers and wikis) or all. Comma-separated
+    #[arg(long, value_name = "FORMATS", value_delimiter = ',', default_value = "text,json")]
+    summary_format: Vec<SummaryFormat>,
+
+    /// Also copy each pack's summaries into PATH, as <PackName>-summary.txt/.json/.md
+    #[arg(long, value_name = "PATH")]
+    summary_dir: Option<PathBuf>,
+
+    /// After the run, write a `<url> -> <path>` line to PATH for every file that failed to download, to fetch them by hand
+    #[arg(long, value_name = "PATH")]
+    dump_failed_urls: Option<PathBuf>,
+
+    /// Once installed, zip the output directory (mods, overrides and all) into PATH, to hand the finished pack to someone else
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["verify_manifest", "compare", "extract_overrides", "check_updates"])]
+    export_zip: Option<PathBuf>,
+
+    /// How --export-zip compresses the files: stored (none, fastest, jars are compressed already), deflate or zstd
+    #[arg(long, value_name = "METHOD", default_value = "deflate", requires = "export_zip")]
+    zip_compression: ZipCompression,
+
+    /// The --zip-compression level: 0-9 for deflate, -7 to 22 for zstd [default: 6 for deflate, 3 for zstd]
+    #[arg(long, value_name = "N", allow_negative_numbers = true, requires = "export_zip")]
+    zip_level: Option<i32>,
+
+    /// How to show progress: bars, or json for one JSON object per event on stderr (for launchers driving their own UI)
+    #[arg(long, value_name = "FORMAT", default_value = "bars")]
+    progress_format: ProgressFormat,
+
+    /// Don't show download progress in the terminal window title
+    #[arg(long)]
+    no_title: bool,
+
+    /// Add a profile for the installed pack to the official Minecraft launcher (updated on reinstall)
+    #[arg(long)]
+    vanilla_profile: bool,
+
+    /// Launcher profiles file for --vanilla-profile [default: launcher_profiles.json in .minecraft]
+    #[arg(long, value_name = "PATH", requires = "vanilla_profile")]
+    launcher_profiles: Option<PathBuf>,
+
+    /// Only report which mods have newer versions on Modrinth; installs nothing
+    #[arg(long)]
+    check_updates: bool,
+
+    /// Print the directory tree each pack would be installed as (downloads and overrides, after --only and the other filters); installs nothing
+    #[arg(long, conflicts_with_all = ["verify_manifest", "compare", "check_updates", "extract_overrides", "export_zip", "resume"])]
+    print_tree: bool,
+
+    /// Check each pack for problems (format, game, paths, URLs and hosts, hashes, env values) and report them all; downloads and writes nothing
+    #[arg(long, conflicts_with_all = ["verify_manifest", "compare", "check_updates", "extract_overrides", "print_tree", "self_test", "validate_cache"])]
+    validate: bool,
+
+    /// Check directories (the inputs) against a manifest written by --manifest, without the pack; installs nothing
+    #[arg(long, value_name = "MANIFEST")]
+    verify_manifest: Option<PathBuf>,
+
+    /// Compare two installed directories (the two inputs) file by file; installs nothing
+    #[arg(long, conflicts_with = "verify_manifest")]
+    compare: bool,
+
+    /// Copy the pack's overrides (and client-/server-overrides) into DEST for editing, keeping their folders; installs nothing
+    #[arg(long, value_name = "DEST", conflicts_with_all = ["verify_manifest", "compare", "check_updates"])]
+    extract_overrides: Option<PathBuf>,
+
+    /// Check what installs depend on (network, temp and data directories, disk space, archive decoding) and report; installs nothing
+    #[arg(long, conflicts_with_all = ["verify_manifest", "compare", "check_updates", "extract_overrides"])]
+    self_test: bool,
+
+    /// Re-hash every entry of the download cache and remove the ones that don't match their hash; installs nothing
+    #[arg(long, conflicts_with_all = ["verify_manifest", "compare", "check_updates", "extract_overrides", "self_test", "no_cache"])]
+    validate_cache: bool,
+
+    /// Modrinth API used by --check-updates (for a Modrinth-compatible server)
+    #[arg(long, value_name = "URL", default_value = MODRINTH_API)]
+    api_url: String,
+
+    /// Append a timestamped plain-text log of the run to PATH
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Like --log-file, logging to <data dir>/logs/modrinther.log
+    #[arg(long, conflicts_with = "log_file")]
+    log_to_data_dir: bool,
+
+    /// Print a single JSON result object to stdout instead of the human output and prompts.
+    /// Progress and diagnostics go to stderr
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Close the final "Press Enter to exit" prompt by itself after SECS seconds; 0 waits for Enter
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    exit_timeout: u64,
+
+    /// Don't clear the console at startup (it's only cleared when stdout is a terminal anyway)
+    #[arg(long)]
+    no_clear: bool,
+
+    /// Never wait for Enter and don't clear the console, for scripts and CI (also MODRINTHER_NONINTERACTIVE=1)
+    #[arg(short = 'y', long, global = true)]
+    yes: bool,
+}
+
+// A summary file written after the install (--summary-format)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SummaryFormat {
+    Text,
+    Json,
+    Markdown,
+    All,
+}
+
+impl FromStr for SummaryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(SummaryFormat::Text),
+            "json" => Ok(SummaryFormat::Json),
+            "md" => Ok(SummaryFormat::Markdown),
+            "all" => Ok(SummaryFormat::All),
+            _ => Err(format!("unknown summary format '{}', expected text, json, md or all", s)),
+        }
+    }
+}
+
+// Prints our own log records to the console, plain for info and prefixed for warnings/errors,
+// or as `log` events with --progress-format json. With --log-file, records down to debug
+// level (per-file results) are also appended there.
+struct ConsoleLogger {
+    to_stderr: bool,
+    json_events: bool,
+    log_file: Option<Mutex<fs::File>>,
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let max_level = if self.log_file.is_some() { Level::Debug } else { Level::Info };
+        metadata.level() <= max_level && metadata.target().starts_with("modrinther")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if record.level() <= Level::Info && self.json_events {
+            let message = record.args().to_string();
+            JsonLinesProgress::log(&record.level().as_str().to_ascii_lowercase(), message.trim_start_matches('\n'));
+        } else if record.level() <= Level::Info {
+            let line = match record.level() {
+                Level::Error => format!("Error: {}", record.args()),
+                Level::Warn => format!("Warning: {}", record.args()),
+                _ => record.args().to_string(),
+            };
+            if self.to_stderr {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
+            }
+        }
+
+        if let Some(log_file) = &self.log_file {
+            let timestamp = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+            let message = record.args().to_string();
+            let mut log_file = log_file.lock().unwrap();
+            for line in message.lines().filter(|l| !l.trim().is_empty()) {
+                let _ = writeln!(log_file, "{} [{}] {}", timestamp, record.level(), line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(log_file) = &self.log_file {
+            let _ = log_file.lock().unwrap().flush();
+        }
+    }
+}
+
+// The single line printed to stdout in --json mode
+#[derive(Debug, Serialize)]
+struct JsonResult {
+    name: String,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    output: Option<PathBuf>,
+    errors: Vec<JsonError>,
+}
+
+// A failed file, or a pack that failed as a whole
+#[derive(Debug, Clone, Serialize)]
+struct JsonError {
+    path: String,
+    kind: String,
+    message: String,
+    /// Whether a retry could have fixed it (a timeout, a 5xx answer, ...), see --retry-only-transient
+    transient: bool,
+}
+
+// One downloaded (or failed) file, with how long it took
+#[derive(Debug, Serialize)]
+struct JsonFile {
+    path: String,
+    /// Where the bytes came from (network, cache, resumed, duplicate), or "failed"
+    status: &'static str,
+    bytes: u64,
+    duration_ms: u128,
+    /// The URL a network download came from, after --mirror rewrites
+    url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonSummary {
     name: String,
-    #[serde(rename = "versionId")]
-    version_id: String,
-    #[serde(skip)]
-    overrides_path: Option<PathBuf>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct ModFile {
-    downloads: Vec<String>,
-    env: HashMap<String, String>,
-    #[serde(rename = "fileSize")]
-    file_size: u64,
-    hashes: HashMap<String, String>,
+    game: String,
+    minecraft_version: String,
+    loader: String,
+    loader_version: String,
+    total_files: usize,
+    /// Every file the pack's index lists, including those left out of `total_files`
+    pack_files: usize,
+    succeeded: usize,
+    failed: usize,
+    /// Failed because the host no longer has the file (404/410)
+    removed: usize,
+    downloaded_bytes: u64,
+    cache_hits: usize,
+    cache_bytes_saved: u64,
+    /// Everything in the output directory afterwards, overrides included
+    disk_bytes: Option<u64>,
+    elapsed_secs: f64,
+    bytes_per_sec: f64,
+    errors: Vec<JsonError>,
+    override_errors: Vec<JsonError>,
+    /// Overrides left out by --overrides-include/--overrides-exclude
+    overrides_skipped: Vec<String>,
+    /// How many of the pack's files are required, optional or unsupported on each side
+    env: EnvTally,
+    /// Mods installed both enabled and as a `.disabled` copy, for toggling
+    disabled_variants: Vec<JsonDisabledVariant>,
+    files: Vec<JsonFile>,
+    skipped: Vec<JsonSkipped>,
+    /// The paths of `skipped` by reason, and the overrides left out by
+    /// --overrides-include/--overrides-exclude as "excluded"
+    skipped_by_reason: BTreeMap<&'static str, Vec<String>>,
+    run: JsonRun,
+}
+
+// The settings a run's outcome depends on, so a failure can be reproduced
+#[derive(Debug, Serialize)]
+struct JsonRun {
+    modrinther_version: &'static str,
+    max_retries: u32,
+    retry_delay_ms: u128,
+    retry_only_transient: bool,
+}
+
+// A mod installed twice, as `enabled` and as its `.disabled` copy
+#[derive(Debug, Serialize)]
+struct JsonDisabledVariant {
+    enabled: String,
+    disabled: String,
+}
+
+// A file that was deliberately not installed
+#[derive(Debug, Serialize)]
+struct JsonSkipped {
     path: String,
+    reason: String,
+}
+
+// Per-pack totals, used for the combined tally of a batch
+struct PackOutcome {
+    name: String,
+    output_dir: PathBuf,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    stats: DownloadStats,
+    errors: Vec<JsonError>,
+    /// The first URL and path of every file that failed to download, for --dump-failed-urls
+    failed_urls: Vec<(String, String)>,
+}
+
+// Why a pack failed. When that was after its downloads (--fail-fast, --verify-after,
+// --export-zip), with the files that failed to download, for --dump-failed-urls
+#[derive(Debug)]
+struct PackFailed {
+    message: String,
+    output_dir: PathBuf,
+    failed_urls: Vec<(String, String)>,
 }
 
+impl fmt::Display for PackFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for PackFailed {}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Clear the console
+    let cli = Cli::parse();
+    let json_events = cli.progress_format == ProgressFormat::Json;
+    match run(cli).await {
+        // As an event too, so stderr stays JSON lines to the end
+        Err(e) if json_events => {
+            JsonLinesProgress::error(&e.to_string());
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
+
+async fn run(mut cli: Cli) -> Result<(), Box<dyn Error>> {
+    if let Some(Command::Validate { packs }) = cli.command.take() {
+        cli.validate = true;
+        cli.inputs.extend(packs);
+    }
+    // --yes can't be undone by the environment, only the other way round
+    cli.yes = cli.yes || noninteractive_env();
+
+    // In --json mode stdout carries only the result, everything else goes to stderr
+    let log_file = if cli.log_file.is_some() || cli.log_to_data_dir {
+        Some(open_log_file(cli.log_file.clone(), data_dir(&cli))?)
+    } else {
+        None
+    };
+    let max_level = if log_file.is_some() { LevelFilter::Debug } else { LevelFilter::Info };
+    log::set_boxed_logger(Box::new(ConsoleLogger {
+        to_stderr: cli.json,
+        json_events: cli.progress_format == ProgressFormat::Json,
+        log_file: log_file.map(Mutex::new),
+    }))?;
+    log::set_max_level(max_level);
+    debug!("Run started: modrinther {} with {} input(s)", env!("CARGO_PKG_VERSION"), cli.inputs.len());
+
+    // Ctrl-C: release the output directory locks before going down
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            InstallLock::release_all();
+            warn!("Interrupted");
+            std::process::exit(130);
+        }
+    });
+
+    // Clear the console, for the window opened by a drag-and-drop; in a script, a tmux
+    // pane or with stdout redirected the earlier output is left alone
     let term = Term::stdout();
-    let _ = term.clear_screen();
+    if !cli.json && !cli.yes && !cli.no_clear && term.is_term() {
+        let _ = term.clear_screen();
+    }
     
-    // Get the path to the file either from arguments or via drag-and-drop
-    let args: Vec<String> = std::env::args().collect();
-    let input_path = if args.len() > 1 {
-        PathBuf::from(&args[1])
-    } else {
+    // Get the paths either from arguments or via drag-and-drop
+    cli.inputs = normalize_inputs(std::mem::take(&mut cli.inputs));
+    if cli.self_test {
+        self_test(&cli).await?;
+        wait_for_enter(&cli).await;
+        return Ok(());
+    }
+    if cli.validate_cache {
+        validate_cache(&cli)?;
+        wait_for_enter(&cli).await;
+        return Ok(());
+    }
+    if cli.inputs.is_empty() && cli.json {
+        return Err("No input files given".into());
+    }
+    if cli.inputs.is_empty() {
         println!("Drag and drop a Modrinth .json, .zip, or .mrpack file onto this executable,");
         println!("or provide it as an argument: modrinther <path-to-file>");
         
         // Wait for user input (so the console doesn't close immediately)
+        wait_for_enter(&cli).await;
+        std::process::exit(1);
+    }
+
+    if cli.manifest_compute_hashes.contains(&HashAlgorithm::Auto) {
+        return Err("--manifest-compute-hashes takes sha1 or sha512, not auto".into());
+    }
+    if cli.export_zip.is_some() && cli.inputs.len() > 1 {
+        return Err(format!("--export-zip takes exactly one pack, got {}", cli.inputs.len()).into());
+    }
+    if cli.name.is_some() && cli.inputs.len() > 1 {
+        return Err(format!("--name takes exactly one pack, got {} (they would all install into the same directory)", cli.inputs.len()).into());
+    }
+    // Checked before installing anything, not once the install is done
+    ExportOptions { compression: cli.zip_compression, level: cli.zip_level }.check()?;
+
+    // Expanded here rather than by the shell, so the same command line works from
+    // scripts and shortcuts that don't expand anything
+    cli.output_template = expand_env_vars(&cli.output_template)?;
+
+    let options = download_options(&cli);
+    debug!("Retries: up to {} per file, first after {}ms", options.max_retries, options.retry_delay.as_millis());
+    if cli.offline && options.cache.is_none() {
+        return Err("--offline needs a cache, but no cache directory could be determined; pass --cache-dir or --data-dir".into());
+    }
+
+    if cli.allow_insecure {
+        warn!("--allow-insecure is active: TLS certificates are NOT verified, so downloads can be intercepted or tampered with");
+    }
+
+    if let Some(manifest) = &cli.verify_manifest {
+        report_manifest_check(&cli, manifest)?;
+        wait_for_enter(&cli).await;
+        return Ok(());
+    }
+    if cli.compare {
+        report_comparison(&cli)?;
+        wait_for_enter(&cli).await;
+        return Ok(());
+    }
+    if let Some(dest) = &cli.extract_overrides {
+        extract_overrides(&cli, dest)?;
+        wait_for_enter(&cli).await;
+        return Ok(());
+    }
+
+    if cli.validate {
+        validate_packs(&cli)?;
+        wait_for_enter(&cli).await;
+        return Ok(());
+    }
+    if cli.print_tree {
+        print_trees(&cli)?;
+        wait_for_enter(&cli).await;
+        return Ok(());
+    }
+
+    // One client (and its connection pool) for the whole batch
+    let client = build_client(&options)?;
+    if cli.check_updates {
+        report_updates(&cli, &client).await?;
+        wait_for_enter(&cli).await;
+        return Ok(());
+    }
+    let multi = MultiProgress::new();
+    let pack_count = cli.inputs.len();
+
+    let mut outcomes = stream::iter(cli.inputs.iter().enumerate())
+        .map(|(i, input_path)| {
+            let cli = &cli;
+            let client = &client;
+            let multi = &multi;
+            async move {
+                if pack_count > 1 {
+                    info!("\n==> [{}/{}] {}", i + 1, pack_count, input_path.display());
+                }
+                let outcome = install_pack(cli, input_path, i, client, multi).await;
+                if let (true, Err(e)) = (pack_count > 1, &outcome) {
+                    info!("Failed to install {}: {}", input_path.display(), e);
+                }
+                (i, outcome.map_err(|e| match e.downcast::<PackFailed>() {
+                    Ok(failed) => *failed,
+                    Err(e) => PackFailed { message: e.to_string(), output_dir: PathBuf::new(), failed_urls: Vec::new() },
+                }))
+            }
+        })
+        .buffer_unordered(cli.parallel_packs as usize)
+        .collect::<Vec<_>>()
+        .await;
+    // Packs finish in any order; put the outcomes back in input order
+    outcomes.sort_by_key(|(i, _)| *i);
+    let outcomes: Vec<Result<PackOutcome, PackFailed>> = outcomes.into_iter().map(|(_, outcome)| outcome).collect();
+    if !cli.no_title && !cli.json {
+        TitleProgress::reset_title();
+    }
+    if let Some(path) = &cli.dump_failed_urls {
+        dump_failed_urls(path, &outcomes)?;
+    }
+
+    if cli.json {
+        let any_pack_failed = outcomes.iter().any(|o| o.is_err());
+        let results: Vec<JsonResult> = cli.inputs.iter().zip(outcomes).map(|(input_path, outcome)| {
+            match outcome {
+                Ok(o) => JsonResult {
+                    name: o.name,
+                    succeeded: o.succeeded,
+                    failed: o.failed,
+                    skipped: o.skipped,
+                    output: Some(o.output_dir),
+                    errors: o.errors,
+                },
+                Err(e) => JsonResult {
+                    name: input_path.display().to_string(),
+                    succeeded: 0,
+                    failed: 0,
+                    skipped: 0,
+                    output: None,
+                    errors: vec![JsonError { path: input_path.display().to_string(), kind: "pack".to_string(), message: e.message, transient: false }],
+                },
+            }
+        }).collect();
+        let line = if results.len() == 1 {
+            serde_json::to_string(&results[0])?
+        } else {
+            serde_json::to_string(&serde_json::json!({ "packs": results }))?
+        };
+        println!("{}", line);
+        if any_pack_failed {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if pack_count > 1 {
+        let installed: Vec<&PackOutcome> = outcomes.iter().filter_map(|o| o.as_ref().ok()).collect();
+        info!("\nBatch complete: {}/{} packs installed", installed.len(), pack_count);
+        info!("Files downloaded: {}, failed: {}",
+            installed.iter().map(|o| o.succeeded).sum::<usize>(),
+            installed.iter().map(|o| o.failed).sum::<usize>());
+        info!("Total downloaded: {}, from cache: {}",
+            format_bytes(installed.iter().map(|o| o.stats.downloaded_bytes).sum::<u64>() as f64),
+            format_bytes(installed.iter().map(|o| o.stats.cache_bytes).sum::<u64>() as f64));
+        if cli.fail_fast && installed.len() < pack_count {
+            return Err(format!("{} of {} packs failed (--fail-fast)", pack_count - installed.len(), pack_count).into());
+        }
+    } else if let Some(Err(e)) = outcomes.into_iter().next() {
+        // Keep the single-pack behavior of failing on the error
+        return Err(e.message.into());
+    }
+
+    // Prevent the window from closing immediately
+    wait_for_enter(&cli).await;
+    Ok(())
+}
+
+// Packs installing side by side (--parallel-packs) take turns at the conflict prompt
+static CONFLICT_PROMPT_TURN: Mutex<()> = Mutex::new(());
+
+// Asks on the console about each file an install over an existing directory would
+// replace, unless --conflict-policy decides or no one is there to answer (--yes, --json,
+// JSON progress or stdin not a terminal). An empty answer keeps the file. With several
+// packs installing at once, each question names its pack.
+fn conflict_prompt(cli: &Cli, input_path: &Path, multi: &MultiProgress) -> Option<ConflictPrompt> {
+    if cli.conflict_policy.is_some() || cli.json || cli.yes || cli.progress_format != ProgressFormat::Bars || !io::stdin().is_terminal() {
+        return None;
+    }
+    let multi = multi.clone();
+    let pack = if cli.parallel_packs > 1 && cli.inputs.len() > 1 {
+        format!("{}: ", input_path.display())
+    } else {
+        String::new()
+    };
+    Some(Arc::new(move |path: &str| {
+        let _turn = CONFLICT_PROMPT_TURN.lock().unwrap_or_else(|e| e.into_inner());
+        multi.suspend(|| loop {
+            print!("{}'{}' differs from the pack's version. [K]eep existing, (o)verwrite, overwrite (a)ll, (s)kip all? ", pack, path);
+            let _ = io::stdout().flush();
+            let mut answer = String::new();
+            match io::stdin().read_line(&mut answer) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {}
+            }
+            match answer.trim().to_ascii_lowercase().as_str() {
+                "" | "k" | "keep" => return Some(ConflictChoice::Keep),
+                "o" | "overwrite" => return Some(ConflictChoice::Overwrite),
+                "a" | "all" | "overwrite all" => return Some(ConflictChoice::OverwriteAll),
+                "s" | "skip" | "skip all" => return Some(ConflictChoice::SkipAll),
+                _ => println!("Please answer k, o, a or s."),
+            }
+        })
+    }))
+}
+
+// Keeps a double-clicked console window open until Enter is pressed, or until
+// --exit-timeout runs out. The read happens on a thread of its own that is simply
+// abandoned on timeout: a blocked stdin read can't be cancelled, and as a tokio
+// blocking task it would hold up the runtime's shutdown until Enter.
+async fn wait_for_enter(cli: &Cli) {
+    if cli.json || cli.yes {
+        return;
+    }
+    if cli.exit_timeout == 0 {
         println!("\nPress Enter to exit...");
+    } else {
+        println!("\nPress Enter to exit (auto-closing in {}s)...", cli.exit_timeout);
+    }
+    let (entered, enter) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
         let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        std::process::exit(1);
+        let _ = entered.send(io::stdin().read_line(&mut input));
+    });
+    if cli.exit_timeout == 0 {
+        let _ = enter.await;
+    } else {
+        let _ = tokio::time::timeout(Duration::from_secs(cli.exit_timeout), enter).await;
+    }
+}
+
+// --dump-failed-urls: one `<url> -> <path>` line per failed download. The paths are
+// relative to the output directory, prefixed with it in a batch of several packs. The
+// file is written even if nothing failed, so a stale list is never left behind. A pack
+// that failed after its downloads (--fail-fast, --verify-after) still has its list.
+fn dump_failed_urls(path: &Path, outcomes: &[Result<PackOutcome, PackFailed>]) -> Result<(), Box<dyn Error>> {
+    let failed: Vec<(&PathBuf, &Vec<(String, String)>)> = outcomes.iter()
+        .map(|outcome| match outcome {
+            Ok(outcome) => (&outcome.output_dir, &outcome.failed_urls),
+            Err(failed) => (&failed.output_dir, &failed.failed_urls),
+        })
+        .collect();
+    let mut list = String::new();
+    for (output_dir, failed_urls) in &failed {
+        for (url, file_path) in failed_urls.iter() {
+            if outcomes.len() > 1 {
+                list.push_str(&format!("{} -> {}\n", url, join_components(output_dir, file_path).display()));
+            } else {
+                list.push_str(&format!("{} -> {}\n", url, file_path));
+            }
+        }
+    }
+    fs::write(path, list).map_err(|e| format!("Failed to write failed-URL list '{}': {}", path.display(), e))?;
+    let count: usize = failed.iter().map(|(_, failed_urls)| failed_urls.len()).sum();
+    if count > 0 {
+        info!("Wrote the URLs of {} failed file(s) to: {}", count, path.display());
+    }
+    Ok(())
+}
+
+// Where modrinther's state lives: --data-dir, else the user data directory
+fn data_dir(cli: &Cli) -> Option<DataDir> {
+    cli.data_dir.clone().or_else(DataDir::default_root).map(DataDir::new)
+}
+
+// Opens the --log-file for appending; without a path (--log-to-data-dir), the log goes to <data dir>/logs
+fn open_log_file(path: Option<PathBuf>, data_dir: Option<DataDir>) -> Result<fs::File, Box<dyn Error>> {
+    let path = match (path, data_dir) {
+        (Some(path), _) => path,
+        (None, Some(data_dir)) => {
+            let logs = data_dir.logs_dir();
+            fs::create_dir_all(&logs).map_err(|e| format!("Failed to create log directory '{}': {}", logs.display(), e))?;
+            logs.join("modrinther.log")
+        }
+        (None, None) => return Err("Can't determine the data directory for the log file, pass --log-file PATH or --data-dir".into()),
+    };
+    let file = fs::OpenOptions::new().create(true).append(true).open(&path).map_err(|e| {
+        format!("Failed to open log file '{}': {}", path.display(), e)
+    })?;
+    Ok(file)
+}
+
+fn download_options(cli: &Cli) -> DownloadOptions {
+    DownloadOptions {
+        order: cli.order,
+        per_host_connections: cli.per_host as usize,
+        host_connections: cli.host_connections.iter()
+            .map(|setting| HostSetting { host: setting.host.clone(), value: setting.value.get() })
+            .collect(),
+        timeout: cli.timeout.map(Duration::from_secs),
+        host_timeouts: cli.host_timeout.iter()
+            .map(|setting| HostSetting { host: setting.host.clone(), value: Duration::from_secs(setting.value.get()) })
+            .collect(),
+        dns_cache: cli.dns_cache,
+        resolve: cli.resolve.clone(),
+        pool_idle_timeout: cli.pool_idle_timeout.map(Duration::from_secs),
+        pool_max_idle_per_host: cli.pool_max_idle,
+        allowed_hosts: cli.allow_host.clone(),
+        mirrors: cli.mirror.clone(),
+        auth: cli.auth.clone(),
+        connections_per_file: cli.concurrency_per_file as usize,
+        cache: if cli.no_cache {
+            None
+        } else {
+            cli.cache_dir.clone().or_else(|| data_dir(cli).map(|dir| dir.cache_dir())).map(ContentCache::new)
+        },
+        offline: cli.offline,
+        hash: cli.hash,
+        hash_policy: cli.hash_policy,
+        strict: cli.strict,
+        allow_insecure: cli.allow_insecure,
+        max_file_size: cli.max_file_size,
+        progress_batch: cli.progress_batch,
+        max_retries: cli.max_retries,
+        retry_delay: Duration::from_millis(cli.retry_delay),
+        retry_only_transient: cli.retry_only_transient,
+        fastest_mirror: cli.fastest_mirror,
+        on_file: cli.per_file_command.clone().map(per_file_command),
+        net_concurrency: cli.net_concurrency as usize,
+        disk_concurrency: cli.disk_concurrency as usize,
+        auto_concurrency: cli.auto_concurrency,
+        resume: cli.resume,
+        fail_fast: cli.fail_fast,
+        ..DownloadOptions::default()
+    }
+}
+
+// The options that decide how an install goes, for modpack_run.json. --auth is left
+// out, its tokens are secret.
+fn run_settings(cli: &Cli) -> BTreeMap<String, serde_json::Value> {
+    let settings = serde_json::json!({
+        "order": cli.order.name(),
+        "parallel_packs": cli.parallel_packs,
+        "per_host": cli.per_host,
+        "host_connections": cli.host_connections.iter().map(|s| format!("{}={}", s.host, s.value)).collect::<Vec<_>>(),
+        "auto_concurrency": cli.auto_concurrency,
+        "fastest_mirror": cli.fastest_mirror,
+        "per_file_command": cli.per_file_command,
+        "net_concurrency": cli.net_concurrency,
+        "disk_concurrency": cli.disk_concurrency,
+        "concurrency_per_file": cli.concurrency_per_file,
+        "timeout_secs": cli.timeout,
+        "host_timeouts": cli.host_timeout.iter().map(|s| format!("{}={}", s.host, s.value)).collect::<Vec<_>>(),
+        "max_retries": cli.max_retries,
+        "retry_delay_ms": cli.retry_delay,
+        "retry_only_transient": cli.retry_only_transient,
+        "fail_fast": cli.fail_fast,
+        "hash": cli.hash.name(),
+        "hash_policy": cli.hash_policy.name(),
+        "conflict_policy": cli.conflict_policy.map(ConflictPolicy::name),
+        "hashes": cli.hashes,
+        "strict": cli.strict,
+        "allow_host": cli.allow_host,
+        "allow_insecure": cli.allow_insecure,
+        "mirrors": cli.mirror.iter().map(|m| format!("{}={}", m.from, m.to)).collect::<Vec<_>>(),
+        "cache": !cli.no_cache,
+        "offline": cli.offline,
+        "max_file_size": cli.max_file_size,
+        "only": cli.only.iter().map(|kind| kind.dir()).collect::<Vec<_>>(),
+        "allow_projects": cli.allow_projects,
+        "deny_projects": cli.deny_projects,
+        "overrides_include": cli.overrides_include,
+        "overrides_exclude": cli.overrides_exclude,
+        "flatten": cli.flatten,
+        "root_prefix": cli.root_prefix,
+        "loader": cli.loader,
+        "index_name": cli.index_name,
+        "verify_after": cli.verify_after,
+    });
+    match settings {
+        serde_json::Value::Object(map) => map.into_iter().collect(),
+        _ => BTreeMap::new(),
+    }
+}
+
+// --per-file-command: runs `template` through the platform's shell for every installed
+// file, {path} replaced by its path (quoted for that shell), in the directory modrinther
+// runs in. The command's output goes to the log file; a non-zero exit fails the file.
+fn per_file_command(template: String) -> FileHook {
+    FileHook(Arc::new(move |file: &ModFile, path: &Path| {
+        let command = template.replace("{path}", &shell_quote(&path.display().to_string()));
+        let mut shell = if cfg!(windows) {
+            let mut shell = std::process::Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = std::process::Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        let output = shell.arg(&command).stdin(std::process::Stdio::null()).output()
+            .map_err(|e| format!("couldn't run '{}': {}", command, e))?;
+        for line in String::from_utf8_lossy(&output.stdout).lines().chain(String::from_utf8_lossy(&output.stderr).lines()) {
+            debug!("Per-file command for {}: {}", file.path, line);
+        }
+        if output.status.success() {
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.lines().rfind(|line| !line.trim().is_empty()) {
+            Some(last) => Err(format!("'{}' failed ({}): {}", command, output.status, last.trim())),
+            None => Err(format!("'{}' failed ({})", command, output.status)),
+        }
+    }))
+}
+
+// Quotes `arg` as one word for the shell --per-file-command runs in
+fn shell_quote(arg: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", arg)
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+// How the CLI installs a pack into `output_dir`, everything but the client and progress
+fn install_options(cli: &Cli, output_dir: &Path) -> Result<InstallOptions, Box<dyn Error>> {
+    Ok(InstallOptions {
+        download: download_options(cli),
+        existing: if cli.rename_existing {
+            ExistingOutput::Rename
+        } else if cli.fail_if_exists {
+            ExistingOutput::Fail
+        } else {
+            ExistingOutput::Merge
+        },
+        conflicts: cli.conflict_policy.unwrap_or_default(),
+        flatten: cli.flatten,
+        root_prefix: cli.root_prefix.clone(),
+        strict: cli.strict,
+        clean_partials: cli.clean_partials,
+        verify_overrides: cli.verify_overrides,
+        require_overrides: cli.require_overrides,
+        strict_overrides: cli.strict_overrides,
+        verify_after: cli.verify_after,
+        verify_threads: cli.parallel_hash_verify.map(usize::from),
+        overrides_only: cli.overrides_only,
+        only: cli.only.clone(),
+        known_hashes: cli.hashes.as_deref().map(load_hash_list).transpose()?.unwrap_or_default(),
+        projects: ProjectPolicy {
+            allow: cli.allow_projects.as_deref().map(load_project_list).transpose()?,
+            deny: cli.deny_projects.as_deref().map(load_project_list).transpose()?.unwrap_or_default(),
+        },
+        overrides_include: cli.overrides_include.clone(),
+        overrides_exclude: cli.overrides_exclude.clone(),
+        overrides_newer_only: cli.overrides_newer_only,
+        max_depth: Some(cli.max_depth),
+        parallel_overrides: cli.parallel_overrides,
+        expect_minecraft: cli.expect_mc_version.clone(),
+        force: cli.force,
+        write_manifest: cli.manifest,
+        manifest_hashes: ManifestHashes {
+            include: cli.include_hashes_in_manifest,
+            compute: cli.manifest_compute_hashes.clone(),
+        },
+        write_sidecars: cli.write_sidecar_hashes,
+        ..InstallOptions::new(output_dir)
+    })
+}
+
+async fn install_pack(
+    cli: &Cli,
+    input_path: &Path,
+    pack_no: usize,
+    client: &reqwest::Client,
+    multi: &MultiProgress,
+) -> Result<PackOutcome, Box<dyn Error>> {
+    let bars: Arc<dyn ProgressSink> = match cli.progress_format {
+        ProgressFormat::Bars => Arc::new(IndicatifProgress::with_multi(multi)),
+        ProgressFormat::Json => Arc::new(JsonLinesProgress::new(&input_path.display().to_string())),
+    };
+    let profiles_path = launcher_profiles_path(cli)?;
+
+    // The extraction is removed again once the pack is installed (overrides are copied from it), unless kept
+    let (index, output_dir, _extracted) = if cli.resume {
+        (load_resumed(cli, input_path)?, input_path.to_path_buf(), None)
+    } else {
+        let (index, base_dir, extracted) = load_pack(cli, input_path, pack_no, bars.as_ref())?;
+        // Derive the output directory from the template (just the pack name by default)
+        let output_dir = base_dir.join(render_output_template(&cli.output_template, &index, cli.name.as_deref(), cli.ascii_names));
+        check_output_dir(&output_dir, &base_dir, cli)?;
+        (index, output_dir, extracted)
+    };
+    if cli.audit {
+        report_compatibility(&index);
+    }
+    let progress: Arc<dyn ProgressSink> = if cli.no_title || cli.json || cli.progress_format == ProgressFormat::Json {
+        bars
+    } else {
+        Arc::new(TitleProgress::new(bars, &index.name))
+    };
+    let options = InstallOptions {
+        client: Some(client.clone()),
+        progress,
+        run_record: Some(RunRecord::start(&input_path.display().to_string(), run_settings(cli))),
+        conflict_prompt: conflict_prompt(cli, input_path, multi),
+        ..install_options(cli, &output_dir)?
+    };
+    let report = install(&index, &options).await?;
+    if let (false, Some(mut record)) = (report.already_installed, options.run_record.clone()) {
+        record.finish();
+        if let Err(e) = record.save(&report.output_dir) {
+            debug!("Failed to write {}: {}", RunRecord::FILE_NAME, e);
+        }
+    }
+    // Nothing was installed, so the summaries and launcher profile of that earlier run stay
+    let outcome = if report.already_installed {
+        PackOutcome {
+            name: index.name.clone(),
+            output_dir: report.output_dir.clone(),
+            succeeded: report.succeeded.len(),
+            failed: 0,
+            skipped: report.skipped.len(),
+            stats: report.stats,
+            errors: Vec::new(),
+            failed_urls: Vec::new(),
+        }
+    } else if cli.overrides_only {
+        // Overrides only: the mod summaries of the last full install stay as they were
+        overrides_outcome(&index, &report)
+    } else {
+        write_summaries(&index, &report, &options.download, &cli.summary_format, cli.summary_dir.as_deref())?
     };
+    // From here on the downloads are done, so a failure keeps the ones that failed
+    let pack_failed = |message: String| -> Box<dyn Error> {
+        Box::new(PackFailed { message, output_dir: outcome.output_dir.clone(), failed_urls: outcome.failed_urls.clone() })
+    };
+    if cli.fail_fast {
+        let first = report.failed.iter().find(|failed| !matches!(failed.error, InstallError::Cancelled));
+        if let Some(failed) = first {
+            let cancelled = report.failed.iter().filter(|failed| matches!(failed.error, InstallError::Cancelled)).count();
+            return Err(pack_failed(format!(
+                "Stopped after {} failed (--fail-fast), {} other file(s) cancelled: {}",
+                failed.file.path, cancelled, failed.error
+            )));
+        }
+    }
+    if !report.verify_failures.is_empty() {
+        let paths: Vec<&str> = report.verify_failures.iter().map(|failure| failure.path.as_str()).collect();
+        return Err(pack_failed(format!(
+            "Verification after install failed for {} file(s), run modrinther again to re-download them: {}",
+            paths.len(), paths.join(", ")
+        )));
+    }
+
+    if let Some(profiles_path) = profiles_path.filter(|_| !report.already_installed) {
+        if outcome.failed > 0 {
+            warn!("Not adding a launcher profile, {} file(s) failed to install", outcome.failed);
+        } else {
+            // The game directory is where the mods are, under the prefix
+            let game_dir = match &cli.root_prefix {
+                Some(prefix) => join_components(&output_dir, prefix),
+                None => output_dir.clone(),
+            };
+            match write_vanilla_profile(&index, &game_dir, &profiles_path) {
+                Ok(version_id) => {
+                    info!("Added launcher profile '{}' to: {}", index.name, profiles_path.display());
+                    if index.minecraft_version() != Some(version_id.as_str()) {
+                        info!("The profile uses version {}; install the loader with its installer if the launcher doesn't list it", version_id);
+                    }
+                }
+                Err(e) => warn!("Failed to add a launcher profile: {}", e),
+            }
+        }
+    }
+    if let Some(dest) = &cli.export_zip {
+        // A friend would get a pack that doesn't start
+        if outcome.failed > 0 {
+            return Err(pack_failed(format!("Not exporting to '{}', {} file(s) failed to install", dest.display(), outcome.failed)));
+        }
+        info!("Exporting the install to: {}", dest.display());
+        let export_options = ExportOptions { compression: cli.zip_compression, level: cli.zip_level };
+        let exported = export_zip(&output_dir, dest, export_options)?;
+        let ratio = if exported.uncompressed == 0 { 100.0 } else { exported.bytes as f64 / exported.uncompressed as f64 * 100.0 };
+        info!("Exported {} file(s) to {} ({}, {:.1}% of {} uncompressed)",
+            exported.files, dest.display(), format_bytes(exported.bytes as f64), ratio, format_bytes(exported.uncompressed as f64));
+    }
+    Ok(outcome)
+}
+
+// One side's line of the summary's environments section, e.g. "12 required, 3 optional, 1 unsupported"
+fn side_tally(counts: &SideTally) -> String {
+    let mut text = format!("{} required, {} optional, {} unsupported", counts.required, counts.optional, counts.unsupported);
+    if counts.other > 0 {
+        text.push_str(&format!(", {} with an unknown value", counts.other));
+    }
+    text
+}
+
+// modpack_summary.md: the pack's versions as a table, then every file with its size and
+// status, ending with the `tail` lines (the tally)
+fn markdown_summary(index: &ModrinthIndex, report: &InstallReport, tail: &[&str]) -> String {
+    // A `|` would end a table cell early
+    let cell = |text: &str| text.replace('|', "\\|");
+    let (loader, loader_version) = index.loader();
+    let mut md = format!("# {}\n\n", cell(&index.name));
+    md.push_str("| | |\n|---|---|\n");
+    md.push_str(&format!("| Version | {} |\n", cell(&index.version_id)));
+    md.push_str(&format!("| Minecraft | {} |\n", cell(index.minecraft_version().unwrap_or("unknown"))));
+    md.push_str(&format!("| Loader | {} {} |\n", loader, cell(loader_version.unwrap_or("unknown"))));
+    md.push_str(&format!(
+        "| Files | {} installed, {} failed, {} skipped |\n\n",
+        report.succeeded.len(), report.failed.len(), report.skipped.len()
+    ));
+
+    let env = index.env_tally();
+    md.push_str("## Environments\n\n| Side | Required | Optional | Unsupported | Unknown |\n|---|---:|---:|---:|---:|\n");
+    for (side, counts) in [("Client", env.client), ("Server", env.server)] {
+        md.push_str(&format!("| {} | {} | {} | {} | {} |\n", side, counts.required, counts.optional, counts.unsupported, counts.other));
+    }
+    md.push('\n');
+
+    md.push_str("## Files\n\n| File | Size | Status | Time |\n|---|---:|---|---:|\n");
+    for installed in &report.succeeded {
+        md.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            cell(&installed.file.path), format_bytes(installed.file.file_size as f64),
+            installed.fetched.source.name(), format_duration(installed.duration)
+        ));
+    }
+    for failed in &report.failed {
+        let status = match failed.error {
+            InstallError::Removed { .. } => "no longer available".to_string(),
+            _ => format!("failed: {}", failed.error),
+        };
+        md.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            cell(&failed.file.path), format_bytes(failed.file.file_size as f64), cell(&status), format_duration(failed.duration)
+        ));
+    }
+    for skipped in &report.skipped {
+        md.push_str(&format!(
+            "| {} | {} | skipped ({}) | |\n",
+            cell(&skipped.file.path), format_bytes(skipped.file.file_size as f64), skipped.reason
+        ));
+    }
+    if report.failed.iter().any(|failed| matches!(failed.error, InstallError::Removed { .. })) {
+        md.push_str(&format!("\n{}\n", REMOVED_HINT));
+    }
+
+    let variants = installed_disabled_variants(report);
+    if !variants.is_empty() {
+        md.push_str("\n## Disabled variants\n\nInstalled both enabled and as a `.disabled` copy, to toggle by renaming (downloaded once):\n\n");
+        for (enabled, disabled) in &variants {
+            md.push_str(&format!("- `{}` and `{}`\n", enabled, disabled));
+        }
+    }
+    if !report.override_failures.is_empty() {
+        md.push_str("\n## Overrides that failed to copy\n\n");
+        for failed in &report.override_failures {
+            md.push_str(&format!("- `{}`: {}\n", failed.relative_path.display(), failed.error));
+        }
+    }
+    md.push('\n');
+    for line in tail {
+        md.push_str(&format!("{}\n\n", line));
+    }
+    md
+}
+
+// The paths of the files left out of an install, grouped by why, with the overrides
+// the overrides filter left out as "excluded"
+fn skipped_by_reason(report: &InstallReport) -> BTreeMap<&'static str, Vec<String>> {
+    let mut groups: BTreeMap<&'static str, Vec<String>> = BTreeMap::new();
+    for skipped in &report.skipped {
+        groups.entry(skipped.reason.name()).or_default().push(skipped.file.path.clone());
+    }
+    if !report.overrides_skipped.is_empty() {
+        groups.insert("excluded", report.overrides_skipped.iter().map(|path| path.display().to_string()).collect());
+    }
+    groups
+}
+
+// The (enabled, disabled) paths of the mods installed both ways, see disabled_variants
+fn installed_disabled_variants(report: &InstallReport) -> Vec<(String, String)> {
+    let installed: Vec<ModFile> = report.succeeded.iter().map(|installed| installed.file.clone()).collect();
+    disabled_variants(&installed).into_iter()
+        .map(|(enabled, disabled)| (installed[enabled].path.clone(), installed[disabled].path.clone()))
+        .collect()
+}
+
+// The outcome of an --overrides-only install, counting override files instead of downloads
+fn overrides_outcome(index: &ModrinthIndex, report: &InstallReport) -> PackOutcome {
+    if !report.override_failures.is_empty() {
+        info!("Failed to copy {} override file(s):", report.override_failures.len());
+        for failed in &report.override_failures {
+            info!("  - {}: {}", failed.relative_path.display(), failed.error);
+        }
+    }
+    PackOutcome {
+        name: index.name.clone(),
+        output_dir: report.output_dir.clone(),
+        succeeded: report.overrides.len(),
+        failed: report.override_failures.len(),
+        skipped: 0,
+        stats: report.stats,
+        errors: override_errors(report),
+        failed_urls: Vec::new(),
+    }
+}
 
-    let (index, base_dir) = if is_archive_file(&input_path) {
-        // Handle ZIP or MRPACK file
-        println!("Processing archive file: {}", input_path.display());
-        process_archive_file(&input_path)?
-    } else {
-        // Handle JSON file directly
-        let index_content = fs::read_to_string(&input_path).map_err(|e| {
-            format!("Failed to read index file '{}': {}", input_path.display(), e)
-        })?;
-        
-        let mut index: ModrinthIndex = serde_json::from_str(&index_content).map_err(|e| {
-            format!("Failed to parse JSON: {}", e)
-        })?;
+// The overrides that failed to copy, as JSON errors
+fn override_errors(report: &InstallReport) -> Vec<JsonError> {
+    report.override_failures.iter()
+        .map(|failed| JsonError {
+            path: failed.relative_path.display().to_string(),
+            kind: "io".to_string(),
+            message: failed.error.to_string(),
+            transient: false,
+        })
+        .collect()
+}
 
-        // Check if "overrides" folder exists
-        let overrides_path = input_path.parent()
-                                .unwrap_or(Path::new("."))
-                                .join("overrides");
-                                
-        if overrides_path.exists() && overrides_path.is_dir() {
-            println!("Found overrides directory at: {}", overrides_path.display());
-            index.overrides_path = Some(overrides_path);
+// --root-prefix: a plain relative path, returned `/`-separated without a trailing `/`
+fn parse_root_prefix(value: &str) -> Result<String, String> {
+    let mut components = Vec::new();
+    for component in Path::new(value).components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            _ => return Err(format!("'{}' must be a relative path inside the output directory, without '..'", value)),
         }
-        
-        (index, input_path.parent().unwrap_or(Path::new(".")).to_path_buf())
-    };
+    }
+    if components.is_empty() {
+        return Err("the prefix is empty".to_string());
+    }
+    Ok(components.join("/"))
+}
+
+// The launcher profiles file --vanilla-profile writes to: --launcher-profiles, else the
+// official launcher's own, which must already exist
+fn launcher_profiles_path(cli: &Cli) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    if !cli.vanilla_profile {
+        return Ok(None);
+    }
+    if let Some(path) = &cli.launcher_profiles {
+        return Ok(Some(path.clone()));
+    }
+    match default_launcher_dir() {
+        Some(dir) if dir.is_dir() => Ok(Some(dir.join(LAUNCHER_PROFILES_FILE))),
+        Some(dir) => Err(format!("No Minecraft launcher found at '{}', pass --launcher-profiles PATH", dir.display()).into()),
+        None => Err("Can't determine the Minecraft launcher directory, pass --launcher-profiles PATH".into()),
+    }
+}
 
-    // Use the modpack name as the output directory name
-    let pack_name = sanitize_filename(&index.name);
-    let output_dir = base_dir.join(&pack_name);
+// Drag-and-drop (mostly on Windows) can hand over a path with spaces quoted, split across
+// several arguments, or as a percent-encoded file:// URL. Arguments that don't name an
+// existing file are put back together where that yields one; the rest pass through as-is.
+fn normalize_inputs(inputs: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < inputs.len() {
+        if inputs[i].exists() {
+            result.push(inputs[i].clone());
+            i += 1;
+            continue;
+        }
 
-    // Create output directory if it doesn't exist
-    fs::create_dir_all(&output_dir)?;
+        // The shortest run of arguments that names an existing file once joined
+        let found = (i + 1..=inputs.len()).find_map(|end| {
+            let joined = inputs[i..end].iter()
+                .map(|arg| arg.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" ");
+            resolve_dropped_path(&joined).map(|path| (end, path))
+        });
+        match found {
+            Some((end, path)) => {
+                debug!("Interpreted {} argument(s) as the path {}", end - i, path.display());
+                result.push(path);
+                i = end;
+            }
+            None => {
+                result.push(inputs[i].clone());
+                i += 1;
+            }
+        }
+    }
+    result
+}
 
-    println!("Installing modpack: {}", index.name);
-    println!("Output directory: {}", output_dir.display());
-    
-    // Clone dependencies to avoid borrowing issues
-    let deps = index.dependencies.clone();
-    println!("Minecraft version: {}", deps.get("minecraft").unwrap_or(&"unknown".to_string()));
-    
-    // Detect loader type and version
-    let loader_type = if deps.contains_key("fabric-loader") {
-        "Fabric"
-    } else if deps.contains_key("forge") {
-        "Forge"
-    } else if deps.contains_key("quilt-loader") {
-        "Quilt"
+// Undoes quoting and percent-encoding, returning the path if it then exists
+fn resolve_dropped_path(raw: &str) -> Option<PathBuf> {
+    let unquoted = raw.trim().trim_matches(|c| c == '"' || c == '\'');
+    let url_path = unquoted.strip_prefix("file://").unwrap_or(unquoted);
+    let decoded = percent_decode_str(url_path).decode_utf8_lossy();
+    // file:///C:/... leaves a slash in front of the drive letter
+    let decoded = match decoded.strip_prefix('/') {
+        Some(rest) if cfg!(windows) && rest.as_bytes().get(1) == Some(&b':') => rest.to_string(),
+        _ => decoded.to_string(),
+    };
+    [unquoted.to_string(), decoded].into_iter().map(PathBuf::from).find(|path| path.exists())
+}
+
+// Refuses output directories an install would litter: a filesystem root or the home
+// directory, or (when it comes from the pack's name, e.g. an empty or ".." name) the
+// directory the pack itself is in or one of its parents
+fn check_output_dir(output_dir: &Path, input_dir: &Path, cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let output = normalize_path(output_dir)?;
+    let why = if output.parent().is_none() {
+        Some("a filesystem root")
+    } else if BaseDirs::new().is_some_and(|dirs| normalize_path(dirs.home_dir()).is_ok_and(|home| home == output)) {
+        Some("your home directory")
+    } else if normalize_path(input_dir)?.starts_with(&output) && cli.output_template.contains('{') {
+        Some("the directory the pack is in, or one above it (derived from the pack's name)")
     } else {
-        "Unknown"
+        None
     };
-    
-    // Create a separate string for the loader version to avoid temporary value issues
-    let unknown_str = "unknown".to_string();
-    let loader_version = match loader_type {
-        "Fabric" => deps.get("fabric-loader"),
-        "Forge" => deps.get("forge"),
-        "Quilt" => deps.get("quilt-loader"),
-        _ => None
-    }.unwrap_or(&unknown_str);
-    
-    println!("Loader: {} {}", loader_type, loader_version);
-    println!("Total files to download: {}", index.files.len());
-
-    // Copy overrides if they exist
-    if let Some(overrides_path) = &index.overrides_path {
-        println!("Copying overrides...");
-        copy_directory_contents(overrides_path, &output_dir)?;
-    }
-
-    // Setup progress bars
-    let mp = MultiProgress::new();
-    let main_pb = mp.add(ProgressBar::new(index.files.len() as u64));
-    main_pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} files ({percent}%) - {elapsed_precise}")
-        .unwrap()
-        .progress_chars("#>-"));
-
-    let download_pb = mp.add(ProgressBar::new(1));
-    download_pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({percent}%) {wide_msg}")
-        .unwrap()
-        .progress_chars("#>-"));
-    
-    // Track the current downloading file name
-    let current_file = Arc::new(Mutex::new(String::new()));
-    let download_pb = Arc::new(download_pb);
+    match why {
+        Some(why) => Err(format!(
+            "Refusing to install into '{}', which is {}; pass --output-template (or --name) to pick a directory",
+            output.display(), why
+        ).into()),
+        None => Ok(()),
+    }
+}
 
-    // Create a summary for later use
-    let mut summary = String::new();
-    summary.push_str(&format!("Modpack: {}\n", index.name));
-    summary.push_str(&format!("Minecraft version: {}\n", deps.get("minecraft").unwrap_or(&unknown_str)));
-    summary.push_str(&format!("Loader: {} {}\n", loader_type, loader_version));
-    summary.push_str(&format!("Total mods: {}\n\n", index.files.len()));
-    summary.push_str("Installed mods:\n");
-    
-    for file in &index.files {
-        let file_name = Path::new(&file.path).file_name()
-            .unwrap_or_default()
-            .to_string_lossy();
-        summary.push_str(&format!("- {} ({} bytes)\n", file_name, file.file_size));
-    }
-
-    // Process files in parallel
-    let index_arc = Arc::new(index);
-    let output_dir_arc = Arc::new(output_dir);
-    let main_pb_arc = Arc::new(main_pb);
-
-    // Use a maximum of 5 concurrent downloads
-    let results = stream::iter(0..index_arc.files.len())
-        .map(|i| {
-            let index = Arc::clone(&index_arc);
-            let output_dir = Arc::clone(&output_dir_arc);
-            let main_pb = Arc::clone(&main_pb_arc);
-            let download_pb = Arc::clone(&download_pb);
-            let current_file = Arc::clone(&current_file);
-            
-            async move {
-                let file = &index.files[i];
-                let file_name = Path::new(&file.path).file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_string();
-                
-                {
-                    let mut current = current_file.lock().unwrap();
-                    *current = file_name.clone();
-                }
-                
-                download_pb.set_length(file.file_size);
-                download_pb.set_position(0);
-                download_pb.set_message(format!("Downloading {}", file_name));
-                
-                let result = download_file(&file, &output_dir, &download_pb).await;
-                
-                if result.is_ok() {
-                    download_pb.finish_with_message(format!("Downloaded {}", file_name));
-                } else if let Err(e) = &result {
-                    download_pb.finish_with_message(format!("Failed to download {}: {}", file_name, e));
-                }
-                
-                main_pb.inc(1);
-                result
+// -v: shows the versions the pack targets together, with any sign they don't match,
+// before anything is downloaded
+fn report_compatibility(index: &ModrinthIndex) {
+    let (loader, loader_version) = index.loader();
+    info!("Compatibility: Minecraft {} with {} {}",
+        index.minecraft_version().unwrap_or("unknown"), loader, loader_version.unwrap_or("unknown"));
+    for problem in index.compatibility_problems() {
+        warn!("{}", problem);
+    }
+}
+
+// --check-updates: lists the mods with newer versions instead of installing anything
+async fn report_updates(cli: &Cli, client: &reqwest::Client) -> Result<(), Box<dyn Error>> {
+    let mut reports = Vec::new();
+    for (i, input_path) in cli.inputs.iter().enumerate() {
+        let (index, _, _extracted) = load_pack(cli, input_path, i, &NoProgress)?;
+        let (loader, _) = index.loader();
+        info!("Checking {} for updates ({} on Minecraft {})...",
+            index.name, loader, index.minecraft_version().unwrap_or("unknown"));
+        let check = check_updates(&index, client, cli.api_url.trim_end_matches('/')).await?;
+
+        if check.updates.is_empty() {
+            info!("All {} mods found on Modrinth are up to date", check.checked - check.unknown);
+        } else {
+            let width = |column: fn(&ModUpdate) -> &str, title: &str| {
+                check.updates.iter().map(|u| column(u).len()).chain([title.len()]).max().unwrap_or(0)
+            };
+            let path_width = width(|u| &u.path, "Mod");
+            let current_width = width(|u| &u.current_version, "Current");
+            info!("{:<path_width$}  {:<current_width$}  Latest", "Mod", "Current");
+            for update in &check.updates {
+                info!("{:<path_width$}  {:<current_width$}  {}", update.path, update.current_version, update.latest_version);
             }
-        })
-        .buffer_unordered(5) // Max 5 concurrent downloads
-        .collect::<Vec<_>>()
-        .await;
+            info!("{} of {} mods have newer versions", check.updates.len(), check.checked);
+        }
+        if check.unknown > 0 {
+            info!("{} file(s) are not known to Modrinth and weren't checked", check.unknown);
+        }
+        reports.push(serde_json::json!({ "name": index.name, "check": check }));
+    }
 
-    main_pb_arc.finish_with_message("Downloads completed!");
+    if cli.json {
+        println!("{}", serde_json::to_string(&serde_json::json!({ "packs": reports }))?);
+    }
+    Ok(())
+}
 
-    // Count success and failures
-    let success_count = results.iter().filter(|r| r.is_ok()).count();
-    let error_count = results.iter().filter(|r| r.is_err()).count();
-    
-    println!("\nInstallation complete!");
-    println!("Successfully downloaded: {}/{}", success_count, index_arc.files.len());
-    
-    // Create a summary file
-    let summary_path = output_dir_arc.join("modpack_summary.txt");
-    fs::write(&summary_path, summary)?;
-    
-    if error_count > 0 {
-        println!("Failed to download: {}/{}", error_count, index_arc.files.len());
-        println!("Errors:");
-        for (i, result) in results.iter().enumerate() {
-            if let Err(e) = result {
-                println!("  - {}: {}", index_arc.files[i].path, e);
+// --validate: lints each pack without installing it, listing every problem, and fails
+// if any pack has one (or can't be read at all)
+fn validate_packs(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let mut reports = Vec::new();
+    let mut invalid = Vec::new();
+    for input_path in &cli.inputs {
+        info!("Validating {}...", input_path.display());
+        let validation = validate_pack(input_path, &cli.index_name, cli.max_depth, &cli.allow_host)
+            .unwrap_or_else(|e| PackValidation {
+                name: input_path.display().to_string(),
+                problems: vec![PackProblem { path: None, problem: e.to_string() }],
+            });
+        for problem in &validation.problems {
+            match &problem.path {
+                Some(path) => info!("PROBLEM  {}: {}", path, problem.problem),
+                None => info!("PROBLEM  {}", problem.problem),
             }
         }
+        if validation.problems.is_empty() {
+            info!("No problems found in {}", validation.name);
+        } else {
+            info!("{} problem(s) found in {}", validation.problems.len(), validation.name);
+            invalid.push(input_path.display().to_string());
+        }
+        reports.push(serde_json::json!({ "input": input_path, "name": validation.name, "problems": validation.problems }));
     }
 
-    println!("Created summary file at: {}", summary_path.display());
+    if cli.json {
+        println!("{}", serde_json::to_string(&serde_json::json!({ "packs": reports }))?);
+    }
+    if !invalid.is_empty() {
+        return Err(format!("Invalid pack(s): {}", invalid.join(", ")).into());
+    }
+    Ok(())
+}
 
-    // Prevent the window from closing immediately
-    println!("\nPress Enter to exit...");
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
+// --print-tree: shows where each pack's files would go, as a tree, without installing
+fn print_trees(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let mut packs = Vec::new();
+    for (i, input_path) in cli.inputs.iter().enumerate() {
+        let (index, base_dir, _extracted) = load_pack(cli, input_path, i, &NoProgress)?;
+        let output_dir = base_dir.join(render_output_template(&cli.output_template, &index, cli.name.as_deref(), cli.ascii_names));
+        let paths = planned_paths(&index, &install_options(cli, &output_dir)?)?;
+        if cli.json {
+            let paths: Vec<_> = paths.iter()
+                .map(|planned| serde_json::json!({ "path": planned.path, "source": planned.source }))
+                .collect();
+            packs.push(serde_json::json!({ "name": index.name, "output_dir": output_dir, "paths": paths }));
+        } else {
+            info!("{}", render_tree(&output_dir, &paths));
+        }
+    }
 
+    if cli.json {
+        println!("{}", serde_json::to_string(&serde_json::json!({ "packs": packs }))?);
+    }
     Ok(())
 }
 
-// Helper function to check if a file is an archive (ZIP or MRPACK)
-fn is_archive_file(path: &Path) -> bool {
-    if let Some(ext) = path.extension() {
-        let ext_str = ext.to_string_lossy().to_lowercase();
-        return ext_str == "zip" || ext_str == "mrpack";
+// One directory of the --print-tree output
+#[derive(Default)]
+struct TreeNode {
+    dirs: BTreeMap<String, TreeNode>,
+    files: BTreeMap<String, FileSource>,
+}
+
+// `tree`-style lines for the files under `root`, directories first and each level
+// sorted by name, overrides marked as such, ending with a count
+fn render_tree(root: &Path, paths: &[PlannedPath]) -> String {
+    let mut tree = TreeNode::default();
+    for planned in paths {
+        let mut components: Vec<&str> = planned.path.split('/').collect();
+        let name = components.pop().unwrap_or_default();
+        let node = components.into_iter().fold(&mut tree, |node, dir| node.dirs.entry(dir.to_string()).or_default());
+        node.files.insert(name.to_string(), planned.source);
+    }
+
+    fn render(node: &TreeNode, indent: &str, out: &mut String) {
+        let count = node.dirs.len() + node.files.len();
+        let dirs = node.dirs.iter().map(|(name, dir)| (format!("{}/", name), Some(dir)));
+        let files = node.files.iter().map(|(name, source)| match source {
+            FileSource::Override => (format!("{} (override)", name), None),
+            FileSource::Download => (name.clone(), None),
+        });
+        for (i, (label, dir)) in dirs.chain(files).enumerate() {
+            let last = i + 1 == count;
+            out.push_str(&format!("{}{}{}\n", indent, if last { "└── " } else { "├── " }, label));
+            if let Some(dir) = dir {
+                render(dir, &format!("{}{}", indent, if last { "    " } else { "│   " }), out);
+            }
+        }
+    }
+
+    let mut out = format!("{}/\n", root.display());
+    render(&tree, "", &mut out);
+    let overrides = paths.iter().filter(|planned| planned.source == FileSource::Override).count();
+    out.push_str(&format!("{} download(s), {} override file(s)", paths.len() - overrides, overrides));
+    out
+}
+
+// The one bar of a --verify-manifest check, over the files hashed so far; removed
+// again once the check is done, so the report follows the "Verifying" line
+struct VerifyBar(ProgressBar);
+
+impl VerifyBar {
+    fn new() -> Self {
+        let bar = ProgressBar::new(0);
+        bar.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} files hashed ({percent}%) - {elapsed_precise}")
+            .unwrap()
+            .progress_chars("#>-"));
+        VerifyBar(bar)
     }
-    false
 }
 
-fn process_archive_file(archive_path: &Path) -> Result<(ModrinthIndex, PathBuf), Box<dyn Error>> {
-    // Create temp directory for extraction
-    let temp_dir = std::env::temp_dir().join("modrinth_temp");
-    if temp_dir.exists() {
-        fs::remove_dir_all(&temp_dir)?;
+impl ProgressSink for VerifyBar {
+    fn on_verify_entry(&self, _path: &str, done: usize, total: usize) {
+        self.0.set_length(total as u64);
+        self.0.set_position(done as u64);
     }
-    fs::create_dir_all(&temp_dir)?;
-    
-    println!("Extracting archive to temporary directory: {}", temp_dir.display());
-    
-    // Open the archive file (ZIP or MRPACK)
-    let file = fs::File::open(archive_path)?;
-    let mut archive = ZipArchive::new(file)?;
-    
-    // Extract all files
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let outpath = temp_dir.join(file.name());
-        
-        if file.name().ends_with('/') {
-            fs::create_dir_all(&outpath)?;
+}
+
+impl Drop for VerifyBar {
+    fn drop(&mut self) {
+        self.0.finish_and_clear();
+    }
+}
+
+// --verify-manifest: checks each input directory against the manifest instead of
+// installing anything, failing if any of them differs
+fn report_manifest_check(cli: &Cli, manifest_path: &Path) -> Result<(), Box<dyn Error>> {
+    let manifest = Manifest::load(manifest_path)?;
+    let mut reports = Vec::new();
+    let mut mismatched = Vec::new();
+    for dir in &cli.inputs {
+        if !dir.is_dir() {
+            return Err(format!("'{}' is not a directory", dir.display()).into());
+        }
+        info!("Verifying {} against {} ({} files)...", dir.display(), manifest_path.display(), manifest.files.len());
+        let progress: Box<dyn ProgressSink> = match cli.progress_format {
+            ProgressFormat::Bars => Box::new(VerifyBar::new()),
+            ProgressFormat::Json => Box::new(JsonLinesProgress::new(&dir.display().to_string())),
+        };
+        let check = verify_manifest(&manifest, dir, cli.parallel_hash_verify.map(usize::from), progress.as_ref())
+            .map_err(|e| format!("Failed to read '{}': {}", dir.display(), e))?;
+        drop(progress);
+        for path in &check.missing {
+            info!("MISSING  {}", path);
+        }
+        for failure in &check.corrupt {
+            info!("CORRUPT  {}: {}", failure.path, failure.problem);
+        }
+        for path in &check.extra {
+            info!("EXTRA    {}", path);
+        }
+        if check.is_clean() {
+            info!("All {} files match the manifest", check.checked);
         } else {
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(p)?;
-                }
-            }
-            let mut outfile = fs::File::create(&outpath)?;
-            io::copy(&mut file, &mut outfile)?;
+            info!("{} missing, {} corrupt, {} extra", check.missing.len(), check.corrupt.len(), check.extra.len());
+            mismatched.push(dir.display().to_string());
         }
+        reports.push(serde_json::json!({ "dir": dir, "check": check }));
     }
-    
-    // Find the modrinth.index.json file
-    let index_path = find_index_json(&temp_dir)?;
-    
-    // Read and parse the index file
-    let index_content = fs::read_to_string(&index_path).map_err(|e| {
-        format!("Failed to read index file '{}': {}", index_path.display(), e)
-    })?;
-    
-    let mut index: ModrinthIndex = serde_json::from_str(&index_content).map_err(|e| {
-        format!("Failed to parse JSON: {}", e)
-    })?;
-    
-    // Check if "overrides" folder exists in the extracted archive
-    let overrides_path = index_path.parent()
-                           .unwrap_or(Path::new("."))
-                           .join("overrides");
-                           
-    if overrides_path.exists() && overrides_path.is_dir() {
-        println!("Found overrides directory at: {}", overrides_path.display());
-        index.overrides_path = Some(overrides_path);
+
+    if cli.json {
+        println!("{}", serde_json::to_string(&serde_json::json!({ "dirs": reports }))?);
     }
-    
-    Ok((index, archive_path.parent().unwrap_or(Path::new(".")).to_path_buf()))
+    if !mismatched.is_empty() {
+        return Err(format!("Not matching the manifest: {}", mismatched.join(", ")).into());
+    }
+    Ok(())
 }
 
-fn find_index_json(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
-    // First check if modrinth.index.json exists in the root
-    let index_path = dir.join("modrinth.index.json");
-    if index_path.exists() {
-        return Ok(index_path);
+// --compare: lists what differs between two installed directories, failing unless
+// they're identical
+fn report_comparison(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let [a, b] = cli.inputs.as_slice() else {
+        return Err(format!("--compare needs exactly two directories, got {}", cli.inputs.len()).into());
+    };
+    for dir in [a, b] {
+        if !dir.is_dir() {
+            return Err(format!("'{}' is not a directory", dir.display()).into());
+        }
     }
-    
-    // Otherwise, search recursively
-    fn search_recursive(dir: &Path) -> Option<PathBuf> {
-        if let Ok(entries) = fs::read_dir(dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() && path.file_name()?.to_string_lossy() == "modrinth.index.json" {
-                    return Some(path);
-                } else if path.is_dir() {
-                    if let Some(found) = search_recursive(&path) {
-                        return Some(found);
-                    }
-                }
+    info!("Comparing {} with {}...", a.display(), b.display());
+    let comparison = compare_dirs(a, b).map_err(|e| format!("Failed to compare the directories: {}", e))?;
+    for path in &comparison.added {
+        info!("ADDED    {}", path);
+    }
+    for path in &comparison.removed {
+        info!("REMOVED  {}", path);
+    }
+    for path in &comparison.changed {
+        info!("CHANGED  {}", path);
+    }
+
+    if cli.json {
+        println!("{}", serde_json::to_string(&comparison)?);
+    }
+    if !comparison.is_identical() {
+        return Err(format!(
+            "The directories differ: {} added, {} removed, {} changed ({} identical)",
+            comparison.added.len(), comparison.removed.len(), comparison.changed.len(), comparison.identical
+        ).into());
+    }
+    info!("The directories are identical ({} files)", comparison.identical);
+    Ok(())
+}
+
+// --extract-overrides: copies the pack's overrides directories into `dest` as they
+// are, for pack authors to edit, without downloading anything
+fn extract_overrides(cli: &Cli, dest: &Path) -> Result<(), Box<dyn Error>> {
+    let [input_path] = cli.inputs.as_slice() else {
+        return Err(format!("--extract-overrides takes exactly one pack, got {}", cli.inputs.len()).into());
+    };
+    let (index, base_dir, extracted) = load_pack(cli, input_path, 0, &NoProgress)?;
+    // The side variants sit beside overrides/, wherever the pack keeps it
+    let root = index.overrides_path.as_deref().and_then(Path::parent)
+        .or(extracted.as_ref().map(|extracted| extracted.path.as_path()))
+        .unwrap_or(&base_dir);
+
+    let copy_options = CopyOptions {
+        strict: cli.strict,
+        parallel: cli.parallel_overrides,
+        include: cli.overrides_include.clone(),
+        exclude: cli.overrides_exclude.clone(),
+        newer_only: cli.overrides_newer_only,
+        max_depth: Some(cli.max_depth),
+        ..CopyOptions::default()
+    };
+    let mut copied = 0;
+    let mut up_to_date = 0;
+    let mut failed = Vec::new();
+    for name in OVERRIDES_DIRS {
+        let src = root.join(name);
+        if !src.is_dir() {
+            continue;
+        }
+        let report = copy_directory_contents(&src, &dest.join(name), &copy_options, &NoProgress)?;
+        if cli.overrides_newer_only {
+            info!("Extracted {} file(s) from {}/, {} already up to date", report.copied.len(), name, report.up_to_date);
+        } else {
+            info!("Extracted {} file(s) from {}/", report.copied.len(), name);
+        }
+        copied += report.copied.len();
+        up_to_date += report.up_to_date;
+        failed.extend(report.failed.into_iter().map(|failure| (name, failure)));
+    }
+    if copied == 0 && up_to_date == 0 && failed.is_empty() {
+        return Err(format!("'{}' has no overrides", input_path.display()).into());
+    }
+    for (name, failure) in &failed {
+        info!("  - {}/{}: {}", name, failure.relative_path.display(), failure.error);
+    }
+
+    if cli.json {
+        println!("{}", serde_json::to_string(&serde_json::json!({ "dest": dest, "copied": copied, "failed": failed.len() }))?);
+    }
+    if !failed.is_empty() {
+        return Err(format!("Failed to extract {} override file(s)", failed.len()).into());
+    }
+    info!("Extracted {} override file(s) to: {}", copied, dest.display());
+    Ok(())
+}
+
+// --validate-cache: re-hashes the whole download cache, dropping corrupt entries
+fn validate_cache(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let cache = download_options(cli).cache
+        .ok_or("No cache directory could be determined; pass --cache-dir or --data-dir")?;
+    if !cache.dir().is_dir() {
+        return Err(format!("There is no cache at '{}' yet", cache.dir().display()).into());
+    }
+    info!("Validating the cache in: {}", cache.dir().display());
+    let validation = cache.validate(true)
+        .map_err(|e| format!("Failed to validate the cache in '{}': {}", cache.dir().display(), e))?;
+
+    if cli.json {
+        println!("{}", serde_json::to_string(&validation)?);
+    }
+    for path in &validation.corrupt {
+        info!("CORRUPT  {} (removed)", path);
+    }
+    for path in &validation.unrecognized {
+        info!("UNKNOWN  {} (left alone)", path);
+    }
+    info!(
+        "Checked {} cache entries: {} valid, {} corrupt ({} removed)",
+        validation.valid + validation.corrupt.len(), validation.valid, validation.corrupt.len(),
+        format_bytes(validation.corrupt_bytes as f64)
+    );
+    Ok(())
+}
+
+// Where --self-test checks for connectivity, with --mirror applied
+const SELF_TEST_URL: &str = "https://cdn.modrinth.com/";
+// Less free space than this fails --self-test; most packs need a few hundred MB
+const SELF_TEST_MIN_SPACE: u64 = 1024 * 1024 * 1024;
+
+// --self-test: checks what an install depends on, through the same client, directories
+// and archive code a real install uses, and prints a PASS/FAIL line for each
+async fn self_test(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let options = download_options(cli);
+    let mut checks: Vec<(&str, Result<String, String>)> = Vec::new();
+
+    let temp = temp_root(cli).unwrap_or_else(std::env::temp_dir).join("modrinth_temp");
+    let temp_writable = probe_writable(&temp).map_err(|e| format!("{}: {}", temp.display(), e));
+    checks.push(("Temp directory writable", temp_writable.clone().map(|()| temp.display().to_string())));
+    checks.push(("Data directory writable", match data_dir(cli) {
+        Some(dir) => probe_writable(dir.root())
+            .map(|()| dir.root().display().to_string())
+            .map_err(|e| format!("{}: {}", dir.root().display(), e)),
+        None => Err("can't be determined, pass --data-dir".to_string()),
+    }));
+    let current_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    for (name, dir) in [("Free space in temp directory", &temp), ("Free space in current directory", &current_dir)] {
+        checks.push((name, match available_space(dir) {
+            Ok(free) if free < SELF_TEST_MIN_SPACE => Err(format!("only {} free on {}", format_bytes(free as f64), dir.display())),
+            Ok(free) => Ok(format!("{} free on {}", format_bytes(free as f64), dir.display())),
+            Err(e) => Err(format!("{}: {}", dir.display(), e)),
+        }));
+    }
+    checks.push(("Archive decoding", match temp_writable {
+        Ok(()) => decode_fixture(&temp.join("self-test"), cli.extract_concurrency as usize),
+        Err(_) => Err("skipped, the temp directory isn't writable".to_string()),
+    }));
+
+    let url = apply_mirrors(SELF_TEST_URL, &options.mirrors).map_err(|e| e.to_string())?;
+    let client = build_client(&options)?;
+    let started = Instant::now();
+    // Any HTTP answer at all means DNS, TLS and the connection work
+    checks.push(("Connection to download host", match client.head(&url).timeout(Duration::from_secs(10)).send().await {
+        Ok(response) => Ok(format!("{} answered HTTP {} in {}ms", url, response.status().as_u16(), started.elapsed().as_millis())),
+        Err(e) => Err(format!("{}: {}", url, InstallError::from(e))),
+    }));
+
+    let failed = checks.iter().filter(|(_, result)| result.is_err()).count();
+    if cli.json {
+        let checks: Vec<serde_json::Value> = checks.iter()
+            .map(|(name, result)| match result {
+                Ok(detail) => serde_json::json!({ "check": name, "ok": true, "detail": detail }),
+                Err(detail) => serde_json::json!({ "check": name, "ok": false, "detail": detail }),
+            })
+            .collect();
+        println!("{}", serde_json::to_string(&serde_json::json!({ "checks": checks }))?);
+    } else {
+        for (name, result) in &checks {
+            match result {
+                Ok(detail) => info!("PASS  {}: {}", name, detail),
+                Err(detail) => info!("FAIL  {}: {}", name, detail),
             }
         }
-        None
     }
-    
-    if let Some(path) = search_recursive(dir) {
-        Ok(path)
+    if failed > 0 {
+        return Err(format!("{} of {} self-test checks failed", failed, checks.len()).into());
+    }
+    info!("All {} checks passed, modrinther version {}", checks.len(), env!("CARGO_PKG_VERSION"));
+    Ok(())
+}
+
+// Writes a tiny pack (a deflated index and one override) and a gzipped index into
+// `dir`, then reads them back the way an install would
+fn decode_fixture(dir: &Path, concurrency: usize) -> Result<String, String> {
+    let scratch = ScratchDir { path: dir.to_path_buf(), keep: false };
+    let index = serde_json::json!({
+        "formatVersion": 1, "game": "minecraft", "versionId": "1.0.0", "name": "self-test",
+        "files": [], "dependencies": { "minecraft": "1.20.1" },
+    }).to_string();
+    let override_content = b"modrinther self-test\n";
+
+    let write = || -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&scratch.path)?;
+        let mut pack = zip::ZipWriter::new(fs::File::create(scratch.path.join("self-test.mrpack"))?);
+        let deflated = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        pack.start_file(DEFAULT_INDEX_NAME, deflated)?;
+        pack.write_all(index.as_bytes())?;
+        pack.start_file("overrides/config/self-test.txt", deflated)?;
+        pack.write_all(override_content)?;
+        pack.finish()?;
+
+        let mut gzipped = GzEncoder::new(fs::File::create(scratch.path.join("self-test.json.gz"))?, Compression::default());
+        gzipped.write_all(index.as_bytes())?;
+        gzipped.finish()?;
+        Ok(())
+    };
+    write().map_err(|e| format!("couldn't write the test files: {}", e))?;
+
+    let extract_options = ExtractOptions { concurrency, ..ExtractOptions::default() };
+    let (extracted, _) = process_archive_file(&scratch.path.join("self-test.mrpack"), &scratch.path.join("extracted"), &extract_options, &NoProgress)
+        .map_err(|e| format!("zip: {}", e))?;
+    let overrides = extracted.overrides_path.ok_or("zip: the override wasn't extracted")?;
+    if fs::read(overrides.join("config").join("self-test.txt")).ok().as_deref() != Some(&override_content[..]) {
+        return Err("zip: the extracted override differs from what was packed".to_string());
+    }
+    let gzipped = load_index(&scratch.path.join("self-test.json.gz")).map_err(|e| format!("gzip: {}", e))?;
+    if extracted.name != "self-test" || gzipped.name != "self-test" {
+        return Err("the decoded index differs from what was written".to_string());
+    }
+    Ok("zip (deflate) and gzip round-trip".to_string())
+}
+
+// Parses the pack's index, extracting it first if it's an archive
+fn load_pack(
+    cli: &Cli,
+    input_path: &Path,
+    pack_no: usize,
+    progress: &dyn ProgressSink,
+) -> Result<(ModrinthIndex, PathBuf, Option<ScratchDir>), Box<dyn Error>> {
+    let mut extracted = None;
+    let (mut index, base_dir) = if is_archive_file(input_path) {
+        // Handle ZIP or MRPACK file, each pack in its own extraction directory
+        info!("Processing archive file: {}", input_path.display());
+        let extract_options = ExtractOptions {
+            audit: cli.audit,
+            index_name: cli.index_name.clone(),
+            concurrency: cli.extract_concurrency as usize,
+            skip_corrupt: cli.skip_corrupt_entries,
+            max_depth: cli.max_depth,
+            protect_existing: matches!(cli.keep_extracted, Some(Some(_))),
+        };
+        // Without an explicit choice, a system temp dir that's unwritable or too small
+        // gives way to one next to the pack
+        let fallback_dir = input_path.parent().unwrap_or(Path::new(".")).join(format!(".modrinther-temp-{}", pack_no));
+        let (extract_dir, can_fall_back) = match (&cli.keep_extracted, temp_root(cli)) {
+            (Some(Some(dir)), _) => (dir.join(input_path.file_stem().unwrap_or_default()), false),
+            (_, Some(root)) => (root.join("modrinth_temp").join(pack_no.to_string()), false),
+            (_, None) => (std::env::temp_dir().join("modrinth_temp").join(pack_no.to_string()), true),
+        };
+        let extract_dir = match probe_writable(&extract_dir) {
+            Ok(()) => extract_dir,
+            Err(e) if can_fall_back => {
+                warn!("Temp directory {} is not writable ({}), extracting to {} instead", extract_dir.display(), e, fallback_dir.display());
+                fallback_dir.clone()
+            }
+            Err(e) => return Err(format!("Temp directory '{}' is not writable: {}", extract_dir.display(), e).into()),
+        };
+        let extracted = extracted.insert(ScratchDir { path: extract_dir, keep: cli.keep_extracted.is_some() });
+        let result = match process_archive_file(input_path, &extracted.path, &extract_options, progress) {
+            Err(e) if can_fall_back && extracted.path != fallback_dir && e.is::<ExtractionIncomplete>() => {
+                warn!("{}; retrying in {}", e, fallback_dir.display());
+                extracted.path = fallback_dir;
+                process_archive_file(input_path, &extracted.path, &extract_options, progress)?
+            }
+            result => result?,
+        };
+        if extracted.keep {
+            info!("Keeping extracted archive at: {}", extracted.path.display());
+        }
+        result
+    } else if input_path.is_dir() {
+        // An already extracted pack: found and read like an extraction, installed beside it
+        let index_path = match find_index_json(input_path, &cli.index_name, cli.max_depth) {
+            Ok(index_path) => index_path,
+            Err(e) if e.is::<TreeTooDeep>() => return Err(e),
+            Err(_) => return Err(format!("'{}' is a directory without a {}, so it isn't an extracted pack", input_path.display(), cli.index_name).into()),
+        };
+        info!("Processing pack directory: {}", input_path.display());
+        let mut index = load_index(&index_path)?;
+        if let Some(overrides_path) = find_overrides(&index_path, input_path) {
+            info!("Found overrides directory at: {}", overrides_path.display());
+            index.overrides_path = Some(overrides_path);
+        }
+        (index, input_path.to_path_buf())
     } else {
-        Err("Could not find modrinth.index.json in the archive file".into())
+        // Handle JSON file directly
+        let mut index = load_index(input_path)?;
+
+        // Check if "overrides" folder exists
+        let overrides_path = input_path.parent()
+                                .unwrap_or(Path::new("."))
+                                .join("overrides");
+                                
+        if overrides_path.exists() && overrides_path.is_dir() {
+            info!("Found overrides directory at: {}", overrides_path.display());
+            index.overrides_path = Some(overrides_path);
+        }
+        
+        (index, input_path.parent().unwrap_or(Path::new(".")).to_path_buf())
+    };
+    if let Some(loader) = &cli.loader {
+        index.choose_loader(loader)?;
     }
+    Ok((index, base_dir, extracted))
 }
 
-fn sanitize_filename(name: &str) -> String {
-    let invalid_chars = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
-    let mut sanitized = name.to_string();
-    
-    for c in invalid_chars {
-        sanitized = sanitized.replace(c, "_");
+// --resume: the index an earlier install saved into its output directory `dir`
+fn load_resumed(cli: &Cli, dir: &Path) -> Result<ModrinthIndex, Box<dyn Error>> {
+    if !dir.is_dir() {
+        return Err(format!("'{}' is not a directory; --resume takes the output directory of an earlier install", dir.display()).into());
     }
-    
-    sanitized
+    let index_path = dir.join(DEFAULT_INDEX_NAME);
+    if !index_path.is_file() {
+        return Err(format!("'{}' has no {}, so there's nothing to resume; install from the pack instead", dir.display(), DEFAULT_INDEX_NAME).into());
+    }
+    info!("Resuming the install in: {}", dir.display());
+    let mut index = load_index(&index_path)?;
+    if let Some(loader) = &cli.loader {
+        index.choose_loader(loader)?;
+    }
+    Ok(index)
 }
 
-async fn download_file(
-    file: &ModFile, 
-    output_dir: &Path, 
-    progress_bar: &ProgressBar
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let url = &file.downloads[0]; // Use the first download URL
-    let file_path = output_dir.join(&file.path);
-    
-    // Create parent directories if they don't exist
-    if let Some(parent) = file_path.parent() {
-        fs::create_dir_all(parent)?;
+// Whether $MODRINTHER_NONINTERACTIVE asks for --yes: set to anything but empty, 0 or false
+fn noninteractive_env() -> bool {
+    std::env::var("MODRINTHER_NONINTERACTIVE")
+        .is_ok_and(|value| !matches!(value.trim().to_ascii_lowercase().as_str(), "" | "0" | "false"))
+}
+
+// Where archives are extracted when chosen explicitly: --temp-dir, else $MODRINTHER_TEMP
+fn temp_root(cli: &Cli) -> Option<PathBuf> {
+    cli.temp_dir.clone()
+        .or_else(|| std::env::var_os("MODRINTHER_TEMP").filter(|dir| !dir.is_empty()).map(PathBuf::from))
+}
+
+const REMOVED_HINT: &str = "Files that are no longer available were taken down from their host, so the pack is probably outdated. \
+Get a newer version of the pack, or run modrinther --check-updates on it to find replacements.";
+
+// How many of the slowest files the verbose log lists
+const SLOWEST_FILES_LOGGED: usize = 10;
+
+// Prints the final tally and writes modpack_summary.txt/.json/.md (as `formats` asks)
+// into the output directory, and copies them into `summary_dir` if given
+fn write_summaries(
+    index: &ModrinthIndex,
+    report: &InstallReport,
+    download: &DownloadOptions,
+    formats: &[SummaryFormat],
+    summary_dir: Option<&Path>,
+) -> Result<PackOutcome, Box<dyn Error>> {
+    let wants = |format| formats.contains(&format) || formats.contains(&SummaryFormat::All);
+    let output_dir = &report.output_dir;
+    let minecraft_version = index.minecraft_version().unwrap_or("unknown");
+    let (loader_type, loader_version) = index.loader();
+    let loader_version = loader_version.unwrap_or("unknown");
+
+    // Create a summary for later use
+    let mut summary = String::new();
+    summary.push_str(&format!("Modpack: {}\n", index.name));
+    summary.push_str(&format!("Game: {}\n", index.game));
+    summary.push_str(&format!("Minecraft version: {}\n", minecraft_version));
+    summary.push_str(&format!("Loader: {} {}\n", loader_type, loader_version));
+    if report.total_files() == index.files.len() {
+        summary.push_str(&format!("Total mods: {}\n\n", report.total_files()));
+    } else {
+        summary.push_str(&format!("Total mods: {} ({} in the pack)\n\n", report.total_files(), index.files.len()));
     }
-    
-    // Create client and download
-    let client = reqwest::Client::new();
-    let response = client.get(url).send().await?;
-    
-    if !response.status().is_success() {
-        return Err(format!("Failed to download: HTTP {}", response.status()).into());
+    let env = index.env_tally();
+    summary.push_str("Environments:\n");
+    for (side, counts) in [("Client", env.client), ("Server", env.server)] {
+        summary.push_str(&format!("- {}: {}\n", side, side_tally(&counts)));
     }
+    summary.push('\n');
+    summary.push_str("Installed mods:\n");
     
-    // Create file and write data
-    let mut file = File::create(&file_path).await?;
-    let mut stream = response.bytes_stream();
-    
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        file.write_all(&chunk).await?;
-        progress_bar.inc(chunk.len() as u64);
+    for installed in &report.succeeded {
+        summary.push_str(&format!(
+            "- {} ({} bytes, {})\n",
+            installed.file.file_name(), installed.file.file_size, format_duration(installed.duration)
+        ));
+    }
+    // Files taken down from their host are listed apart: retrying won't help, updating the pack might
+    let (removed, failed): (Vec<&FailedFile>, Vec<&FailedFile>) = report.failed.iter()
+        .partition(|failed| matches!(failed.error, InstallError::Removed { .. }));
+    for failed in &failed {
+        summary.push_str(&format!(
+            "- {} ({} bytes) failed after {}\n",
+            failed.file.file_name(), failed.file.file_size, format_duration(failed.duration)
+        ));
+    }
+    for failed in &removed {
+        summary.push_str(&format!("- {} ({} bytes) no longer available\n", failed.file.file_name(), failed.file.file_size));
+    }
+    for skipped in &report.skipped {
+        summary.push_str(&format!("- {} skipped ({})\n", skipped.file.file_name(), skipped.reason));
     }
+    if !removed.is_empty() {
+        summary.push_str(&format!("\n{}\n", REMOVED_HINT));
+    }
+    let variants = installed_disabled_variants(report);
+    if !variants.is_empty() {
+        summary.push_str("\nMods installed both enabled and as a .disabled copy, to toggle by renaming (downloaded once):\n");
+        for (enabled, disabled) in &variants {
+            summary.push_str(&format!("- {} and {}\n", enabled, disabled));
+        }
+    }
+    if !report.override_failures.is_empty() {
+        summary.push_str("\nOverrides that failed to copy:\n");
+        for failed in &report.override_failures {
+            summary.push_str(&format!("- {}: {}\n", failed.relative_path.display(), failed.error));
+        }
+    }
+
+    // Count success and failures
+    let success_count = report.succeeded.len();
+    let error_count = report.failed.len();
+    let stats = report.stats;
+    let elapsed = report.elapsed;
+    let throughput = stats.downloaded_bytes as f64 / elapsed.as_secs_f64().max(0.001);
     
-    file.flush().await?;
+    info!("\nInstallation complete!");
+    info!("Successfully downloaded: {}/{}", success_count, report.total_files());
+    let mut tally = format!("Downloaded {} file(s) ({}) in {} ({}/s)",
+        stats.downloaded_files,
+        format_bytes(stats.downloaded_bytes as f64),
+        format_duration(elapsed),
+        format_bytes(throughput));
+    if stats.cache_hits > 0 {
+        tally.push_str(&format!(", {} from cache ({} saved)", stats.cache_hits, format_bytes(stats.cache_bytes as f64)));
+    }
+    if stats.resumed_files > 0 {
+        tally.push_str(&format!(", {} already done by an earlier run", stats.resumed_files));
+    }
+    if !report.override_failures.is_empty() {
+        tally.push_str(&format!(", {} override file(s) failed to copy", report.override_failures.len()));
+    }
+    info!("{}", tally);
+    let skipped = skipped_by_reason(report);
+    if !report.skipped.is_empty() {
+        let groups: Vec<String> = skipped.iter()
+            .filter(|(reason, _)| **reason != "excluded")
+            .map(|(reason, paths)| format!("{} {}", paths.len(), reason))
+            .collect();
+        info!("Skipped {} file(s): {}", report.skipped.len(), groups.join(", "));
+    }
+    for (reason, paths) in &skipped {
+        debug!("Skipped ({}): {}", reason, paths.join(", "));
+    }
+    if stats.duplicate_urls > 0 {
+        debug!("Requests saved by merging duplicate downloads: {}", stats.duplicate_urls);
+    }
+
+    // Slowest files first, in the verbose log, to spot a slow mirror
+    let mut timings: Vec<(&ModFile, Duration)> = report.succeeded.iter()
+        .map(|installed| (&installed.file, installed.duration))
+        .chain(report.failed.iter().map(|failed| (&failed.file, failed.duration)))
+        .collect();
+    timings.sort_by_key(|&(_, duration)| std::cmp::Reverse(duration));
+    if !timings.is_empty() {
+        debug!("Slowest files:");
+    }
+    for (file, duration) in timings.iter().take(SLOWEST_FILES_LOGGED) {
+        let host = file.downloads.first().and_then(|url| host_of(url)).unwrap_or_else(|| "no URL".to_string());
+        debug!("  {} {} ({})", format_duration(*duration), file.path, host);
+    }
+
+    summary.push_str(&format!("\n{}\n", tally));
+
+    // Unlike the download total, this includes the overrides (and earlier installs' leftovers)
+    let on_disk = report.disk_usage.map(|disk_usage| {
+        let downloads: u64 = report.succeeded.iter().map(|installed| installed.file.file_size).sum();
+        format!(
+            "Size on disk: {} ({} downloads, {} overrides and other files)",
+            format_bytes(disk_usage as f64),
+            format_bytes(downloads as f64),
+            format_bytes(disk_usage.saturating_sub(downloads) as f64)
+        )
+    });
+    if let Some(on_disk) = &on_disk {
+        info!("{}", on_disk);
+        summary.push_str(&format!("{}\n", on_disk));
+    }
     
-    Ok(())
-}
+    // Create a summary file
+    let mut written = Vec::new();
+    if wants(SummaryFormat::Text) {
+        let summary_path = output_dir.join("modpack_summary.txt");
+        fs::write(&summary_path, summary)?;
+        written.push(("summary file", summary_path, "summary.txt"));
+    }
+
+    // And a machine-readable one next to it
+    let errors: Vec<JsonError> = report.failed.iter()
+        .map(|failed| JsonError {
+            path: failed.file.path.clone(),
+            kind: failed.error.kind().to_string(),
+            message: failed.error.to_string(),
+            transient: failed.error.is_transient(),
+        })
+        .collect();
+    let json_summary = JsonSummary {
+        name: index.name.clone(),
+        game: index.game.clone(),
+        minecraft_version: minecraft_version.to_string(),
+        loader: loader_type.to_string(),
+        loader_version: loader_version.to_string(),
+        total_files: report.total_files(),
+        pack_files: index.files.len(),
+        succeeded: success_count,
+        failed: error_count,
+        removed: removed.len(),
+        downloaded_bytes: stats.downloaded_bytes,
+        cache_hits: stats.cache_hits,
+        cache_bytes_saved: stats.cache_bytes,
+        disk_bytes: report.disk_usage,
+        elapsed_secs: elapsed.as_secs_f64(),
+        bytes_per_sec: throughput,
+        errors: errors.clone(),
+        override_errors: override_errors(report),
+        overrides_skipped: report.overrides_skipped.iter().map(|path| path.display().to_string()).collect(),
+        env,
+        disabled_variants: variants.into_iter().map(|(enabled, disabled)| JsonDisabledVariant { enabled, disabled }).collect(),
+        files: report.succeeded.iter()
+            .map(|installed| JsonFile {
+                path: installed.file.path.clone(),
+                status: installed.fetched.source.name(),
+                bytes: installed.fetched.bytes,
+                duration_ms: installed.duration.as_millis(),
+                url: installed.fetched.url.clone(),
+            })
+            .chain(report.failed.iter().map(|failed| JsonFile {
+                path: failed.file.path.clone(),
+                status: "failed",
+                bytes: 0,
+                duration_ms: failed.duration.as_millis(),
+                url: None,
+            }))
+            .collect(),
+        skipped: report.skipped.iter().map(|skipped| JsonSkipped {
+            path: skipped.file.path.clone(),
+            reason: skipped.reason.to_string(),
+        }).collect(),
+        skipped_by_reason: skipped,
+        run: JsonRun {
+            modrinther_version: env!("CARGO_PKG_VERSION"),
+            max_retries: download.max_retries,
+            retry_delay_ms: download.retry_delay.as_millis(),
+            retry_only_transient: download.retry_only_transient,
+        },
+    };
+    if wants(SummaryFormat::Json) {
+        let json_summary_path = output_dir.join("modpack_summary.json");
+        fs::write(&json_summary_path, serde_json::to_string_pretty(&json_summary)?)?;
+        written.push(("JSON summary", json_summary_path, "summary.json"));
+    }
+
+    // And one to paste into an issue or a wiki
+    if wants(SummaryFormat::Markdown) {
+        let mut tail = vec![tally.as_str()];
+        tail.extend(on_disk.as_deref());
+        let markdown_path = output_dir.join("modpack_summary.md");
+        fs::write(&markdown_path, markdown_summary(index, report, &tail))?;
+        written.push(("Markdown summary", markdown_path, "summary.md"));
+    }
 
-fn copy_directory_contents(src: &Path, dst: &Path) -> Result<(), Box<dyn Error>> {
-    if !dst.exists() {
-        fs::create_dir_all(dst)?;
+    if error_count > 0 {
+        info!("Failed to download: {}/{}", error_count, report.total_files());
+    }
+    if !failed.is_empty() {
+        info!("Errors:");
+        for failed in &failed {
+            info!("  - {}: {}", failed.file.path, failed.error);
+        }
+    }
+    if !removed.is_empty() {
+        info!("No longer available:");
+        for failed in &removed {
+            info!("  - {} ({})", failed.file.path, failed.file.downloads.first().map(String::as_str).unwrap_or("no URL"));
+        }
+        info!("{}", REMOVED_HINT);
+    }
+    if !report.override_failures.is_empty() {
+        info!("Failed to copy {} override file(s):", report.override_failures.len());
+        for failed in &report.override_failures {
+            info!("  - {}: {}", failed.relative_path.display(), failed.error);
+        }
     }
 
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let file_type = entry.file_type()?;
-        let src_path = entry.path();
-        let dst_path = dst.join(entry.file_name());
+    for (what, path, _) in &written {
+        info!("Created {} at: {}", what, path.display());
+    }
 
-        if file_type.is_dir() {
-            copy_directory_contents(&src_path, &dst_path)?;
-        } else {
-            fs::copy(&src_path, &dst_path)?;
+    // Central copies, so the summaries of a whole batch can be read side by side
+    if let Some(summary_dir) = summary_dir {
+        fs::create_dir_all(summary_dir)?;
+        let stem = sanitize_filename(&index.name);
+        for (_, source, suffix) in &written {
+            let copy = summary_dir.join(format!("{}-{}", stem, suffix));
+            fs::copy(source, &copy)?;
+            info!("Copied summary to: {}", copy.display());
         }
     }
 
-    Ok(())
-}
\ No newline at end of file
+    Ok(PackOutcome {
+        name: index.name.clone(),
+        output_dir: output_dir.clone(),
+        succeeded: success_count,
+        failed: error_count,
+        skipped: report.skipped.len(),
+        stats,
+        errors,
+        failed_urls: report.failed.iter()
+            .filter_map(|failed| Some((failed.file.downloads.first()?.clone(), failed.file.path.clone())))
+            .collect(),
+    })
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::fsutil::{join_components, CopiedFile};
+use crate::hash::{digest_file, HashAlgorithm};
+use crate::index::ModFile;
+
+/// A record of what an install put on disk, written as `modpack_manifest.json`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    pub name: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ManifestEntry {
+    /// Path relative to the output directory, always with `/` separators
+    pub path: String,
+    pub size: u64,
+    pub sha512: Option<String>,
+    /// Every hash known for the file, by algorithm (`sha1`, `sha512`, ...), with
+    /// [`ManifestHashes::include`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hashes: BTreeMap<String, String>,
+    pub source: FileSource,
+}
+
+/// Which hashes a manifest records per file besides `sha512`.
+#[derive(Debug, Clone, Default)]
+pub struct ManifestHashes {
+    /// Add a `hashes` object to each entry: the index's hashes for downloads (whichever
+    /// algorithms it lists), the copy's SHA-512 for overrides
+    pub include: bool,
+    /// Algorithms computed from the installed files instead, for downloads and
+    /// overrides alike (`Auto` isn't one)
+    pub compute: Vec<HashAlgorithm>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileSource {
+    Download,
+    Override,
+}
+
+impl Manifest {
+    pub const FILE_NAME: &'static str = "modpack_manifest.json";
+
+    pub fn new(name: &str) -> Self {
+        Manifest { name: name.to_string(), files: Vec::new() }
+    }
+
+    // Reads a manifest written by an earlier install
+    pub fn load(path: &Path) -> Result<Manifest, Box<dyn Error>> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            format!("Failed to read manifest '{}': {}", path.display(), e)
+        })?;
+        let manifest = serde_json::from_str(&content).map_err(|e| {
+            format!("Failed to parse manifest '{}': {}", path.display(), e)
+        })?;
+        Ok(manifest)
+    }
+
+    pub fn add_download(&mut self, file: &ModFile) {
+        self.files.push(ManifestEntry {
+            path: file.path.clone(),
+            size: file.file_size,
+            sha512: file.hashes.get("sha512").cloned(),
+            hashes: file.hashes.iter().map(|(algorithm, hash)| (algorithm.clone(), hash.to_ascii_lowercase())).collect(),
+            source: FileSource::Download,
+        });
+    }
+
+    pub fn add_override(&mut self, file: &CopiedFile) {
+        self.files.push(ManifestEntry {
+            path: portable_path(&file.relative_path),
+            size: file.size,
+            sha512: file.sha512.clone(),
+            hashes: file.sha512.iter().map(|hash| ("sha512".to_string(), hash.clone())).collect(),
+            source: FileSource::Override,
+        });
+    }
+
+    // Settles what each entry's `hashes` holds: nothing without `include`, otherwise
+    // what the index (or the copy) gave, with the `compute` algorithms hashed again from
+    // the files in `dir`
+    pub fn apply_hashes(&mut self, dir: &Path, hashes: &ManifestHashes) -> io::Result<()> {
+        if !hashes.include {
+            self.files.iter_mut().for_each(|entry| entry.hashes.clear());
+            return Ok(());
+        }
+        if hashes.compute.is_empty() {
+            return Ok(());
+        }
+        self.files.par_iter_mut().try_for_each(|entry| {
+            let digests = digest_file(&join_components(dir, &entry.path))?;
+            for algorithm in &hashes.compute {
+                let digest = match algorithm {
+                    HashAlgorithm::Sha1 => &digests.sha1,
+                    HashAlgorithm::Sha512 | HashAlgorithm::Auto => &digests.sha512,
+                };
+                entry.hashes.insert(algorithm.name().to_string(), digest.clone());
+            }
+            Ok(())
+        })
+    }
+}
+
+pub(crate) fn portable_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
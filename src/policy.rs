@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::index::ModFile;
+
+/// Which Modrinth projects a pack may install, from `--allow-projects` and
+/// `--deny-projects` lists. Files are told apart by the project ID in their Modrinth
+/// CDN URL (`cdn.modrinth.com/data/<project>/versions/...`).
+#[derive(Debug, Clone, Default)]
+pub struct ProjectPolicy {
+    /// If set, only these projects are installed; everything else is skipped
+    pub allow: Option<HashSet<String>>,
+    /// Projects that make the install fail if the pack includes them
+    pub deny: HashSet<String>,
+}
+
+/// What a [`ProjectPolicy`] says about one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allowed,
+    /// Not on the allowlist (or not from Modrinth at all, with an allowlist)
+    NotAllowed,
+    Denied,
+}
+
+impl ProjectPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_none() && self.deny.is_empty()
+    }
+
+    // A denylist wins over the allowlist. Files without a project ID can't be on either
+    // list, so they are only installed if there is no allowlist.
+    pub fn decide(&self, file: &ModFile) -> Decision {
+        let project = modrinth_project_id(file);
+        if project.as_ref().is_some_and(|project| self.deny.contains(project)) {
+            return Decision::Denied;
+        }
+        match &self.allow {
+            Some(allow) if !project.is_some_and(|project| allow.contains(&project)) => Decision::NotAllowed,
+            _ => Decision::Allowed,
+        }
+    }
+}
+
+// Reads a list of project IDs, one per line; blank lines and `#` comments are ignored.
+// Anything that can't be an ID (a pasted URL, two IDs on a line) is an error rather
+// than an entry no file ever matches.
+pub fn load_project_list(path: &Path) -> Result<HashSet<String>, Box<dyn Error>> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        format!("Failed to read project list '{}': {}", path.display(), e)
+    })?;
+    let mut ids = HashSet::new();
+    for (number, line) in content.lines().enumerate() {
+        let id = line.split('#').next().unwrap_or_default().trim();
+        if id.is_empty() {
+            continue;
+        }
+        if !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("{}:{}: '{}' is not a Modrinth project ID", path.display(), number + 1, id).into());
+        }
+        ids.insert(id.to_string());
+    }
+    Ok(ids)
+}
+
+// The Modrinth project a file comes from, as encoded in its first download URL
+pub fn modrinth_project_id(file: &ModFile) -> Option<String> {
+    let url = reqwest::Url::parse(file.downloads.first()?).ok()?;
+    if url.host_str() != Some("cdn.modrinth.com") {
+        return None;
+    }
+    let mut segments = url.path_segments()?;
+    match (segments.next(), segments.next()) {
+        (Some("data"), Some(project)) if !project.is_empty() => Some(project.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn project_lists_are_parsed_strictly() {
+        let dir = temp_dir("project-list");
+        let list = dir.join("allow.txt");
+        fs::write(&list, "# curated\nAANobbMI\n\n  P7dR8mSH  # fabric-api\ngvQqBUqZ\n").unwrap();
+        let ids = load_project_list(&list).unwrap();
+        assert_eq!(ids, HashSet::from(["AANobbMI", "P7dR8mSH", "gvQqBUqZ"].map(String::from)));
+
+        for bad in ["https://modrinth.com/mod/sodium\n", "AANobbMI P7dR8mSH\n"] {
+            fs::write(&list, bad).unwrap();
+            let error = load_project_list(&list).unwrap_err();
+            assert!(error.to_string().contains(":1:"), "{}", error);
+        }
+        assert!(load_project_list(&dir.join("missing.txt")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
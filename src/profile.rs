@@ -0,0 +1,79 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::{json, Map, Value};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::fsutil::sanitize_filename;
+use crate::index::ModrinthIndex;
+
+pub const LAUNCHER_PROFILES_FILE: &str = "launcher_profiles.json";
+
+// The official launcher's data directory (.minecraft), if it can be determined
+pub fn default_launcher_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join(".minecraft"))
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support").join("minecraft"))
+    } else {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".minecraft"))
+    }
+}
+
+// The launcher version a pack runs on, named the way each loader's installer names it
+// (e.g. "fabric-loader-0.15.0-1.20.1"), or None without a Minecraft version
+pub fn launcher_version_id(index: &ModrinthIndex) -> Option<String> {
+    let minecraft = index.minecraft_version()?;
+    let id = match index.loader() {
+        ("Fabric", Some(loader)) => format!("fabric-loader-{}-{}", loader, minecraft),
+        ("Quilt", Some(loader)) => format!("quilt-loader-{}-{}", loader, minecraft),
+        ("Forge", Some(loader)) => format!("{}-forge-{}", minecraft, loader),
+        ("NeoForge", Some(loader)) => format!("neoforge-{}", loader),
+        _ => minecraft.to_string(),
+    };
+    Some(id)
+}
+
+// Adds (or updates) a profile for the pack installed in `game_dir` to the launcher
+// profiles file at `profiles_path`, keeping every other profile and setting. Returns
+// the launcher version the profile uses.
+pub fn write_vanilla_profile(index: &ModrinthIndex, game_dir: &Path, profiles_path: &Path) -> Result<String, Box<dyn Error>> {
+    let version_id = launcher_version_id(index).ok_or("The pack doesn't name a Minecraft version, can't create a launcher profile")?;
+    let game_dir = std::path::absolute(game_dir)?;
+
+    let mut profiles: Value = match fs::read_to_string(profiles_path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| {
+            format!("Failed to parse launcher profiles '{}': {}", profiles_path.display(), e)
+        })?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => json!({ "profiles": {}, "settings": {}, "version": 3 }),
+        Err(e) => return Err(format!("Failed to read launcher profiles '{}': {}", profiles_path.display(), e).into()),
+    };
+    let entries = profiles.as_object_mut()
+        .ok_or_else(|| format!("Launcher profiles '{}' is not a JSON object", profiles_path.display()))?
+        .entry("profiles")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| format!("'profiles' in '{}' is not a JSON object", profiles_path.display()))?;
+
+    // Keyed by the pack name, so reinstalling the pack updates its profile instead of adding another
+    let now = OffsetDateTime::now_utc().format(&Rfc3339)?;
+    let key = format!("modrinther-{}", sanitize_filename(&index.name));
+    let profile = entries.entry(key).or_insert_with(|| json!({ "created": now, "icon": "Furnace", "type": "custom" }));
+    if let Some(profile) = profile.as_object_mut() {
+        profile.insert("name".to_string(), json!(index.name));
+        profile.insert("gameDir".to_string(), json!(game_dir));
+        profile.insert("lastVersionId".to_string(), json!(version_id));
+        profile.insert("lastUsed".to_string(), json!(now));
+    }
+
+    // Write under a temporary name, so the launcher never reads a torn file
+    if let Some(parent) = profiles_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp = profiles_path.with_extension("json.tmp");
+    fs::write(&temp, serde_json::to_string_pretty(&profiles)?)?;
+    fs::rename(&temp, profiles_path)?;
+    Ok(version_id)
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use console::Term;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde_json::{json, Value};
+
+/// Receives progress events from the installer.
+///
+/// The CLI renders these with indicatif ([`IndicatifProgress`]); other
+/// frontends can implement the trait to drive their own UI. Every method has
+/// an empty default so implementors only handle what they care about.
+/// `on_bytes` is called for every batch of received bytes, so keep it cheap.
+pub trait ProgressSink: Send + Sync {
+    /// A download phase for `total_files` files is about to start.
+    fn on_start(&self, _total_files: usize) {}
+    /// Entry `done` of `total` was extracted from the archive. May be called from
+    /// several threads at once.
+    fn on_extract_entry(&self, _name: &str, _done: usize, _total: usize) {}
+    /// Override `done` of `total` (`path`, relative to the output directory) was copied.
+    /// May be called from several threads at once.
+    fn on_copy_entry(&self, _path: &str, _done: usize, _total: usize) {}
+    /// Installed file `done` of `total` (`path`, relative to the directory checked) was
+    /// re-read and hashed by a verification. May be called from several threads at once.
+    fn on_verify_entry(&self, _path: &str, _done: usize, _total: usize) {}
+    /// The file at `path` (relative to the output directory) started downloading.
+    fn on_file_start(&self, _path: &str, _size: u64) {}
+    /// `bytes` more bytes of `path` were written.
+    fn on_bytes(&self, _path: &str, _bytes: u64) {}
+    /// Nothing has arrived for `path` from `host` for `idle`; repeated about every
+    /// second for as long as the download stays stalled.
+    fn on_stalled(&self, _path: &str, _host: &str, _idle: Duration) {}
+    /// `path` finished; `error` is set if it failed.
+    fn on_file_done(&self, _path: &str, _error: Option<&str>) {}
+    /// All downloads finished.
+    fn on_complete(&self) {}
+}
+
+/// A sink that ignores every event.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// The terminal progress bars used by the CLI.
+pub struct IndicatifProgress {
+    _multi: MultiProgress,
+    main_pb: ProgressBar,
+    download_pb: ProgressBar,
+    /// The download bar shows a "waiting on" message, to be replaced once bytes flow again
+    stalled: AtomicBool,
+}
+
+impl IndicatifProgress {
+    pub fn new() -> Self {
+        Self::with_multi(&MultiProgress::new())
+    }
+
+    // Adds the bars to an existing MultiProgress, so several installs can share one display
+    pub fn with_multi(multi: &MultiProgress) -> Self {
+        let main_pb = multi.add(ProgressBar::new(0));
+        main_pb.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} files ({percent}%) - {elapsed_precise}")
+            .unwrap()
+            .progress_chars("#>-"));
+
+        let download_pb = multi.add(ProgressBar::new(1));
+        download_pb.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({percent}%) {wide_msg}")
+            .unwrap()
+            .progress_chars("#>-"));
+
+        IndicatifProgress { _multi: multi.clone(), main_pb, download_pb, stalled: AtomicBool::new(false) }
+    }
+}
+
+impl Default for IndicatifProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn display_name(path: &str) -> String {
+    Path::new(path).file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+impl ProgressSink for IndicatifProgress {
+    fn on_start(&self, total_files: usize) {
+        self.main_pb.set_length(total_files as u64);
+        self.main_pb.set_position(0);
+        self.main_pb.reset_elapsed();
+    }
+
+    fn on_copy_entry(&self, _path: &str, done: usize, total: usize) {
+        self.main_pb.set_length(total as u64);
+        self.main_pb.set_position(done as u64);
+    }
+
+    fn on_verify_entry(&self, _path: &str, done: usize, total: usize) {
+        self.main_pb.set_length(total as u64);
+        self.main_pb.set_position(done as u64);
+    }
+
+    fn on_file_start(&self, path: &str, size: u64) {
+        self.download_pb.set_length(size);
+        self.download_pb.set_position(0);
+        self.download_pb.set_message(format!("Downloading {}", display_name(path)));
+    }
+
+    fn on_bytes(&self, path: &str, bytes: u64) {
+        self.download_pb.inc(bytes);
+        if self.stalled.swap(false, Ordering::Relaxed) {
+            self.download_pb.set_message(format!("Downloading {}", display_name(path)));
+        }
+    }
+
+    fn on_stalled(&self, path: &str, host: &str, idle: Duration) {
+        self.stalled.store(true, Ordering::Relaxed);
+        self.download_pb.set_message(format!("{}: waiting on {}... ({}s)", display_name(path), host, idle.as_secs()));
+        self.download_pb.tick();
+    }
+
+    fn on_file_done(&self, path: &str, error: Option<&str>) {
+        match error {
+            None => self.download_pb.finish_with_message(format!("Downloaded {}", display_name(path))),
+            Some(e) => self.download_pb.finish_with_message(format!("Failed to download {}: {}", display_name(path), e)),
+        }
+        self.main_pb.inc(1);
+    }
+
+    fn on_complete(&self) {
+        self.main_pb.finish_with_message("Downloads completed!");
+    }
+}
+
+/// Mirrors download progress into the terminal window title
+/// (e.g. "modrinther: 83/210 - PackName"), passing every event on to `inner`.
+pub struct TitleProgress {
+    inner: Arc<dyn ProgressSink>,
+    label: String,
+    total: AtomicUsize,
+    done: AtomicUsize,
+}
+
+impl TitleProgress {
+    pub fn new(inner: Arc<dyn ProgressSink>, label: &str) -> Self {
+        TitleProgress {
+            inner,
+            label: label.to_string(),
+            total: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+        }
+    }
+
+    fn update_title(&self, done: usize) {
+        let total = self.total.load(Ordering::Relaxed);
+        Term::stdout().set_title(format!("modrinther: {}/{} - {}", done, total, self.label));
+    }
+
+    // Clears the title again (terminals then fall back to their default)
+    pub fn reset_title() {
+        Term::stdout().set_title("");
+    }
+}
+
+impl ProgressSink for TitleProgress {
+    fn on_start(&self, total_files: usize) {
+        self.total.store(total_files, Ordering::Relaxed);
+        self.done.store(0, Ordering::Relaxed);
+        self.update_title(0);
+        self.inner.on_start(total_files);
+    }
+
+    fn on_extract_entry(&self, name: &str, done: usize, total: usize) {
+        self.inner.on_extract_entry(name, done, total);
+    }
+
+    fn on_copy_entry(&self, path: &str, done: usize, total: usize) {
+        self.inner.on_copy_entry(path, done, total);
+    }
+
+    fn on_verify_entry(&self, path: &str, done: usize, total: usize) {
+        self.inner.on_verify_entry(path, done, total);
+    }
+
+    fn on_file_start(&self, path: &str, size: u64) {
+        self.inner.on_file_start(path, size);
+    }
+
+    fn on_bytes(&self, path: &str, bytes: u64) {
+        self.inner.on_bytes(path, bytes);
+    }
+
+    fn on_stalled(&self, path: &str, host: &str, idle: Duration) {
+        self.inner.on_stalled(path, host, idle);
+    }
+
+    fn on_file_done(&self, path: &str, error: Option<&str>) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.update_title(done);
+        self.inner.on_file_done(path, error);
+    }
+
+    fn on_complete(&self) {
+        self.inner.on_complete();
+    }
+}
+
+/// How the CLI shows progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// Terminal progress bars ([`IndicatifProgress`])
+    #[default]
+    Bars,
+    /// One JSON object per event on stderr ([`JsonLinesProgress`])
+    Json,
+}
+
+impl FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bars" => Ok(ProgressFormat::Bars),
+            "json" => Ok(ProgressFormat::Json),
+            _ => Err(format!("unknown progress format '{}', expected bars or json", s)),
+        }
+    }
+}
+
+// `bytes` events for one file are written at most this often
+const JSON_BYTES_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Writes every event to stderr as one JSON object per line, for a parent process
+/// driving its own progress UI. Each object has an `event` field (`start`, `extract`,
+/// `copy`, `verify`, `file_start`, `bytes`, `stalled`, `file_done` or `complete`) and the
+/// `pack` it belongs to; `bytes` events are throttled per file. A frontend using it
+/// sends its log messages and a fatal error through [`JsonLinesProgress::log`] and
+/// [`JsonLinesProgress::error`], so nothing but JSON reaches stderr.
+pub struct JsonLinesProgress {
+    pack: String,
+    /// Bytes received so far and when they were last reported, per file in flight
+    files: Mutex<HashMap<String, (u64, Instant)>>,
+}
+
+impl JsonLinesProgress {
+    pub fn new(pack: &str) -> Self {
+        JsonLinesProgress { pack: pack.to_string(), files: Mutex::new(HashMap::new()) }
+    }
+
+    fn emit(&self, event: &str, mut fields: Value) {
+        fields["pack"] = json!(self.pack);
+        write_event(event, fields);
+    }
+
+    /// Writes a `log` event: a log message at `level` (`error`, `warn` or `info`), not
+    /// tied to one pack
+    pub fn log(level: &str, message: &str) {
+        write_event("log", json!({ "level": level, "message": message }));
+    }
+
+    /// Writes an `error` event: the error the run stopped with
+    pub fn error(message: &str) {
+        write_event("error", json!({ "message": message }));
+    }
+}
+
+fn write_event(event: &str, mut fields: Value) {
+    fields["event"] = json!(event);
+    // One locked write per line, so events from parallel downloads never interleave
+    let _ = writeln!(io::stderr().lock(), "{}", fields);
+}
+
+impl ProgressSink for JsonLinesProgress {
+    fn on_start(&self, total_files: usize) {
+        self.emit("start", json!({ "total_files": total_files }));
+    }
+
+    fn on_extract_entry(&self, name: &str, done: usize, total: usize) {
+        self.emit("extract", json!({ "name": name, "done": done, "total": total }));
+    }
+
+    fn on_copy_entry(&self, path: &str, done: usize, total: usize) {
+        self.emit("copy", json!({ "path": path, "done": done, "total": total }));
+    }
+
+    fn on_verify_entry(&self, path: &str, done: usize, total: usize) {
+        self.emit("verify", json!({ "path": path, "done": done, "total": total }));
+    }
+
+    fn on_file_start(&self, path: &str, size: u64) {
+        self.files.lock().unwrap().insert(path.to_string(), (0, Instant::now()));
+        self.emit("file_start", json!({ "path": path, "size": size }));
+    }
+
+    fn on_bytes(&self, path: &str, bytes: u64) {
+        let done = {
+            let mut files = self.files.lock().unwrap();
+            let (done, last) = files.entry(path.to_string()).or_insert((0, Instant::now()));
+            *done += bytes;
+            if last.elapsed() < JSON_BYTES_INTERVAL {
+                return;
+            }
+            *last = Instant::now();
+            *done
+        };
+        self.emit("bytes", json!({ "path": path, "done": done }));
+    }
+
+    fn on_stalled(&self, path: &str, host: &str, idle: Duration) {
+        self.emit("stalled", json!({ "path": path, "host": host, "idle_secs": idle.as_secs() }));
+    }
+
+    fn on_file_done(&self, path: &str, error: Option<&str>) {
+        let done = self.files.lock().unwrap().remove(path).map(|(done, _)| done).unwrap_or(0);
+        match error {
+            None => self.emit("file_done", json!({ "path": path, "status": "ok", "bytes": done })),
+            Some(error) => self.emit("file_done", json!({ "path": path, "status": "failed", "bytes": done, "error": error })),
+        }
+    }
+
+    fn on_complete(&self) {
+        self.emit("complete", json!({}));
+    }
+}
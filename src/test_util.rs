@@ -0,0 +1,27 @@
+// Helpers shared by the unit tests of several modules
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::index::ModFile;
+
+// A fresh, empty directory under the system temp directory, named after the test and
+// this process so parallel test runs don't share it
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("modrinther-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// An index entry for `path`, downloaded from `url`, of `size` bytes with `hashes`
+// (algorithm, hex digest)
+pub(crate) fn mod_file(path: &str, url: &str, hashes: &[(&str, &str)], size: u64) -> ModFile {
+    ModFile {
+        downloads: vec![url.to_string()],
+        env: HashMap::new(),
+        file_size: size,
+        hashes: hashes.iter().map(|(algorithm, digest)| (algorithm.to_string(), digest.to_string())).collect(),
+        path: path.to_string(),
+    }
+}
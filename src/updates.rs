@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+use log::debug;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::download::{host_of, retry_after};
+use crate::index::{ModFile, ModrinthIndex};
+
+pub const MODRINTH_API: &str = "https://api.modrinth.com/v2";
+
+// Hashes sent per API request
+const BATCH_SIZE: usize = 500;
+// How often a rate-limited API request is retried
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+
+#[derive(Debug, Deserialize)]
+struct ApiVersion {
+    id: String,
+    project_id: String,
+    version_number: String,
+}
+
+/// A pack file that has a newer version on Modrinth for the pack's
+/// Minecraft version and loader.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModUpdate {
+    pub path: String,
+    pub project_id: String,
+    pub current_version: String,
+    pub latest_version: String,
+    pub latest_version_id: String,
+}
+
+/// The result of [`check_updates`].
+#[derive(Debug, Default, Serialize)]
+pub struct UpdateCheck {
+    pub updates: Vec<ModUpdate>,
+    /// Files looked up on Modrinth
+    pub checked: usize,
+    /// Looked up, but Modrinth doesn't know the file (e.g. it was repackaged)
+    pub unknown: usize,
+}
+
+// Asks the Modrinth API, by file hash, which of the pack's Modrinth-hosted files have
+// newer versions. Read-only: nothing is downloaded or changed.
+pub async fn check_updates(
+    index: &ModrinthIndex,
+    client: &reqwest::Client,
+    api_base: &str,
+) -> Result<UpdateCheck, Box<dyn Error>> {
+    let game_version = index.minecraft_version().ok_or("The pack doesn't name a Minecraft version")?;
+    let loader = match index.loader() {
+        ("Unknown", _) => return Err("The pack doesn't name a known loader".into()),
+        (loader, _) => loader.to_lowercase(),
+    };
+
+    // Only files served from Modrinth's CDN can be looked up; group them by hash algorithm
+    let mut by_algorithm: HashMap<&str, Vec<(&ModFile, String)>> = HashMap::new();
+    for file in &index.files {
+        let on_modrinth = file.downloads.first()
+            .and_then(|url| host_of(url))
+            .is_some_and(|host| host == "cdn.modrinth.com");
+        if !on_modrinth {
+            continue;
+        }
+        if let Some(algorithm) = ["sha512", "sha1"].into_iter().find(|a| file.hashes.contains_key(*a)) {
+            by_algorithm.entry(algorithm).or_default().push((file, file.hashes[algorithm].to_lowercase()));
+        }
+    }
+
+    let mut check = UpdateCheck::default();
+    for (algorithm, files) in by_algorithm {
+        for batch in files.chunks(BATCH_SIZE) {
+            let hashes: Vec<&str> = batch.iter().map(|(_, hash)| hash.as_str()).collect();
+            let current: HashMap<String, ApiVersion> = post_json(
+                client,
+                &format!("{}/version_files", api_base),
+                &json!({ "hashes": hashes, "algorithm": algorithm }),
+            ).await?;
+            let latest: HashMap<String, ApiVersion> = post_json(
+                client,
+                &format!("{}/version_files/update", api_base),
+                &json!({ "hashes": hashes, "algorithm": algorithm, "loaders": [loader], "game_versions": [game_version] }),
+            ).await?;
+
+            for (file, hash) in batch {
+                check.checked += 1;
+                let Some(current) = current.get(hash) else {
+                    check.unknown += 1;
+                    continue;
+                };
+                if let Some(latest) = latest.get(hash).filter(|latest| latest.id != current.id) {
+                    check.updates.push(ModUpdate {
+                        path: file.path.clone(),
+                        project_id: current.project_id.clone(),
+                        current_version: current.version_number.clone(),
+                        latest_version: latest.version_number.clone(),
+                        latest_version_id: latest.id.clone(),
+                    });
+                }
+            }
+        }
+    }
+    check.updates.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(check)
+}
+
+// POSTs a JSON body, waiting out rate limits (429) as long as the API asks
+async fn post_json<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    body: &serde_json::Value,
+) -> Result<T, Box<dyn Error>> {
+    let mut retries = 0;
+    loop {
+        let response = client.post(url).json(body).send().await?;
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS && retries < MAX_RATE_LIMIT_RETRIES {
+            // Modrinth announces when the rate limit window resets, in seconds
+            let delay = retry_after(&response)
+                .or_else(|| {
+                    response.headers().get("x-ratelimit-reset")
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.trim().parse().ok())
+                        .map(Duration::from_secs)
+                })
+                .unwrap_or(Duration::from_secs(1));
+            retries += 1;
+            debug!("Modrinth API rate limit reached, waiting {}s", delay.as_secs());
+            tokio::time::sleep(delay).await;
+            continue;
+        }
+        if !status.is_success() {
+            return Err(format!("Modrinth API request to {} failed: HTTP {}", url, status).into());
+        }
+        return Ok(response.json().await?);
+    }
+}
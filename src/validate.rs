@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::archive::{find_index_json, find_overrides, is_archive_file, read_packed_index};
+use crate::download::is_host_allowed;
+use crate::fsutil::is_plain_relative;
+use crate::hash::{hash_problems, HashAlgorithm};
+use crate::index::{case_collisions, load_index, ModrinthIndex};
+use crate::verify::list_files;
+
+// The `env` values the Modrinth format defines for each side
+const ENV_VALUES: [&str; 3] = ["required", "optional", "unsupported"];
+
+/// Something wrong with a pack, found by [`validate_pack`].
+#[derive(Debug, Clone, Serialize)]
+pub struct PackProblem {
+    /// The file (or archive entry) it's about, if it's about one
+    pub path: Option<String>,
+    pub problem: String,
+}
+
+impl PackProblem {
+    fn pack(problem: impl Into<String>) -> Self {
+        PackProblem { path: None, problem: problem.into() }
+    }
+
+    fn file(path: &str, problem: impl Into<String>) -> Self {
+        PackProblem { path: Some(path.to_string()), problem: problem.into() }
+    }
+}
+
+/// What [`validate_pack`] found in one pack.
+#[derive(Debug, Clone, Serialize)]
+pub struct PackValidation {
+    pub name: String,
+    pub problems: Vec<PackProblem>,
+}
+
+// Checks a pack (archive, extracted directory or bare index, as the installer takes
+// them) the way an install would before downloading anything, but reports every
+// problem instead of stopping at the first, and reads archives in place: nothing is
+// fetched or written. An index that can't be found or parsed is an error.
+pub fn validate_pack(input: &Path, index_name: &str, max_depth: usize, allowed_hosts: &[String]) -> Result<PackValidation, Box<dyn Error>> {
+    let mut problems = Vec::new();
+    let mut overrides = Vec::new();
+    let index = if is_archive_file(input) {
+        let (index, index_entry, entries) = read_packed_index(input, index_name)?;
+        // Overrides sit beside the index, wherever it is in the archive
+        let prefix = format!("{}overrides/", &index_entry[..index_entry.len() - index_name.len()]);
+        for entry in &entries {
+            if !is_plain_relative(entry) {
+                problems.push(PackProblem::file(entry, "archive entry would be extracted outside the pack (absolute, or through '..')"));
+            } else if let Some(path) = entry.strip_prefix(&prefix).filter(|path| !path.is_empty() && !path.ends_with('/')) {
+                overrides.push(path.to_string());
+            }
+        }
+        index
+    } else if input.is_dir() {
+        let index_path = find_index_json(input, index_name, max_depth)?;
+        let index = load_index(&index_path)?;
+        if let Some(overrides_path) = find_overrides(&index_path, input) {
+            list_files(&overrides_path, Path::new(""), &mut overrides)?;
+        }
+        index
+    } else {
+        let index = load_index(input)?;
+        let overrides_path = input.parent().unwrap_or(Path::new(".")).join("overrides");
+        if overrides_path.is_dir() {
+            list_files(&overrides_path, Path::new(""), &mut overrides)?;
+        }
+        index
+    };
+
+    problems.extend(index_problems(&index, &overrides, allowed_hosts));
+    Ok(PackValidation { name: index.name, problems })
+}
+
+// Everything wrong with the index itself, and with its files together with the
+// `overrides` (relative to the overrides directory)
+pub fn index_problems(index: &ModrinthIndex, overrides: &[String], allowed_hosts: &[String]) -> Vec<PackProblem> {
+    let mut problems = Vec::new();
+    if index.format_version != 1 {
+        problems.push(PackProblem::pack(format!("formatVersion is {}, but the only Modrinth pack format is version 1", index.format_version)));
+    }
+    if let Err(problem) = index.check_game() {
+        problems.push(PackProblem::pack(problem));
+    }
+    if index.minecraft_version().is_none() {
+        problems.push(PackProblem::pack("The pack doesn't declare a Minecraft version (no 'minecraft' dependency)"));
+    }
+    problems.extend(index.version_problems().into_iter().map(PackProblem::pack));
+    problems.extend(index.compatibility_problems().into_iter().map(PackProblem::pack));
+
+    let mut seen = HashSet::new();
+    for file in &index.files {
+        let path = file.path.as_str();
+        if !is_plain_relative(path) {
+            problems.push(PackProblem::file(path, "path leaves the output directory (absolute, or through '..')"));
+        }
+        if !seen.insert(path) {
+            problems.push(PackProblem::file(path, "listed more than once"));
+        }
+
+        if file.downloads.is_empty() {
+            problems.push(PackProblem::file(path, "no download URLs"));
+        }
+        for url in &file.downloads {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if !matches!(parsed.scheme(), "http" | "https") => {
+                    problems.push(PackProblem::file(path, format!("download URL '{}' is not http(s)", url)));
+                }
+                Ok(parsed) => {
+                    let host = parsed.host_str().unwrap_or_default();
+                    if !is_host_allowed(host, allowed_hosts) {
+                        problems.push(PackProblem::file(path, format!("download host '{}' is not allowed by --allow-host", host)));
+                    }
+                }
+                Err(e) => problems.push(PackProblem::file(path, format!("download URL '{}' is malformed: {}", url, e))),
+            }
+        }
+
+        problems.extend(hash_problems(&file.hashes).into_iter().map(|problem| PackProblem::file(path, problem)));
+        if HashAlgorithm::Auto.resolve(&file.hashes).is_none() {
+            problems.push(PackProblem::file(path, "no usable sha1 or sha512 hash, so the download can't be verified"));
+        }
+
+        let mut sides: Vec<(&String, &String)> = file.env.iter().collect();
+        sides.sort();
+        for (side, value) in sides {
+            if side != "client" && side != "server" {
+                problems.push(PackProblem::file(path, format!("unknown env side '{}', expected client or server", side)));
+            } else if !ENV_VALUES.contains(&value.as_str()) {
+                problems.push(PackProblem::file(path, format!("env.{} is '{}', expected {}", side, value, ENV_VALUES.join(", "))));
+            }
+        }
+    }
+
+    let paths = index.files.iter().map(|file| file.path.as_str()).chain(overrides.iter().map(String::as_str));
+    for (first, second) in case_collisions(paths) {
+        problems.push(PackProblem::file(second, format!("differs from '{}' only in case, one overwrites the other on Windows and macOS", first)));
+    }
+    problems
+}
@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use log::{debug, warn};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::checkpoint::{Checkpoint, RunRecord};
+use crate::fsutil::{join_components, long_path, InstallLock};
+use crate::hash::{digest_file, sha512_reader, HashAlgorithm, HashPolicy, SIDECAR_EXTENSION};
+use crate::index::ModFile;
+use crate::manifest::{portable_path, Manifest, ManifestEntry};
+use crate::progress::ProgressSink;
+
+/// An installed file that doesn't match the index (or manifest).
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyFailure {
+    pub path: String,
+    /// What's wrong, e.g. "missing" or "sha512 mismatch: expected ..., got ..."
+    pub problem: String,
+}
+
+// Runs `work` on a pool of `threads` threads, or on rayon's global pool (one thread
+// per core) if None. Files are hashed on whichever pool runs the work.
+fn with_threads<T: Send>(threads: Option<usize>, work: impl FnOnce() -> T + Send) -> T {
+    match threads.map(|threads| rayon::ThreadPoolBuilder::new().num_threads(threads).build()) {
+        Some(Ok(pool)) => pool.install(work),
+        Some(Err(e)) => {
+            debug!("Couldn't start {} verification threads ({}), using the default pool", threads.unwrap_or_default(), e);
+            work()
+        }
+        None => work(),
+    }
+}
+
+// Re-reads every file from `output_dir` and checks its size and `algorithm` hash
+// against the index, returning the ones that don't match (in `files` order, however
+// many `threads` hash them). Hash mismatches are only logged under `HashPolicy::Warn`
+// and not looked for under `Off`, as with the downloads.
+pub fn verify_files(
+    files: &[ModFile],
+    output_dir: &Path,
+    algorithm: HashAlgorithm,
+    policy: HashPolicy,
+    threads: Option<usize>,
+    progress: &dyn ProgressSink,
+) -> Vec<VerifyFailure> {
+    let output_dir = long_path(output_dir);
+    let done = AtomicUsize::new(0);
+    with_threads(threads, || {
+        files.par_iter()
+            .filter_map(|file| {
+                let result = verify_file(file, &output_dir, algorithm, policy);
+                progress.on_verify_entry(&file.path, done.fetch_add(1, Ordering::Relaxed) + 1, files.len());
+                result.err().map(|problem| VerifyFailure { path: file.path.clone(), problem })
+            })
+            .collect()
+    })
+}
+
+fn verify_file(file: &ModFile, output_dir: &Path, algorithm: HashAlgorithm, policy: HashPolicy) -> Result<(), String> {
+    let path = join_components(output_dir, &file.path);
+    let size = fs::metadata(&path).map_err(|_| "missing".to_string())?.len();
+    if size != file.file_size {
+        return Err(format!("size mismatch: expected {} bytes, found {}", file.file_size, size));
+    }
+    if policy == HashPolicy::Off {
+        return Ok(());
+    }
+    let digests = digest_file(&path).map_err(|e| format!("unreadable: {}", e))?;
+    match digests.mismatch(&file.hashes, algorithm) {
+        Some((algorithm, expected, actual)) if policy == HashPolicy::Warn => {
+            warn!("{}: {} mismatch (expected {}, got {}), keeping it (--hash-policy warn)", file.path, algorithm, expected, actual);
+            Ok(())
+        }
+        Some((algorithm, expected, actual)) => Err(format!("{} mismatch: expected {}, got {}", algorithm, expected, actual)),
+        None => Ok(()),
+    }
+}
+
+/// How a directory differs from a [`Manifest`], see [`verify_manifest`].
+#[derive(Debug, Default, Serialize)]
+pub struct ManifestCheck {
+    /// How many manifest entries were checked
+    pub checked: usize,
+    /// Files the manifest lists that aren't on disk
+    pub missing: Vec<String>,
+    /// Files whose size or hash differs from the manifest
+    pub corrupt: Vec<VerifyFailure>,
+    /// Files on disk the manifest doesn't list, modrinther's own files (and `.sha512`
+    /// files of listed ones) aside
+    pub extra: Vec<String>,
+}
+
+impl ManifestCheck {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.corrupt.is_empty() && self.extra.is_empty()
+    }
+}
+
+// What modrinther itself leaves in an output directory, never listed in a manifest
+const OWN_FILES: &[&str] = &[
+    Manifest::FILE_NAME,
+    "modpack_summary.txt",
+    "modpack_summary.json",
+    "modpack_summary.md",
+    "modrinth.index.json",
+    Checkpoint::FILE_NAME,
+    RunRecord::FILE_NAME,
+    InstallLock::FILE_NAME,
+];
+
+// Checks `dir` against a manifest from an earlier install, without needing the pack:
+// every listed file must exist with the listed size and SHA-512 (when recorded), and
+// every other file on disk is reported as extra. Lists are sorted by path, so they
+// come out the same however many `threads` hash the files.
+pub fn verify_manifest(manifest: &Manifest, dir: &Path, threads: Option<usize>, progress: &dyn ProgressSink) -> io::Result<ManifestCheck> {
+    let dir = long_path(dir);
+    let mut check = ManifestCheck { checked: manifest.files.len(), ..ManifestCheck::default() };
+    let done = AtomicUsize::new(0);
+    let problems: Vec<(&ManifestEntry, Option<String>)> = with_threads(threads, || {
+        manifest.files.par_iter()
+            .filter_map(|entry| {
+                let result = verify_entry(entry, &dir);
+                progress.on_verify_entry(&entry.path, done.fetch_add(1, Ordering::Relaxed) + 1, manifest.files.len());
+                result.err().map(|problem| (entry, problem))
+            })
+            .collect()
+    });
+    for (entry, problem) in problems {
+        match problem {
+            None => check.missing.push(entry.path.clone()),
+            Some(problem) => check.corrupt.push(VerifyFailure { path: entry.path.clone(), problem }),
+        }
+    }
+
+    let listed: HashSet<&str> = manifest.files.iter().map(|entry| entry.path.as_str()).collect();
+    let mut on_disk = Vec::new();
+    list_files(&dir, Path::new(""), &mut on_disk)?;
+    check.extra = on_disk.into_iter()
+        .filter(|path| !listed.contains(path.as_str()) && !OWN_FILES.contains(&path.as_str()))
+        .filter(|path| !path.strip_suffix(SIDECAR_EXTENSION).is_some_and(|hashed| listed.contains(hashed)))
+        .collect();
+
+    check.missing.sort();
+    check.corrupt.sort_by(|a, b| a.path.cmp(&b.path));
+    check.extra.sort();
+    Ok(check)
+}
+
+// Err(None) if the file is missing, Err(Some(problem)) if it doesn't match
+fn verify_entry(entry: &ManifestEntry, dir: &Path) -> Result<(), Option<String>> {
+    let path = join_components(dir, &entry.path);
+    let size = fs::metadata(&path).map_err(|_| None)?.len();
+    if size != entry.size {
+        return Err(Some(format!("size mismatch: expected {} bytes, found {}", entry.size, size)));
+    }
+    if let Some(expected) = &entry.sha512 {
+        let file = fs::File::open(&path).map_err(|e| Some(format!("unreadable: {}", e)))?;
+        let actual = sha512_reader(file).map_err(|e| Some(format!("unreadable: {}", e)))?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(Some(format!("sha512 mismatch: expected {}, got {}", expected, actual)));
+        }
+    }
+    Ok(())
+}
+
+// Every file (or symlink, never followed) under `dir`, as `/`-separated relative paths
+pub(crate) fn list_files(dir: &Path, relative: &Path, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let relative_path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), &relative_path, files)?;
+        } else {
+            files.push(portable_path(&relative_path));
+        }
+    }
+    Ok(())
+}
+
+/// How directory `b` differs from directory `a`, see [`compare_dirs`].
+#[derive(Debug, Default, Serialize)]
+pub struct DirComparison {
+    /// Files only in `b`
+    pub added: Vec<String>,
+    /// Files only in `a`
+    pub removed: Vec<String>,
+    /// Files in both whose content differs
+    pub changed: Vec<String>,
+    /// Files in both with the same content
+    pub identical: usize,
+}
+
+impl DirComparison {
+    pub fn is_identical(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// Compares two installed directories file by file (size, then SHA-512), leaving out
+// modrinther's own files, which differ between any two installs. Lists are sorted.
+pub fn compare_dirs(a: &Path, b: &Path) -> io::Result<DirComparison> {
+    let (a, b) = (long_path(a), long_path(b));
+    let list = |dir: &Path| -> io::Result<HashSet<String>> {
+        let mut files = Vec::new();
+        list_files(dir, Path::new(""), &mut files)?;
+        Ok(files.into_iter().filter(|path| !OWN_FILES.contains(&path.as_str())).collect())
+    };
+    let (in_a, in_b) = (list(&a)?, list(&b)?);
+
+    let mut comparison = DirComparison {
+        added: in_b.difference(&in_a).cloned().collect(),
+        removed: in_a.difference(&in_b).cloned().collect(),
+        ..DirComparison::default()
+    };
+    let common: Vec<&String> = in_a.intersection(&in_b).collect();
+    let differs: Vec<bool> = common.par_iter()
+        .map(|path| files_differ(&join_components(&a, path), &join_components(&b, path)))
+        .collect::<io::Result<_>>()?;
+    for (path, differs) in common.into_iter().zip(differs) {
+        if differs {
+            comparison.changed.push(path.clone());
+        } else {
+            comparison.identical += 1;
+        }
+    }
+
+    comparison.added.sort();
+    comparison.removed.sort();
+    comparison.changed.sort();
+    Ok(comparison)
+}
+
+fn files_differ(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::symlink_metadata(a)?.len() != fs::symlink_metadata(b)?.len() {
+        return Ok(true);
+    }
+    Ok(sha512_reader(fs::File::open(a)?)? != sha512_reader(fs::File::open(b)?)?)
+}